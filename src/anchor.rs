@@ -0,0 +1,129 @@
+use crate::Offset;
+use fxread::Record;
+
+/// Describes the constant sequences flanking the guide which are used to locate
+/// the guide within each read rather than relying on a fixed positional offset.
+///
+/// The guide is taken directly after the upstream flank, or if the upstream
+/// flank cannot be found, directly before the optional downstream flank.
+/// Both flanks are searched for allowing up to `max_mismatches` mismatches.
+#[derive(Debug, Clone)]
+pub struct Anchor {
+    upstream: Vec<u8>,
+    downstream: Option<Vec<u8>>,
+    max_mismatches: usize,
+}
+impl Anchor {
+    /// Creates a new anchor from an upstream flank and an optional downstream flank
+    #[must_use]
+    pub fn new(upstream: &[u8], downstream: Option<&[u8]>, max_mismatches: usize) -> Self {
+        Self {
+            upstream: upstream.to_ascii_uppercase(),
+            downstream: downstream.map(<[u8]>::to_ascii_uppercase),
+            max_mismatches,
+        }
+    }
+
+    /// Locates the start of a guide of a given size within a record.
+    ///
+    /// The flanks are first searched for in the sequence as is and then in its
+    /// reverse complement, returning a [`Offset::Forward`] or [`Offset::Reverse`]
+    /// respectively. Returns [`None`] if neither flank is found.
+    #[must_use]
+    pub fn locate(&self, record: &Record, size: usize) -> Option<Offset> {
+        if let Some(index) = self.locate_sequence(record.seq(), size) {
+            return Some(Offset::Forward(index));
+        }
+        self.locate_sequence(&record.seq_rev_comp(), size)
+            .map(Offset::Reverse)
+    }
+
+    /// Locates the start of the guide within a single orientation of a sequence
+    fn locate_sequence(&self, seq: &[u8], size: usize) -> Option<usize> {
+        if let Some(index) = Self::find(&self.upstream, seq, self.max_mismatches) {
+            return Some(index + self.upstream.len());
+        }
+        self.downstream
+            .as_ref()
+            .and_then(|flank| Self::find(flank, seq, self.max_mismatches))
+            .and_then(|index| index.checked_sub(size))
+    }
+
+    /// Finds the leftmost position of the pattern within the sequence with the
+    /// fewest mismatches (up to a maximum number of mismatches)
+    fn find(pattern: &[u8], seq: &[u8], max_mismatches: usize) -> Option<usize> {
+        if pattern.is_empty() || pattern.len() > seq.len() {
+            return None;
+        }
+        let mut best: Option<(usize, usize)> = None;
+        for (idx, window) in seq.windows(pattern.len()).enumerate() {
+            let limit = best.map_or(max_mismatches, |(d, _)| d.saturating_sub(1));
+            if let Some(distance) = Self::distance(pattern, window, limit) {
+                best = Some((distance, idx));
+                if distance == 0 {
+                    break;
+                }
+            }
+        }
+        best.map(|(_, idx)| idx)
+    }
+
+    /// Calculates the hamming distance between two equal length sequences,
+    /// returning [`None`] as soon as it exceeds the limit
+    fn distance(x: &[u8], y: &[u8], limit: usize) -> Option<usize> {
+        let mut distance = 0;
+        for (a, b) in x.iter().zip(y) {
+            if a != b {
+                distance += 1;
+                if distance > limit {
+                    return None;
+                }
+            }
+        }
+        Some(distance)
+    }
+}
+
+#[cfg(test)]
+mod testing {
+    use super::Anchor;
+    use crate::Offset;
+    use fxread::{FastaReader, FastxRead, Record};
+
+    fn record(sequence: &'static [u8]) -> Record {
+        let mut reader: Box<dyn FastxRead<Item = Record>> = Box::new(FastaReader::new(sequence));
+        reader.next().unwrap()
+    }
+
+    #[test]
+    fn test_find() {
+        assert_eq!(Anchor::find(b"GGT", b"AAGGTAA", 0), Some(2));
+        assert_eq!(Anchor::find(b"GGT", b"AAGCTAA", 0), None);
+        assert_eq!(Anchor::find(b"GGT", b"AAGCTAA", 1), Some(2));
+        // prefers the position with fewer mismatches
+        assert_eq!(Anchor::find(b"GGT", b"GCTAGGT", 1), Some(4));
+    }
+
+    #[test]
+    fn test_locate_upstream() {
+        let anchor = Anchor::new(b"GGTT", None, 1);
+        match anchor.locate(&record(b">r.0\nAGGATACTGAA\n"), 4) {
+            Some(Offset::Forward(x)) => assert_eq!(x, 5),
+            _ => panic!("Expected forward offset"),
+        }
+        match anchor.locate(&record(b">r.0\nTTCAGTAACCT\n"), 4) {
+            Some(Offset::Reverse(x)) => assert_eq!(x, 5),
+            _ => panic!("Expected reverse offset"),
+        }
+        assert!(anchor.locate(&record(b">r.0\nAAAAAAAAAAA\n"), 4).is_none());
+    }
+
+    #[test]
+    fn test_locate_downstream() {
+        let anchor = Anchor::new(b"GGTT", Some(b"CCCC"), 0);
+        match anchor.locate(&record(b">r.0\nAAAAACTGCCCCA\n"), 4) {
+            Some(Offset::Forward(x)) => assert_eq!(x, 4),
+            _ => panic!("Expected forward offset"),
+        }
+    }
+}
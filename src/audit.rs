@@ -0,0 +1,83 @@
+use crate::{MatchType, MissReason, Offset};
+use anyhow::Result;
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    sync::{Arc, Mutex},
+};
+
+/// Streams the assignment of every read to a shared tab-delimited table.
+///
+/// Each sample holds its own [`ReadLog`] (see [`ReadLog::for_sample`]) sharing the
+/// same underlying writer so that samples counted in parallel write to a single file.
+#[derive(Clone)]
+pub struct ReadLog {
+    writer: Arc<Mutex<Box<dyn Write + Send>>>,
+    sample: String,
+}
+impl ReadLog {
+    /// Creates a new log writing to the provided path
+    pub fn new(path: &str) -> Result<Self> {
+        Self::from_writer(Box::new(BufWriter::new(File::create(path)?)))
+    }
+
+    /// Creates a new log from a writer and writes its header
+    pub fn from_writer(mut writer: Box<dyn Write + Send>) -> Result<Self> {
+        writeln!(writer, "Read\tSample\tGuide\tMatch\tOffset\tOrientation")?;
+        Ok(Self {
+            writer: Arc::new(Mutex::new(writer)),
+            sample: String::new(),
+        })
+    }
+
+    /// Returns a log sharing the same writer for the provided sample
+    #[must_use]
+    pub fn for_sample(&self, sample: &str) -> Self {
+        Self {
+            writer: Arc::clone(&self.writer),
+            sample: sample.to_string(),
+        }
+    }
+
+    /// Writes the assignment of a single read. Unassigned reads are written
+    /// with the reason they were missed as their match type.
+    pub fn write(
+        &self,
+        id: &[u8],
+        assignment: Option<(&[u8], MatchType, Offset)>,
+        reason: Option<MissReason>,
+    ) -> Result<()> {
+        let mut writer = self.writer.lock().expect("poisoned read log");
+        writer.write_all(id)?;
+        match assignment {
+            Some((alias, match_type, offset)) => {
+                write!(writer, "\t{}\t", self.sample)?;
+                writer.write_all(alias)?;
+                writeln!(
+                    writer,
+                    "\t{}\t{}\t{}",
+                    match_type,
+                    offset.index(),
+                    if offset.is_forward() {
+                        "forward"
+                    } else {
+                        "reverse"
+                    }
+                )?;
+            }
+            None => writeln!(
+                writer,
+                "\t{}\tNA\t{}\tNA\tNA",
+                self.sample,
+                reason.unwrap_or(MissReason::NoMatch)
+            )?,
+        }
+        Ok(())
+    }
+
+    /// Flushes the underlying writer
+    pub fn flush(&self) -> Result<()> {
+        self.writer.lock().expect("poisoned read log").flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,262 @@
+use crate::{AssignmentOptions, Counter, Library, Offset, Permuter};
+use anyhow::{bail, Result};
+use bstr::io::BufReadExt;
+use fxread::{FastxRead, Record};
+use hashbrown::{HashMap, HashSet};
+use std::io::BufReader;
+
+type FxReader = Box<dyn FastxRead<Item = Record>>;
+type CellKey = (Vec<u8>, Vec<u8>);
+
+/// Container for the set of expected cell barcodes used to validate
+/// and correct the barcodes observed in each read.
+pub struct Whitelist {
+    barcodes: HashSet<Vec<u8>>,
+}
+impl Whitelist {
+    /// Creates a whitelist from a filepath (optionally compressed) with
+    /// a single barcode per line
+    pub fn new(path: &str) -> Result<Self> {
+        let (reader, _format) = niffler::from_path(path)?;
+        Self::from_buffer(BufReader::new(reader))
+    }
+
+    /// Creates a whitelist from a buffer with a single barcode per line
+    pub fn from_buffer<R: BufReadExt>(mut buffer: R) -> Result<Self> {
+        let mut barcodes = HashSet::new();
+        buffer.for_byte_line(|line| {
+            if !line.is_empty() {
+                barcodes.insert(line.to_vec());
+            }
+            Ok(true)
+        })?;
+        if barcodes.is_empty() {
+            bail!("Provided cell barcode whitelist is empty")
+        }
+        Ok(Self { barcodes })
+    }
+
+    /// Corrects an observed barcode against the whitelist.
+    ///
+    /// Barcodes found in the whitelist are returned as is, otherwise the barcode
+    /// is corrected to its whitelisted one-off neighbour if there is exactly one.
+    /// Barcodes with no or with ambiguous neighbours return [`None`].
+    ///
+    /// One-off neighbours are generated for each observed barcode rather than for
+    /// the whitelist, as whitelists can contain millions of barcodes.
+    #[must_use]
+    pub fn correct(&self, barcode: &[u8]) -> Option<Vec<u8>> {
+        if self.barcodes.contains(barcode) {
+            return Some(barcode.to_vec());
+        }
+        let mut neighbours = Permuter::one_off(barcode)
+            .into_iter()
+            .filter(|x| self.barcodes.contains(x));
+        match (neighbours.next(), neighbours.next()) {
+            (Some(x), None) => Some(x),
+            _ => None,
+        }
+    }
+}
+
+/// Describes the location of the cell barcode within the first mate and
+/// an optional [`Whitelist`] to correct the observed barcodes against.
+pub struct CellBarcode {
+    offset: usize,
+    size: usize,
+    whitelist: Option<Whitelist>,
+}
+impl CellBarcode {
+    /// Creates a new cell barcode description at a positional offset of a given size
+    #[must_use]
+    pub fn new(offset: usize, size: usize, whitelist: Option<Whitelist>) -> Self {
+        Self {
+            offset,
+            size,
+            whitelist,
+        }
+    }
+
+    /// Extracts the cell barcode from a record and corrects it against the
+    /// whitelist if provided. Returns [`None`] if the barcode is out of bounds,
+    /// contains an ambiguous nucleotide, or could not be corrected.
+    #[must_use]
+    pub fn extract(&self, record: &Record) -> Option<Vec<u8>> {
+        let barcode = record.seq().get(self.offset..self.offset + self.size)?;
+        match &self.whitelist {
+            Some(w) => w.correct(barcode),
+            None if barcode.contains(&b'N') => None,
+            None => Some(barcode.to_vec()),
+        }
+    }
+}
+
+/// Struct to handle the mapping of single-cell feature barcode reads.
+/// The cell barcode (and optional [`Umi`](crate::Umi)) is read from the first mate and the
+/// guide is matched from the second mate against the [`Library`].
+pub struct CellCounter {
+    results: HashMap<CellKey, usize>,
+    total_reads: usize,
+    barcoded_reads: usize,
+    matched_reads: usize,
+}
+impl CellCounter {
+    /// Initializes counting of read pairs from the barcode reader (R1) and the
+    /// guide reader (R2) within the [`Library`] following the [`AssignmentOptions`].
+    /// If a [`Umi`](crate::Umi) is provided the resulting counts are the number of
+    /// distinct UMIs for each cell and guide, otherwise they are the number of reads.
+    /// Mate files with a different number of reads are an error.
+    pub fn new(
+        reader: FxReader,
+        guide_reader: FxReader,
+        library: &Library,
+        offset: Offset,
+        barcode: &CellBarcode,
+        options: &AssignmentOptions,
+    ) -> Result<Self> {
+        let umi = options.umi();
+        let mut total_reads = 0;
+        let mut barcoded_reads = 0;
+        let mut matched_reads = 0;
+        let mut reads = HashMap::new();
+        let mut umis: HashMap<CellKey, HashMap<Vec<u8>, usize>> = HashMap::new();
+        for pair in Counter::paired_records(reader, guide_reader) {
+            let (x, y) = pair?;
+            total_reads += 1;
+            let Some(cell) = barcode.extract(&x) else {
+                continue;
+            };
+            barcoded_reads += 1;
+            let Some(alias) = Counter::assign_record(&y, library, &[offset], options) else {
+                continue;
+            };
+            matched_reads += 1;
+            let key = (cell, alias.clone());
+            match umi {
+                Some(u) => {
+                    if let Some(molecule) = u.extract(&x) {
+                        *umis.entry(key).or_default().entry(molecule).or_insert(0) += 1;
+                    }
+                }
+                None => *reads.entry(key).or_insert(0) += 1,
+            }
+        }
+        let results = match umi {
+            Some(u) => umis
+                .into_iter()
+                .map(|(key, molecules)| (key, u.deduplicate(&molecules)))
+                .collect(),
+            None => reads,
+        };
+        Ok(Self {
+            results,
+            total_reads,
+            barcoded_reads,
+            matched_reads,
+        })
+    }
+
+    /// Returns the count of a cell barcode and guide alias or zero if it was not observed
+    #[must_use]
+    pub fn get_value(&self, cell: &[u8], alias: &[u8]) -> usize {
+        *self
+            .results
+            .get(&(cell.to_vec(), alias.to_vec()))
+            .unwrap_or(&0)
+    }
+
+    /// An iterator over all observed `((cell, alias), count)` entries
+    pub fn entries(&self) -> impl Iterator<Item = (&CellKey, &usize)> {
+        self.results.iter()
+    }
+
+    /// Returns the sorted unique cell barcodes observed
+    #[must_use]
+    pub fn cells(&self) -> Vec<&Vec<u8>> {
+        let mut cells = self
+            .results
+            .keys()
+            .map(|(cell, _)| cell)
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        cells.sort_unstable();
+        cells
+    }
+
+    /// Returns the total number of read pairs processed
+    pub fn total_reads(&self) -> usize {
+        self.total_reads
+    }
+
+    /// Returns the number of read pairs with a valid cell barcode
+    pub fn barcoded_reads(&self) -> usize {
+        self.barcoded_reads
+    }
+
+    /// Returns the number of read pairs with a valid cell barcode that matched the library
+    pub fn matched_reads(&self) -> usize {
+        self.matched_reads
+    }
+
+    /// Returns the fraction of read pairs that matched the library with a valid cell barcode
+    pub fn fraction_mapped(&self) -> f64 {
+        self.matched_reads as f64 / self.total_reads as f64
+    }
+}
+
+#[cfg(test)]
+mod testing {
+    use super::{CellBarcode, CellCounter, Whitelist};
+    use crate::{AssignmentOptions, Library, Offset, Umi};
+    use fxread::{FastaReader, FastxRead, Record};
+
+    fn reader(sequence: &'static [u8]) -> Box<dyn FastxRead<Item = Record>> {
+        Box::new(FastaReader::new(sequence))
+    }
+
+    fn library() -> Library {
+        Library::from_reader(reader(b">g.0\nACTG\n>g.1\nTTTT\n")).unwrap()
+    }
+
+    fn whitelist() -> Whitelist {
+        Whitelist::from_buffer("AAAA\nCCCC\nAAAC\n".as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_whitelist_correction() {
+        let whitelist = whitelist();
+        assert_eq!(whitelist.correct(b"CCCC").unwrap(), b"CCCC");
+        assert_eq!(whitelist.correct(b"CCGC").unwrap(), b"CCCC");
+        assert!(whitelist.correct(b"AAAG").is_none());
+        assert!(whitelist.correct(b"GGGG").is_none());
+    }
+
+    #[test]
+    fn test_empty_whitelist() {
+        assert!(Whitelist::from_buffer("".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_cell_counting() {
+        let barcodes =
+            reader(b">r.0\nCCCCAA\n>r.1\nCCCCAA\n>r.2\nCCGCAT\n>r.3\nGGGGAA\n>r.4\nAAAATT\n");
+        let guides = reader(b">r.0\nACTG\n>r.1\nACTG\n>r.2\nACTG\n>r.3\nACTG\n>r.4\nTTTT\n");
+        let barcode = CellBarcode::new(0, 4, Some(whitelist()));
+        let counter = CellCounter::new(
+            barcodes,
+            guides,
+            &library(),
+            Offset::Forward(0),
+            &barcode,
+            &AssignmentOptions::new(false).with_umi(Some(Umi::new(4, 2, false))),
+        )
+        .unwrap();
+        assert_eq!(counter.get_value(b"CCCC", b"g.0"), 2);
+        assert_eq!(counter.get_value(b"AAAA", b"g.1"), 1);
+        assert_eq!(counter.cells().len(), 2);
+        assert_eq!(counter.total_reads(), 5);
+        assert_eq!(counter.barcoded_reads(), 4);
+        assert_eq!(counter.matched_reads(), 4);
+    }
+}
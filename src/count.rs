@@ -2,50 +2,235 @@ use crate::progress::{
     finish_progress_bar, finish_progress_bar_ref, initialize_multi_progress,
     initialize_progress_bar, start_progress_bar, start_progress_bar_ref,
 };
-use crate::results::write_results;
+use crate::results::{
+    write_cell_results, write_cell_summary, write_dual_results, write_offset_summary,
+    write_qc_summary, write_results, write_summary, write_unmapped_summary,
+};
 use crate::utils::vec_to_nuc;
-use crate::{Counter, GeneMap, Library, Offset, Permuter};
+use crate::{
+    AssignmentOptions, CellBarcode, CellCounter, Counter, DualLibrary, GeneMap, Library,
+    LibraryTable, Metadata, Offset, Permuter, ReadLog, ReadSinks, Report, SampleSummary,
+    UnmappedOutput,
+};
 use anyhow::{bail, Result};
 use fxread::initialize_reader;
-use indicatif::ProgressBar;
 use rayon::prelude::*;
+use std::path::Path;
+
+/// The samples of a run: the filepath of each input, the optional mate (R2) of
+/// each input at the same index, the name of each sample, and the sample metadata
+/// carried into the summary tables.
+pub struct Samples {
+    input_paths: Vec<String>,
+    mate_paths: Option<Vec<String>>,
+    names: Vec<String>,
+    metadata: Metadata,
+}
+impl Samples {
+    /// Creates the samples of a run, validating that every input has a name and
+    /// (if mates are provided) a mate
+    pub fn new(
+        input_paths: Vec<String>,
+        mate_paths: Option<Vec<String>>,
+        names: Vec<String>,
+        metadata: Metadata,
+    ) -> Result<Self> {
+        if names.len() != input_paths.len() {
+            bail!("Must provide as many sample names as there are input files")
+        }
+        if mate_paths
+            .as_ref()
+            .is_some_and(|mates| mates.len() != input_paths.len())
+        {
+            bail!("Must provide as many mate filepaths as there are input files")
+        }
+        Ok(Self {
+            input_paths,
+            mate_paths,
+            names,
+            metadata,
+        })
+    }
+
+    /// Returns the filepath of each input
+    #[must_use]
+    pub fn input_paths(&self) -> &[String] {
+        &self.input_paths
+    }
+
+    /// Returns the filepath of each mate if provided
+    #[must_use]
+    pub fn mate_paths(&self) -> Option<&[String]> {
+        self.mate_paths.as_deref()
+    }
+
+    /// Returns the name of each sample
+    #[must_use]
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Returns the sample metadata
+    #[must_use]
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+}
+
+/// Describes the outputs of a run: the count table (or the output directory of
+/// single-cell counting) and the optional gene map of its rows, whether guides
+/// without any reads and recombined guide pairs are included, the optional offset,
+/// QC, and run summaries, the HTML report, the unmapped reads and assignment log,
+/// and whether progress is hidden.
+#[derive(Default)]
+pub struct CountOutputs {
+    output_path: Option<String>,
+    genemap: Option<GeneMap>,
+    include_zero: bool,
+    include_recombined: bool,
+    offset_summary: Option<String>,
+    qc_summary: Option<String>,
+    summary: Option<String>,
+    report: Option<Report>,
+    unmapped: Option<UnmappedOutput>,
+    log: Option<ReadLog>,
+    quiet: bool,
+}
+impl CountOutputs {
+    /// Creates the outputs of a run writing the count table to `output_path` or stdout
+    #[must_use]
+    pub fn new(output_path: Option<String>) -> Self {
+        Self {
+            output_path,
+            ..Self::default()
+        }
+    }
+
+    /// Sets the optional [`GeneMap`] of the count table rows
+    #[must_use]
+    pub fn with_genemap(mut self, genemap: Option<GeneMap>) -> Self {
+        self.genemap = genemap;
+        self
+    }
+
+    /// Sets whether guides without any reads are included in the count table
+    #[must_use]
+    pub fn with_include_zero(mut self, include_zero: bool) -> Self {
+        self.include_zero = include_zero;
+        self
+    }
+
+    /// Sets whether recombined guide pairs are included in a dual-guide count table
+    #[must_use]
+    pub fn with_include_recombined(mut self, include_recombined: bool) -> Self {
+        self.include_recombined = include_recombined;
+        self
+    }
+
+    /// Sets the optional path of the summary of reads assigned at each offset
+    #[must_use]
+    pub fn with_offset_summary(mut self, offset_summary: Option<String>) -> Self {
+        self.offset_summary = offset_summary;
+        self
+    }
+
+    /// Sets the optional path of the match type and miss reason summary
+    #[must_use]
+    pub fn with_qc_summary(mut self, qc_summary: Option<String>) -> Self {
+        self.qc_summary = qc_summary;
+        self
+    }
+
+    /// Sets the optional path prefix of the JSON and TSV run summaries
+    #[must_use]
+    pub fn with_summary(mut self, summary: Option<String>) -> Self {
+        self.summary = summary;
+        self
+    }
+
+    /// Sets the optional HTML [`Report`]
+    #[must_use]
+    pub fn with_report(mut self, report: Option<Report>) -> Self {
+        self.report = report;
+        self
+    }
+
+    /// Sets the optional [`UnmappedOutput`] of unassigned reads
+    #[must_use]
+    pub fn with_unmapped(mut self, unmapped: Option<UnmappedOutput>) -> Self {
+        self.unmapped = unmapped;
+        self
+    }
+
+    /// Sets the optional [`ReadLog`] of every read assignment
+    #[must_use]
+    pub fn with_log(mut self, log: Option<ReadLog>) -> Self {
+        self.log = log;
+        self
+    }
+
+    /// Sets whether progress is hidden
+    #[must_use]
+    pub fn with_quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
+    /// Opens the unmapped read and assignment log outputs of a sample
+    fn sinks(&self, name: &str, path: &str) -> Result<ReadSinks> {
+        let unmapped = match &self.unmapped {
+            Some(u) => Some(u.sample(name, is_fastq(path)?)?),
+            None => None,
+        };
+        Ok(ReadSinks::new(
+            unmapped,
+            self.log.as_ref().map(|l| l.for_sample(name)),
+        ))
+    }
+}
 
 /// Counts the number of matching sgRNA-Reads for a provided
 /// filepath
 fn count_sample(
     path: &str,
-    name: &str,
-    offset: Offset,
+    mate: Option<(&str, &[Offset])>,
+    offsets: &[Offset],
     library: &Library,
-    permuter: &Option<Permuter>,
-    position_recursion: bool,
-    pb: Option<&ProgressBar>,
+    options: &AssignmentOptions,
+    sinks: ReadSinks,
 ) -> Result<Counter> {
     let reader = initialize_reader(path)?;
-    start_progress_bar_ref(pb, format!("Processing: {}", name));
-    let counter = Counter::new(
-        reader,
-        library,
-        permuter,
-        offset,
-        library.size(),
-        position_recursion,
-    );
-    finish_progress_bar_ref(
-        pb,
-        format!(
-            "Finished: {}; Fraction mapped: {:.3} [{} / {}]",
-            name,
-            counter.fraction_mapped(),
-            counter.matched_reads(),
-            counter.total_reads()
+    match mate {
+        Some((mate_path, mate_offsets)) => Counter::new_paired(
+            reader,
+            initialize_reader(mate_path)?,
+            library,
+            offsets,
+            mate_offsets,
+            options,
+            sinks,
         ),
-    );
-    Ok(counter)
+        None => Counter::new(reader, library, offsets, options, sinks),
+    }
+}
+
+/// Checks whether the records of a filepath have base qualities (i.e. are fastq)
+fn is_fastq(path: &str) -> Result<bool> {
+    Ok(initialize_reader(path)?
+        .next()
+        .is_some_and(|record| record.qual().is_some()))
 }
 
 /// Generates Mismatch Library if Necessary
-fn generate_permutations(library: &Library, quiet: bool) -> Permuter {
+/// (i.e. mismatches or insertions and deletions are allowed)
+fn generate_permutations(
+    library: &Library,
+    options: &AssignmentOptions,
+    quiet: bool,
+) -> Option<Permuter> {
+    if options.max_mismatches() == 0 && !options.indels() {
+        return None;
+    }
     let pb = if quiet {
         None
     } else {
@@ -53,38 +238,86 @@ fn generate_permutations(library: &Library, quiet: bool) -> Permuter {
     };
 
     start_progress_bar(&pb, "Generating Mismatch Library".to_string());
-    let permuter = Permuter::new(library.keys());
+    let mut permuter = Permuter::with_mismatches(library.keys(), options.max_mismatches());
+    if options.indels() {
+        permuter = permuter.with_indels(library.keys());
+    }
     finish_progress_bar(&pb, "Finished Mismatch Library".to_string());
-    permuter
+    Some(permuter)
 }
 
-/// Validates that the library size is not too large with respect to the input sequences
+/// Summarizes the counts of every sample over the library aliases
+fn summarize<'a, I>(
+    results: &[Counter],
+    sample_names: &[String],
+    aliases: impl Fn() -> I,
+) -> Vec<SampleSummary>
+where
+    I: Iterator<Item = &'a Vec<u8>>,
+{
+    results
+        .iter()
+        .zip(sample_names)
+        .map(|(counter, name)| SampleSummary::new(name, counter, aliases()))
+        .collect()
+}
+
+/// Validates that the shortest library sequences fit within the input sequences,
+/// warning about the length buckets which are too long to be reached
 fn validate_library_size(library: &Library, input_paths: &[String]) -> Result<bool> {
     for path in input_paths {
         let mut reader = initialize_reader(path)?;
-        let size = reader.next().unwrap().seq().len();
-        if library.size() > size {
+        let Some(record) = reader.next() else {
+            continue;
+        };
+        let size = record.seq().len();
+        if library.min_size() > size {
             return Ok(false);
         }
+        let unreachable = library
+            .sizes()
+            .iter()
+            .filter(|s| **s > size)
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        if !unreachable.is_empty() {
+            eprintln!(
+                "WARNING: Library sequences of length {} are longer than the reads of {} ({} bp) and cannot be matched",
+                unreachable.join(", "),
+                path,
+                size
+            );
+        }
     }
     Ok(true)
 }
 
-/// Counts the number of matching sgRNA-reads for all provided filepaths
+/// Counts the number of matching sgRNA-reads for all provided samples
+///
+/// If the samples have mates each input is treated as the first mate (R1)
+/// of a paired-end sample and is matched with the mate (R2) at the same index,
+/// trimmed with the offsets at the same index in `mate_offset`.
+///
+/// Each sample may be given several offsets which are tried in order for every
+/// read. The count table and the optional summaries, report, unmapped reads, and
+/// assignment log are written as described by the [`CountOutputs`], and the sample
+/// metadata is carried into each of the per-sample summary tables.
 pub fn count(
-    library_path: &str,
-    input_paths: Vec<String>,
-    sample_names: &[String],
-    output_path: Option<String>,
-    offset: Vec<Offset>,
-    exact: bool,
-    genemap: &Option<GeneMap>,
-    position_recursion: bool,
-    include_zero: bool,
-    quiet: bool,
+    table: &LibraryTable,
+    samples: &Samples,
+    offset: Vec<Vec<Offset>>,
+    mate_offset: Option<Vec<Vec<Offset>>>,
+    options: AssignmentOptions,
+    outputs: &CountOutputs,
 ) -> Result<()> {
     // generate library
-    let library = Library::from_reader(initialize_reader(library_path)?)?;
+    let library = table.library()?;
+
+    // adds the genes of the merged aliases of shared sequences
+    let genemap = &outputs
+        .genemap
+        .as_ref()
+        .map(|g| g.with_merged_aliases(library.values()));
 
     // validate all library sgRNA aliases exist if genemap provided
     if let Some(g) = genemap {
@@ -95,53 +328,327 @@ pub fn count(
     }
 
     // validate library size
-    if !validate_library_size(&library, &input_paths)? {
-        bail!("Sequences in reference library are larger than the sequences in input.\n\nConsider reducing the length of your reference sequences (i.e. extracting the variable region of the sgRNA or reducing the length of the adapters.)")
+    if !validate_library_size(&library, samples.input_paths())? {
+        bail!("Sequences in reference library are larger than the sequences in input.\n\nConsider reducing the length of your reference sequences (i.e. extracting the variable region of the sgRNA with --trim-library or reducing the length of the adapters.)")
+    }
+
+    // validate that every mate has offsets if running paired-end
+    if let Some(mates) = samples.mate_paths() {
+        match &mate_offset {
+            Some(o) if o.len() == mates.len() => {}
+            _ => bail!("Must provide as many mate offsets as there are mate files"),
+        }
     }
 
     // generate permuter if necessary
-    let permuter = if exact {
-        None
-    } else {
-        Some(generate_permutations(&library, quiet))
-    };
+    let permuter = generate_permutations(&library, &options, outputs.quiet);
+    let options = options.with_permuter(permuter);
 
     // generate multiprogress and individual progress bars
-    let (_mp, progress_bars) = if quiet {
+    let (_mp, progress_bars) = if outputs.quiet {
         (None, None)
     } else {
-        initialize_multi_progress(sample_names)
+        initialize_multi_progress(samples.names())
     };
 
     // main counting function
-    let results: Result<Vec<Counter>> = input_paths
-        .into_par_iter()
-        .zip(sample_names)
+    let results: Result<Vec<Counter>> = samples
+        .input_paths()
+        .par_iter()
+        .zip(samples.names())
         .zip(offset)
         .enumerate()
         .map(|(idx, ((path, name), offset))| {
-            count_sample(
-                &path,
-                name,
-                offset,
-                &library,
-                &permuter,
-                position_recursion,
-                match &progress_bars {
-                    Some(pbs) => Some(&pbs[idx]),
-                    None => None,
-                },
-            )
+            let pb = progress_bars.as_ref().map(|pbs| &pbs[idx]);
+            let mate = match (samples.mate_paths(), &mate_offset) {
+                (Some(paths), Some(offsets)) => {
+                    Some((paths[idx].as_str(), offsets[idx].as_slice()))
+                }
+                _ => None,
+            };
+            let sinks = outputs.sinks(name, path)?;
+            start_progress_bar_ref(pb, format!("Processing: {}", name));
+            let counter = count_sample(path, mate, &offset, &library, &options, sinks)?;
+            finish_progress_bar_ref(
+                pb,
+                format!(
+                    "Finished: {}; Fraction mapped: {:.3} [{} / {}]; Indels: {}; Low quality: {}; Forward: {}; Reverse: {}",
+                    name,
+                    counter.fraction_mapped(),
+                    counter.matched_reads(),
+                    counter.total_reads(),
+                    counter.indel_reads(),
+                    counter.low_quality_reads(),
+                    counter.forward_reads(),
+                    counter.reverse_reads(),
+                ),
+            );
+            Ok(counter)
         })
         .collect();
 
+    let results = results?;
+    if let Some(l) = &outputs.log {
+        l.flush()?;
+    }
     write_results(
-        output_path,
-        &results?,
+        outputs.output_path.clone(),
+        &results,
         &library,
-        sample_names,
+        samples.names(),
         genemap,
-        include_zero,
+        outputs.include_zero,
+        options.umi().is_some(),
+    )?;
+
+    if let Some(path) = &outputs.offset_summary {
+        write_offset_summary(path, &results, samples.names(), samples.metadata())?;
+    }
+
+    if let Some(path) = &outputs.qc_summary {
+        write_qc_summary(path, &results, samples.names(), samples.metadata())?;
+    }
+
+    let summaries = summarize(&results, samples.names(), || library.values());
+    if let Some(prefix) = &outputs.summary {
+        write_summary(Path::new(prefix), &summaries, samples.metadata())?;
+    }
+
+    if let Some(r) = &outputs.report {
+        let aliases = library.values().collect::<Vec<_>>();
+        r.write(samples.input_paths(), &results, &summaries, &aliases)?;
+    }
+
+    if let Some(u) = &outputs.unmapped {
+        write_unmapped_summary(&u.summary_path(), &results, samples.names(), u.top())?;
+    }
+
+    Ok(())
+}
+
+/// Counts the number of matching dual-guide constructs for all provided samples
+///
+/// The first guide is matched against the library of `table` and the second
+/// guide against the library of `dual_table`. If the samples have mates the
+/// second guide is extracted from the mate at the same index, otherwise it is
+/// extracted from the same read. The count table and the optional QC and run
+/// summaries are written as described by the [`CountOutputs`] alongside the
+/// sample metadata.
+pub fn count_dual(
+    table: &LibraryTable,
+    dual_table: &LibraryTable,
+    samples: &Samples,
+    offset: Vec<Offset>,
+    dual_offset: Vec<Offset>,
+    options: AssignmentOptions,
+    outputs: &CountOutputs,
+) -> Result<()> {
+    // generate library
+    let library = DualLibrary::from_readers(table.reader()?, dual_table.reader()?)?;
+
+    // validate all construct aliases exist if genemap provided
+    if let Some(g) = &outputs.genemap {
+        if let Some(missing) = g.missing_constructs(&library) {
+            let missing_str = vec_to_nuc(&missing)?;
+            bail!("Missing construct aliases in gene map: {:?}", missing_str);
+        }
+    }
+
+    // validate that every sample has a second guide offset
+    if dual_offset.len() != samples.input_paths().len() {
+        bail!("Must provide as many second guide offsets as there are input files")
+    }
+
+    // validate library sizes at both positions
+    let dual_paths = samples.mate_paths().unwrap_or(samples.input_paths());
+    if !validate_library_size(library.first(), samples.input_paths())?
+        || !validate_library_size(library.second(), dual_paths)?
+    {
+        bail!("Sequences in reference library are larger than the sequences in input.\n\nConsider reducing the length of your reference sequences (i.e. extracting the variable region of the sgRNA with --trim-library or reducing the length of the adapters.)")
+    }
+
+    // generate permuters for each position if necessary (anchors are not located
+    // in dual-guide reads)
+    let permuter = generate_permutations(library.first(), &options, outputs.quiet);
+    let dual_permuter = generate_permutations(library.second(), &options, outputs.quiet);
+    let options = options.with_anchor(None).with_permuter(permuter);
+
+    // generate multiprogress and individual progress bars
+    let (_mp, progress_bars) = if outputs.quiet {
+        (None, None)
+    } else {
+        initialize_multi_progress(samples.names())
+    };
+
+    // main counting function
+    let results: Result<Vec<Counter>> = samples
+        .input_paths()
+        .par_iter()
+        .zip(samples.names())
+        .zip(offset)
+        .zip(dual_offset)
+        .enumerate()
+        .map(|(idx, (((path, name), offset), dual_offset))| {
+            let pb = progress_bars.as_ref().map(|pbs| &pbs[idx]);
+            let mate_reader = match samples.mate_paths() {
+                Some(paths) => Some(initialize_reader(&paths[idx])?),
+                None => None,
+            };
+            start_progress_bar_ref(pb, format!("Processing: {}", name));
+            let counter = Counter::new_dual(
+                initialize_reader(path)?,
+                mate_reader,
+                &library,
+                offset,
+                dual_offset,
+                &options,
+                &dual_permuter,
+            )?;
+            finish_progress_bar_ref(
+                pb,
+                format!(
+                    "Finished: {}; Fraction mapped: {:.3} [{} / {}]; Indels: {}; Low quality: {}; Recombined: {}",
+                    name,
+                    counter.fraction_mapped(),
+                    counter.matched_reads(),
+                    counter.total_reads(),
+                    counter.indel_reads(),
+                    counter.low_quality_reads(),
+                    counter.recombined_reads(),
+                ),
+            );
+            Ok(counter)
+        })
+        .collect();
+
+    let results = results?;
+    write_dual_results(
+        outputs.output_path.clone(),
+        &results,
+        &library,
+        samples.names(),
+        &outputs.genemap,
+        outputs.include_zero,
+        outputs.include_recombined,
+    )?;
+
+    if let Some(path) = &outputs.qc_summary {
+        write_qc_summary(path, &results, samples.names(), samples.metadata())?;
+    }
+
+    if let Some(prefix) = &outputs.summary {
+        let summaries = summarize(&results, samples.names(), || library.aliases());
+        write_summary(Path::new(prefix), &summaries, samples.metadata())?;
+    }
+
+    Ok(())
+}
+
+/// Counts the guides observed in each cell for all provided samples
+///
+/// The cell barcode and optional UMI are extracted from the inputs and the guide
+/// is extracted from the mates of the samples with the offsets in `mate_offset`.
+/// A sparse cell-by-guide matrix is written for each sample into a subdirectory
+/// of the output directory of the [`CountOutputs`] named after the sample,
+/// alongside a summary of every sample (and its metadata) in the output directory.
+pub fn count_cells(
+    table: &LibraryTable,
+    samples: &Samples,
+    mate_offset: Vec<Offset>,
+    barcode: &CellBarcode,
+    options: AssignmentOptions,
+    outputs: &CountOutputs,
+) -> Result<()> {
+    let (Some(mate_paths), Some(output_directory)) = (samples.mate_paths(), &outputs.output_path)
+    else {
+        bail!("Single-cell counting requires mates (R2) holding the guides and an output directory")
+    };
+
+    // generate library
+    let library = table.library()?;
+
+    // adds the genes of the merged aliases of shared sequences
+    let genemap = &outputs
+        .genemap
+        .as_ref()
+        .map(|g| g.with_merged_aliases(library.values()));
+
+    // validate all library sgRNA aliases exist if genemap provided
+    if let Some(g) = genemap {
+        if let Some(missing) = g.missing_aliases(&library) {
+            let missing_str = vec_to_nuc(&missing)?;
+            bail!("Missing sgRNA aliases in gene map: {:?}", missing_str);
+        }
+    }
+
+    // validate that every guide mate has an offset
+    if mate_offset.len() != mate_paths.len() {
+        bail!("Must provide as many mate offsets as there are mate files")
+    }
+
+    // validate library size against the guide mates
+    if !validate_library_size(&library, mate_paths)? {
+        bail!("Sequences in reference library are larger than the sequences in input.\n\nConsider reducing the length of your reference sequences (i.e. extracting the variable region of the sgRNA with --trim-library or reducing the length of the adapters.)")
+    }
+
+    // generate permuter if necessary
+    let permuter = generate_permutations(&library, &options, outputs.quiet);
+    let options = options.with_permuter(permuter);
+
+    // generate multiprogress and individual progress bars
+    let (_mp, progress_bars) = if outputs.quiet {
+        (None, None)
+    } else {
+        initialize_multi_progress(samples.names())
+    };
+
+    // main counting function
+    let results = samples
+        .input_paths()
+        .par_iter()
+        .zip(mate_paths)
+        .zip(samples.names())
+        .zip(mate_offset)
+        .enumerate()
+        .map(|(idx, (((path, mate_path), name), offset))| {
+            let pb = progress_bars.as_ref().map(|pbs| &pbs[idx]);
+            start_progress_bar_ref(pb, format!("Processing: {}", name));
+            let counter = CellCounter::new(
+                initialize_reader(path)?,
+                initialize_reader(mate_path)?,
+                &library,
+                offset,
+                barcode,
+                &options,
+            )?;
+            let directory = Path::new(output_directory).join(name);
+            write_cell_results(
+                directory.to_str().expect("invalid path"),
+                &counter,
+                &library,
+                genemap,
+            )?;
+            finish_progress_bar_ref(
+                pb,
+                format!(
+                    "Finished: {}; Cells: {}; Fraction mapped: {:.3} [{} / {}]",
+                    name,
+                    counter.cells().len(),
+                    counter.fraction_mapped(),
+                    counter.matched_reads(),
+                    counter.total_reads()
+                ),
+            );
+            Ok(counter)
+        })
+        .collect::<Result<Vec<CellCounter>>>()?;
+
+    let summary = Path::new(output_directory).join("summary.tsv");
+    write_cell_summary(
+        summary.to_str().expect("invalid path"),
+        &results,
+        samples.names(),
+        samples.metadata(),
     )?;
 
     Ok(())
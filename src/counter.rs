@@ -1,9 +1,14 @@
-use super::{Library, Permuter};
-use crate::Offset;
+use super::{DualLibrary, Library, Permuter};
+use crate::{Anchor, Offset, QualityFilter, ReadLog, Umi, Unmapped};
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
 use fxread::{FastxRead, Record};
 use hashbrown::HashMap;
+use std::fmt;
 
-#[derive(Debug, PartialEq)]
+type FxReader = Box<dyn FastxRead<Item = Record>>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 enum Position {
     Plus,
     Minus,
@@ -11,68 +16,656 @@ enum Position {
     Null,
 }
 
+/// Describes how a read was matched to its library sequence
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MatchType {
+    /// The window matched the library sequence exactly
+    Exact,
+    /// The window matched the library sequence through the [`Permuter`]
+    Mismatch,
+    /// The window matched after shifting the offset by +1
+    PositionPlus,
+    /// The window matched after shifting the offset by -1
+    PositionMinus,
+    /// The window matched with a single insertion or deletion
+    Indel,
+}
+impl fmt::Display for MatchType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Exact => "exact",
+            Self::Mismatch => "mismatch",
+            Self::PositionPlus => "position_plus",
+            Self::PositionMinus => "position_minus",
+            Self::Indel => "indel",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Describes why a read could not be assigned to a library sequence.
+/// Reasons are ordered by how informative they are when a read misses at
+/// several offsets or positions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum MissReason {
+    /// The window extends beyond the end of the read
+    OutOfBounds,
+    /// The window did not match any library sequence
+    NoMatch,
+    /// The window is equally near several library sequences (i.e. in the null set)
+    Ambiguous,
+    /// The window matched but failed the [`QualityFilter`]
+    LowQuality,
+    /// Both guides of a dual-guide read matched but their pair is not in the design
+    Recombined,
+}
+impl fmt::Display for MissReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::OutOfBounds => "out_of_bounds",
+            Self::NoMatch => "no_match",
+            Self::Ambiguous => "ambiguous",
+            Self::LowQuality => "low_quality",
+            Self::Recombined => "recombined",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The outcome of assigning a single record against the [`Library`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Assignment<'a> {
+    /// The record matched the alias at the offset
+    Matched(&'a Vec<u8>, MatchType, Offset),
+    /// The record could not be assigned
+    Missed(MissReason),
+}
+impl<'a> Assignment<'a> {
+    /// Returns the matched alias, how it was matched, and its offset
+    fn matched(self) -> Option<(&'a Vec<u8>, MatchType, Offset)> {
+        match self {
+            Self::Matched(alias, match_type, offset) => Some((alias, match_type, offset)),
+            _ => None,
+        }
+    }
+
+    /// Returns the reason the record was not assigned
+    fn missed(self) -> Option<MissReason> {
+        match self {
+            Self::Missed(reason) => Some(reason),
+            Self::Matched(..) => None,
+        }
+    }
+
+    /// Keeps the more informative reason if the record was not assigned
+    fn or_miss(self, reason: MissReason) -> Self {
+        match self {
+            Self::Missed(r) => Self::Missed(r.max(reason)),
+            Self::Matched(..) => self,
+        }
+    }
+}
+
+/// Accumulates how each read was matched or why it was missed
+#[derive(Default)]
+struct Outcomes {
+    matches: HashMap<MatchType, usize>,
+    misses: HashMap<MissReason, usize>,
+}
+impl Outcomes {
+    /// Adds the outcome of a single read
+    fn insert(&mut self, assignment: Assignment) {
+        match assignment {
+            Assignment::Matched(_, match_type, _) => {
+                *self.matches.entry(match_type).or_insert(0) += 1;
+            }
+            Assignment::Missed(reason) => *self.misses.entry(reason).or_insert(0) += 1,
+        }
+    }
+}
+
+/// Describes how a read pair is assigned when the protospacer
+/// may be found in either mate of a paired-end run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum MateMode {
+    /// Assigns the pair to whichever mate matches the library.
+    /// Pairs whose mates match different sequences are left unassigned.
+    Either,
+    /// Assigns the pair only if both mates match the same library sequence
+    Agree,
+}
+
+/// Options describing how reads are assigned against the [`Library`]: the number of
+/// tolerated mismatches (and whether indels are tolerated) and the optional
+/// [`Permuter`] generated from them, the optional [`Anchor`] used to
+/// locate the guide, the optional [`QualityFilter`] of the guide window, the optional
+/// [`Umi`] used to deduplicate reads, whether the window position is recursively
+/// shifted, and the [`MateMode`] of read pairs.
+pub struct AssignmentOptions {
+    max_mismatches: usize,
+    indels: bool,
+    permuter: Option<Permuter>,
+    anchor: Option<Anchor>,
+    quality: Option<QualityFilter>,
+    umi: Option<Umi>,
+    position: Position,
+    mate_mode: MateMode,
+}
+impl AssignmentOptions {
+    /// Creates options for exact matching with or without position recursion
+    #[must_use]
+    pub fn new(position_recursion: bool) -> Self {
+        Self {
+            max_mismatches: 0,
+            indels: false,
+            permuter: None,
+            anchor: None,
+            quality: None,
+            umi: None,
+            position: if position_recursion {
+                Position::Centered
+            } else {
+                Position::Null
+            },
+            mate_mode: MateMode::Either,
+        }
+    }
+
+    /// Sets the number of tolerated mismatches and whether indels are tolerated
+    #[must_use]
+    pub fn with_mismatches(mut self, max_mismatches: usize, indels: bool) -> Self {
+        self.max_mismatches = max_mismatches;
+        self.indels = indels;
+        self
+    }
+
+    /// Sets the optional [`Permuter`]
+    #[must_use]
+    pub fn with_permuter(mut self, permuter: Option<Permuter>) -> Self {
+        self.permuter = permuter;
+        self
+    }
+
+    /// Sets the optional [`Anchor`]
+    #[must_use]
+    pub fn with_anchor(mut self, anchor: Option<Anchor>) -> Self {
+        self.anchor = anchor;
+        self
+    }
+
+    /// Sets the optional [`QualityFilter`]
+    #[must_use]
+    pub fn with_quality(mut self, quality: Option<QualityFilter>) -> Self {
+        self.quality = quality;
+        self
+    }
+
+    /// Sets the optional [`Umi`]
+    #[must_use]
+    pub fn with_umi(mut self, umi: Option<Umi>) -> Self {
+        self.umi = umi;
+        self
+    }
+
+    /// Sets the [`MateMode`]
+    #[must_use]
+    pub fn with_mate_mode(mut self, mate_mode: MateMode) -> Self {
+        self.mate_mode = mate_mode;
+        self
+    }
+
+    /// Returns the optional [`Umi`]
+    #[must_use]
+    pub fn umi(&self) -> &Option<Umi> {
+        &self.umi
+    }
+
+    /// Returns the number of tolerated mismatches
+    #[must_use]
+    pub fn max_mismatches(&self) -> usize {
+        self.max_mismatches
+    }
+
+    /// Returns whether insertions and deletions are tolerated
+    #[must_use]
+    pub fn indels(&self) -> bool {
+        self.indels
+    }
+}
+
+/// The optional outputs of a sample which unassigned reads ([`Unmapped`]) and the
+/// assignment of every read ([`ReadLog`]) are streamed to
+#[derive(Default)]
+pub struct ReadSinks {
+    unmapped: Option<Unmapped>,
+    log: Option<ReadLog>,
+}
+impl ReadSinks {
+    /// Creates the outputs of a sample
+    #[must_use]
+    pub fn new(unmapped: Option<Unmapped>, log: Option<ReadLog>) -> Self {
+        Self { unmapped, log }
+    }
+}
+
+/// Accumulates the aliases of assigned reads alongside the read counts
+/// of each UMI observed for every alias and the offsets the reads were assigned at.
+/// Assigned reads whose UMI could not be extracted are tallied separately.
+#[derive(Default)]
+struct Tally {
+    counts: HashMap<Vec<u8>, usize>,
+    umis: HashMap<Vec<u8>, HashMap<Vec<u8>, usize>>,
+    offsets: HashMap<Offset, usize>,
+    invalid_umis: usize,
+}
+impl Tally {
+    /// Adds an assigned read and the offset it was assigned at to the tally alongside
+    /// the UMI of the record if a [`Umi`] is provided
+    fn insert(mut self, alias: &[u8], offset: Offset, record: &Record, umi: &Option<Umi>) -> Self {
+        *self.counts.entry(alias.to_vec()).or_insert(0) += 1;
+        *self.offsets.entry(offset).or_insert(0) += 1;
+        match umi.as_ref().map(|u| u.extract(record)) {
+            Some(Some(molecule)) => {
+                *self
+                    .umis
+                    .entry(alias.to_vec())
+                    .or_default()
+                    .entry(molecule)
+                    .or_insert(0) += 1;
+            }
+            Some(None) => self.invalid_umis += 1,
+            None => {}
+        }
+        self
+    }
+
+    /// Calculates the deduplicated UMI counts of each alias if a [`Umi`] is provided
+    fn umi_counts(&self, umi: &Option<Umi>) -> Option<HashMap<Vec<u8>, usize>> {
+        umi.as_ref().map(|u| {
+            self.umis
+                .iter()
+                .map(|(alias, umis)| (alias.clone(), u.deduplicate(umis)))
+                .collect()
+        })
+    }
+}
+
 /// Struct to handle the mapping between the trimmed records generated by [`FastxRead`]
 /// and the sequences found in the [`Library`]. It also has an optional argument for
 /// unambiguous sequence permutations contained within [`Permuter`].
 pub struct Counter {
     results: HashMap<Vec<u8>, usize>,
+    umi_results: Option<HashMap<Vec<u8>, usize>>,
+    recombined: HashMap<Vec<u8>, usize>,
+    offsets: HashMap<Offset, usize>,
     total_reads: usize,
     matched_reads: usize,
+    recombined_reads: usize,
+    indel_reads: usize,
+    invalid_umi_reads: usize,
+    outcomes: Outcomes,
+    unmapped: Option<HashMap<Vec<u8>, usize>>,
 }
 impl Counter {
     /// Initializes a counter from a hashmap directly (for testing)
     pub fn from_hashmap(map: HashMap<Vec<u8>, usize>) -> Self {
         Self {
             results: map,
+            umi_results: None,
+            recombined: HashMap::new(),
+            offsets: HashMap::new(),
             total_reads: 0,
             matched_reads: 0,
+            recombined_reads: 0,
+            indel_reads: 0,
+            invalid_umi_reads: 0,
+            outcomes: Outcomes::default(),
+            unmapped: None,
         }
     }
 
     /// Initializes counting of reads from the [`FastxRead`] object within
-    /// the [`Library`]. Optional argument for the unambiguous sequence permutations
-    /// contained within [`Permuter`].
-    #[must_use]
+    /// the [`Library`] following the [`AssignmentOptions`]. Each of the provided offsets
+    /// is tried in order (with the length buckets of the [`Library`] from longest to
+    /// shortest) until the read is assigned, and the [`Umi`] of assigned reads is
+    /// extracted if provided. Reads failing the optional [`QualityFilter`] are
+    /// tallied separately and unassigned reads are written to the optional [`Unmapped`]
+    /// of the [`ReadSinks`]. The assignment of every read is streamed to the optional
+    /// [`ReadLog`], and the first error in writing to either output is returned.
     pub fn new(
-        reader: Box<dyn FastxRead<Item = Record>>,
+        reader: FxReader,
         library: &Library,
-        permuter: &Option<Permuter>,
+        offsets: &[Offset],
+        options: &AssignmentOptions,
+        mut sinks: ReadSinks,
+    ) -> Result<Self> {
+        let mut total_reads = 0;
+        let mut matched_reads = 0;
+        let mut indel_reads = 0;
+        let mut outcomes = Outcomes::default();
+        let mut tally = Tally::default();
+        for x in reader {
+            total_reads += 1;
+            let assignment =
+                Self::assign_tolerant(&x, library, &options.permuter, offsets, options);
+            Self::write_log(&sinks.log, &x, assignment)?;
+            outcomes.insert(assignment);
+            match assignment {
+                Assignment::Matched(alias, match_type, offset) => {
+                    indel_reads += usize::from(match_type == MatchType::Indel);
+                    matched_reads += 1;
+                    tally = tally.insert(alias, offset, &x, &options.umi);
+                }
+                Assignment::Missed(_) => {
+                    Self::write_unmapped(&mut sinks.unmapped, &x, library, offsets, options)?;
+                }
+            }
+        }
+        Ok(Self {
+            umi_results: tally.umi_counts(&options.umi),
+            results: tally.counts,
+            recombined: HashMap::new(),
+            offsets: tally.offsets,
+            total_reads,
+            matched_reads,
+            recombined_reads: 0,
+            indel_reads,
+            invalid_umi_reads: tally.invalid_umis,
+            outcomes,
+            unmapped: Self::finish_unmapped(sinks.unmapped)?,
+        })
+    }
+
+    /// Initializes counting of read pairs from two [`FastxRead`] objects within
+    /// the [`Library`]. Each mate is trimmed with its own offsets and the pair
+    /// is assigned following the [`MateMode`] of the [`AssignmentOptions`]. The optional
+    /// [`Umi`] is extracted from the first mate and the optional [`Anchor`] is located in
+    /// each mate. Pairs which are unassigned because a mate failed the optional
+    /// [`QualityFilter`] are tallied separately and the first mate of unassigned pairs is
+    /// written to the optional [`Unmapped`] of the [`ReadSinks`]. The assignment of every
+    /// pair is streamed to the optional [`ReadLog`] under the identifier of the first mate.
+    /// Mate files with a different number of reads are an error, as is the first error
+    /// in writing to either output.
+    pub fn new_paired(
+        reader: FxReader,
+        mate_reader: FxReader,
+        library: &Library,
+        offsets: &[Offset],
+        mate_offsets: &[Offset],
+        options: &AssignmentOptions,
+        mut sinks: ReadSinks,
+    ) -> Result<Self> {
+        let permuter = &options.permuter;
+        let mut total_reads = 0;
+        let mut matched_reads = 0;
+        let mut indel_reads = 0;
+        let mut outcomes = Outcomes::default();
+        let mut tally = Tally::default();
+        for pair in Self::paired_records(reader, mate_reader) {
+            let (x, y) = pair?;
+            total_reads += 1;
+            let primary_assignment = Self::assign_tolerant(&x, library, permuter, offsets, options);
+            let mate_assignment =
+                Self::assign_tolerant(&y, library, permuter, mate_offsets, options);
+            let (primary, mate) = (primary_assignment.matched(), mate_assignment.matched());
+            let (Some(alias), Some((_, match_type, offset))) = (
+                Self::resolve_mates(primary.map(|x| x.0), mate.map(|x| x.0), options.mate_mode),
+                primary.or(mate),
+            ) else {
+                // mates which both matched different sequences are ambiguous
+                let reason = primary_assignment
+                    .missed()
+                    .into_iter()
+                    .chain(mate_assignment.missed())
+                    .max()
+                    .unwrap_or(MissReason::Ambiguous);
+                outcomes.insert(Assignment::Missed(reason));
+                Self::write_unmapped(&mut sinks.unmapped, &x, library, offsets, options)?;
+                Self::write_log(&sinks.log, &x, Assignment::Missed(reason))?;
+                continue;
+            };
+
+            // pairs are only indel recovered if no mate matched without an indel
+            if primary
+                .into_iter()
+                .chain(mate)
+                .all(|(_, match_type, _)| match_type == MatchType::Indel)
+            {
+                indel_reads += 1;
+            }
+
+            // the match of the first mate is reported if it was assigned
+            outcomes.insert(Assignment::Matched(alias, match_type, offset));
+            Self::write_log(
+                &sinks.log,
+                &x,
+                Assignment::Matched(alias, match_type, offset),
+            )?;
+            matched_reads += 1;
+            tally = tally.insert(alias, offset, &x, &options.umi);
+        }
+        Ok(Self {
+            umi_results: tally.umi_counts(&options.umi),
+            results: tally.counts,
+            recombined: HashMap::new(),
+            offsets: tally.offsets,
+            total_reads,
+            matched_reads,
+            recombined_reads: 0,
+            indel_reads,
+            invalid_umi_reads: tally.invalid_umis,
+            outcomes,
+            unmapped: Self::finish_unmapped(sinks.unmapped)?,
+        })
+    }
+
+    /// Initializes counting of dual-guide constructs within the [`DualLibrary`].
+    /// The first guide is trimmed from each read with `offset` and the second guide
+    /// is trimmed with `dual_offset` from either the same read or, if provided,
+    /// from its mate. A read is only assigned if both positions match, and pairs
+    /// of matched guides which are not found in the design are tallied separately
+    /// as recombined pairs. The second guide is matched with the `dual_permuter` in
+    /// place of the [`Permuter`] of the [`AssignmentOptions`], whose [`Anchor`] is not
+    /// used. The optional [`Umi`] is extracted from the first read and reads with a
+    /// guide failing the optional [`QualityFilter`] are tallied separately.
+    /// Mate files with a different number of reads are an error.
+    pub fn new_dual(
+        reader: FxReader,
+        mate_reader: Option<FxReader>,
+        library: &DualLibrary,
         offset: Offset,
-        size: usize,
-        position_recursion: bool,
-    ) -> Self {
-        let position = if position_recursion {
-            Position::Centered
-        } else {
-            Position::Null
+        dual_offset: Offset,
+        options: &AssignmentOptions,
+        dual_permuter: &Option<Permuter>,
+    ) -> Result<Self> {
+        let records: Box<dyn Iterator<Item = Result<(Record, Option<Record>)>>> = match mate_reader
+        {
+            Some(m) => Box::new(
+                Self::paired_records(reader, m).map(|pair| pair.map(|(x, y)| (x, Some(y)))),
+            ),
+            None => Box::new(reader.into_iter().map(|x| Ok((x, None)))),
         };
+
+        let mut tally = Tally::default();
+        let mut recombined = HashMap::new();
         let mut total_reads = 0;
         let mut matched_reads = 0;
-        let results = Self::count(
-            reader,
-            library,
-            permuter,
-            offset,
-            size,
-            &position,
-            &mut total_reads,
-            &mut matched_reads,
-        );
-        Self {
-            results,
+        let mut recombined_reads = 0;
+        let mut indel_reads = 0;
+        let mut outcomes = Outcomes::default();
+        for pair in records {
+            let (x, y) = pair?;
+            total_reads += 1;
+            let first =
+                Self::assign_tolerant(&x, library.first(), &options.permuter, &[offset], options);
+            let second = Self::assign_tolerant(
+                y.as_ref().unwrap_or(&x),
+                library.second(),
+                dual_permuter,
+                &[dual_offset],
+                options,
+            );
+            if let Some(reason) = first.missed().into_iter().chain(second.missed()).max() {
+                outcomes.insert(Assignment::Missed(reason));
+            }
+            if let (Some((first, first_match, _)), Some((second, second_match, _))) =
+                (first.matched(), second.matched())
+            {
+                if first_match == MatchType::Indel || second_match == MatchType::Indel {
+                    indel_reads += 1;
+                }
+                match library.construct(first, second) {
+                    Some(alias) => {
+                        matched_reads += 1;
+                        // report the inexact window if either was not an exact match
+                        let match_type = if first_match == MatchType::Exact {
+                            second_match
+                        } else {
+                            first_match
+                        };
+                        outcomes.insert(Assignment::Matched(alias, match_type, offset));
+                        tally = tally.insert(alias, offset, &x, &options.umi);
+                    }
+                    None => {
+                        recombined_reads += 1;
+                        outcomes.insert(Assignment::Missed(MissReason::Recombined));
+                        *recombined
+                            .entry(DualLibrary::recombined_alias(first, second))
+                            .or_insert(0) += 1;
+                    }
+                }
+            }
+        }
+        Ok(Self {
+            umi_results: tally.umi_counts(&options.umi),
+            results: tally.counts,
+            recombined,
+            offsets: tally.offsets,
             total_reads,
             matched_reads,
-        }
+            recombined_reads,
+            indel_reads,
+            invalid_umi_reads: tally.invalid_umis,
+            outcomes,
+            unmapped: None,
+        })
+    }
+
+    /// Pairs the records of a reader with those of its mate reader and fails
+    /// if either runs out of records before the other.
+    pub(crate) fn paired_records(
+        mut reader: FxReader,
+        mut mate_reader: FxReader,
+    ) -> impl Iterator<Item = Result<(Record, Record)>> {
+        let mut exhausted = false;
+        std::iter::from_fn(move || {
+            if exhausted {
+                return None;
+            }
+            match (reader.next(), mate_reader.next()) {
+                (Some(x), Some(y)) => Some(Ok((x, y))),
+                (None, None) => None,
+                (x, _) => {
+                    exhausted = true;
+                    let (shorter, longer) = if x.is_some() {
+                        ("mates (R2)", "first mates (R1)")
+                    } else {
+                        ("first mates (R1)", "mates (R2)")
+                    };
+                    Some(Err(anyhow!(
+                        "Paired read files are out of sync: the {shorter} have fewer reads than the {longer}"
+                    )))
+                }
+            }
+        })
     }
 
     /// Publically exposes the results dictionary and returns either the observed count
     /// of a specific match or a zero if there were no matches.
     #[must_use]
     pub fn get_value(&self, token: &[u8]) -> &usize {
-        match self.results.get(token) {
-            Some(c) => c,
-            None => &0,
-        }
+        self.results.get(token).unwrap_or(&0)
+    }
+
+    /// Returns the observed count of a recombined guide pair or a zero if it was not observed
+    #[must_use]
+    pub fn get_recombined_value(&self, token: &[u8]) -> &usize {
+        self.recombined.get(token).unwrap_or(&0)
+    }
+
+    /// Returns the number of distinct UMIs observed for a specific match or a zero
+    /// if there were no matches or UMIs were not extracted.
+    #[must_use]
+    pub fn get_umi_value(&self, token: &[u8]) -> &usize {
+        self.umi_results
+            .as_ref()
+            .and_then(|x| x.get(token))
+            .unwrap_or(&0)
+    }
+
+    /// Returns `true` if UMIs were extracted and deduplicated
+    #[must_use]
+    pub fn has_umis(&self) -> bool {
+        self.umi_results.is_some()
+    }
+
+    /// Returns the number of assigned reads at each offset, ordered by decreasing count
+    #[must_use]
+    pub fn offset_counts(&self) -> Vec<(Offset, usize)> {
+        let mut counts = self
+            .offsets
+            .iter()
+            .map(|(offset, count)| (*offset, *count))
+            .collect::<Vec<_>>();
+        counts.sort_unstable_by_key(|(offset, count)| {
+            (
+                std::cmp::Reverse(*count),
+                offset.is_reverse(),
+                *offset.index(),
+            )
+        });
+        counts
+    }
+
+    /// Returns the most frequent sequences of the windows extracted from unassigned
+    /// reads and their counts, if unmapped reads were collected. Reads too short to
+    /// extract a window are reported under an empty sequence.
+    #[must_use]
+    pub fn top_unmapped(&self, n: usize) -> Option<Vec<(&Vec<u8>, usize)>> {
+        self.unmapped.as_ref().map(|sequences| {
+            let mut ranked = sequences
+                .iter()
+                .map(|(sequence, count)| (sequence, *count))
+                .collect::<Vec<_>>();
+            ranked.sort_unstable_by(|(x, a), (y, b)| b.cmp(a).then_with(|| x.cmp(y)));
+            ranked.truncate(n);
+            ranked
+        })
+    }
+
+    /// Returns the number of assigned reads matched in the forward direction
+    #[must_use]
+    pub fn forward_reads(&self) -> usize {
+        self.offsets
+            .iter()
+            .filter(|(offset, _)| offset.is_forward())
+            .map(|(_, count)| count)
+            .sum()
+    }
+
+    /// Returns the number of assigned reads matched on the reverse complement
+    #[must_use]
+    pub fn reverse_reads(&self) -> usize {
+        self.offsets
+            .iter()
+            .filter(|(offset, _)| offset.is_reverse())
+            .map(|(_, count)| count)
+            .sum()
+    }
+
+    /// An iterator over the aliases of all observed recombined guide pairs
+    pub fn recombined(&self) -> impl Iterator<Item = &Vec<u8>> {
+        self.recombined.keys()
     }
 
     /// Assignment process against the [`Library`].
@@ -89,10 +682,142 @@ impl Counter {
         }
     }
 
-    /// Assignment process of the struct performing the string matching.
+    /// Checks whether a token is an ambiguous neighbour of several library sequences.
+    /// This will only execute if the [`Permuter`] is optionally not [`None`].
+    fn check_ambiguous(token: &[u8], permuter: &Option<Permuter>) -> bool {
+        permuter.as_ref().is_some_and(|p| p.is_ambiguous(token))
+    }
+
+    /// Assigns a single record against the [`Library`] and returns the matched alias.
+    /// Used by counters which aggregate reads under additional keys (i.e. cell barcodes).
+    pub(crate) fn assign_record<'a>(
+        record: &Record,
+        library: &'a Library,
+        offsets: &[Offset],
+        options: &AssignmentOptions,
+    ) -> Option<&'a Vec<u8>> {
+        Self::assign_tolerant(record, library, &options.permuter, offsets, options)
+            .matched()
+            .map(|x| x.0)
+    }
+
+    /// Assigns a record first with [`Self::assign`] and then, if that fails and the
+    /// [`Permuter`] holds insertions and deletions, with [`Self::assign_indel`].
+    /// Each window of [`Self::windows`] is tried in order until the record is either
+    /// matched or rejected by the [`QualityFilter`]. Windows shifted by one basepair
+    /// are only tried once no window matches at its own offset, so that reads of
+    /// adjacent offsets are assigned to their own, and indels are only considered
+    /// once no window matches without one. The `permuter` is provided separately from the
+    /// [`AssignmentOptions`] so that each guide of a dual-guide read uses its own.
+    fn assign_tolerant<'a>(
+        record: &Record,
+        library: &'a Library,
+        permuter: &Option<Permuter>,
+        offsets: &[Offset],
+        options: &AssignmentOptions,
+    ) -> Assignment<'a> {
+        let (position, quality) = (&options.position, &options.quality);
+        let windows = Self::windows(record, library, offsets, &options.anchor);
+        let indels = permuter.as_ref().is_some_and(Permuter::has_indels);
+        let mut miss = Assignment::Missed(MissReason::OutOfBounds);
+        for position in Self::positions(position) {
+            for (offset, size) in &windows {
+                match Self::assign(record, library, permuter, *offset, *size, position, quality) {
+                    Assignment::Missed(reason) if reason != MissReason::LowQuality => {
+                        miss = miss.or_miss(reason);
+                    }
+                    // matched or rejected by the quality filter
+                    assignment => return assignment,
+                }
+            }
+        }
+        for (offset, size) in windows.iter().filter(|_| indels) {
+            match Self::assign_indel(record, library, permuter, *offset, *size, quality) {
+                Assignment::Missed(reason) => miss = miss.or_miss(reason),
+                assignment => return assignment,
+            }
+        }
+        miss
+    }
+
+    /// Lists the positions at which each window is tried in order: centered and then
+    /// shifted by +1 and -1 if the position is recursed, or only as provided otherwise
+    fn positions(position: &Position) -> &'static [Position] {
+        match position {
+            Position::Centered => &[Position::Centered, Position::Plus, Position::Minus],
+            Position::Plus => &[Position::Plus],
+            Position::Minus => &[Position::Minus],
+            Position::Null => &[Position::Null],
+        }
+    }
+
+    /// Lists the offset and size of every window to try for a record. Each length
+    /// bucket of the [`Library`] is tried from longest to shortest at each of the
+    /// provided offsets, or at the offset of the [`Anchor`] if it is provided and
+    /// located within the record.
+    fn windows(
+        record: &Record,
+        library: &Library,
+        offsets: &[Offset],
+        anchor: &Option<Anchor>,
+    ) -> Vec<(Offset, usize)> {
+        library
+            .sizes()
+            .iter()
+            .flat_map(
+                |size| match anchor.as_ref().and_then(|a| a.locate(record, *size)) {
+                    Some(offset) => vec![(offset, *size)],
+                    None => offsets.iter().map(|offset| (*offset, *size)).collect(),
+                },
+            )
+            .collect()
+    }
+
+    /// Assignment process allowing for a single insertion or deletion within the guide.
+    /// A window one basepair shorter and one basepair longer than the guide are trimmed
+    /// at the offset and matched against the deletions and insertions of the [`Permuter`].
+    /// If both windows match different sequences the read is ambiguous and unassigned.
+    fn assign_indel<'a>(
+        record: &Record,
+        library: &'a Library,
+        permuter: &Option<Permuter>,
+        offset: Offset,
+        size: usize,
+        quality: &Option<QualityFilter>,
+    ) -> Assignment<'a> {
+        let Some(p) = permuter.as_ref().filter(|p| p.has_indels()) else {
+            return Assignment::Missed(MissReason::NoMatch);
+        };
+        let Some(deletion) = Self::apply_trim(record, offset, size - 1, &Position::Null) else {
+            return Assignment::Missed(MissReason::OutOfBounds);
+        };
+        let deletion = p.contains_deletion(&deletion);
+        let insertion = Self::apply_trim(record, offset, size + 1, &Position::Null)
+            .and_then(|token| p.contains_insertion(&token));
+        let (sequence, window) = match (deletion, insertion) {
+            (Some(x), Some(y)) if x != y => return Assignment::Missed(MissReason::Ambiguous),
+            (Some(x), _) => (x, size - 1),
+            (None, Some(x)) => (x, size + 1),
+            (None, None) => return Assignment::Missed(MissReason::NoMatch),
+        };
+        let qual = Self::window_quality(record, offset, window, &Position::Null, quality);
+        match library.alias(sequence) {
+            Some(_) if !Self::passes_quality(quality, &qual) => {
+                Assignment::Missed(MissReason::LowQuality)
+            }
+            Some(alias) => Assignment::Matched(alias, MatchType::Indel, offset),
+            None => Assignment::Missed(MissReason::NoMatch),
+        }
+    }
+
+    /// Assignment process of the struct performing the string matching at a single
+    /// window and position.
     /// Tokens are first matched against the library (exact matches)
     /// and if none are found then are matched against the permutations (oneoff matches).
-    /// If still none are found then it returns [`None`].
+    /// If a [`QualityFilter`] is provided mismatches are only accepted where it allows
+    /// them and matched windows failing its requirements are rejected.
+    /// If still none are found then the record is missed, noting whether its window
+    /// was out of bounds or ambiguous.
     fn assign<'a>(
         record: &Record,
         library: &'a Library,
@@ -100,42 +825,150 @@ impl Counter {
         offset: Offset,
         size: usize,
         position: &Position,
-    ) -> Option<&'a Vec<u8>> {
+        quality: &Option<QualityFilter>,
+    ) -> Assignment<'a> {
         // Apply Trimming to Record
-        let token = match Self::apply_trim(record, offset, size, position) {
-            Some(t) => t,
-            None => return None,
+        let Some(token) = Self::apply_trim(record, offset, size, position) else {
+            return Assignment::Missed(MissReason::OutOfBounds);
         };
+        let qual = Self::window_quality(record, offset, size, position, quality);
 
         // Check trimmed sequence against library
         let alias = match Self::check_library(&token, library) {
-            Some(s) => Some(s),
+            Some(s) => Some((s, MatchType::Exact)),
             None => match Self::check_permuter(&token, permuter) {
-                Some(s) => library.alias(s),
+                Some(s) if Self::allows_mismatch(quality, &token, s, &qual) => {
+                    library.alias(s).map(|a| (a, MatchType::Mismatch))
+                }
+                // a mismatch at a high quality base rejects the record outright
+                Some(_) => return Assignment::Missed(MissReason::LowQuality),
                 None => None,
             },
         };
 
-        // If no alias is found try offsetting the position by one
-        if alias.is_none() {
-            match position {
-                // Try offsetting +1
-                Position::Centered => {
-                    Self::assign(record, library, permuter, offset, size, &Position::Plus)
-                }
+        match alias {
+            // Reject the record if the matched window is of low quality
+            Some(_) if !Self::passes_quality(quality, &qual) => {
+                Assignment::Missed(MissReason::LowQuality)
+            }
 
-                // Try offsetting -1
-                Position::Plus => {
-                    Self::assign(record, library, permuter, offset, size, &Position::Minus)
-                }
+            // Otherwise return the library alias (and whether the position was shifted)
+            Some((a, match_type)) => {
+                let match_type = match position {
+                    Position::Plus => MatchType::PositionPlus,
+                    Position::Minus => MatchType::PositionMinus,
+                    Position::Centered | Position::Null => match_type,
+                };
+                Assignment::Matched(a, match_type, offset)
+            }
 
-                // Both positions have been tried or no recursion option so return the null
-                Position::Minus | Position::Null => alias,
+            // Otherwise note whether the window was ambiguous
+            None if Self::check_ambiguous(&token, permuter) => {
+                Assignment::Missed(MissReason::Ambiguous)
             }
+            None => Assignment::Missed(MissReason::NoMatch),
+        }
+    }
+
+    /// Extracts the qualities of the trimmed window if a [`QualityFilter`] is provided
+    /// and the record has qualities
+    fn window_quality(
+        record: &Record,
+        offset: Offset,
+        size: usize,
+        position: &Position,
+        quality: &Option<QualityFilter>,
+    ) -> Option<Vec<u8>> {
+        quality.as_ref()?;
+        let qual = record.qual()?;
+        let (min, max) = Self::bounds(qual, *offset.index(), size, position)?;
+        match offset {
+            Offset::Forward(_) => Some(qual[min..max].to_vec()),
+            // the reverse complemented window is read from the end of the qualities
+            Offset::Reverse(_) => Some(
+                qual[qual.len() - max..qual.len() - min]
+                    .iter()
+                    .rev()
+                    .copied()
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Checks the window qualities against the [`QualityFilter`] if both are provided
+    fn passes_quality(quality: &Option<QualityFilter>, qual: &Option<Vec<u8>>) -> bool {
+        match (quality, qual) {
+            (Some(f), Some(q)) => f.passes(q),
+            _ => true,
         }
-        // Otherwise return the library alias
-        else {
-            alias
+    }
+
+    /// Checks whether the [`QualityFilter`] allows the mismatches between the token
+    /// and its library sequence if both are provided
+    fn allows_mismatch(
+        quality: &Option<QualityFilter>,
+        token: &[u8],
+        reference: &[u8],
+        qual: &Option<Vec<u8>>,
+    ) -> bool {
+        match (quality, qual) {
+            (Some(f), Some(q)) => f.allows_mismatch(token, reference, q),
+            _ => true,
+        }
+    }
+
+    /// Writes an unassigned record to the [`Unmapped`] if provided alongside the window
+    /// of the longest library sequences extracted at its first offset (or its located [`Anchor`])
+    fn write_unmapped(
+        unmapped: &mut Option<Unmapped>,
+        record: &Record,
+        library: &Library,
+        offsets: &[Offset],
+        options: &AssignmentOptions,
+    ) -> Result<()> {
+        if let Some(u) = unmapped {
+            let size = library.size();
+            let window = options
+                .anchor
+                .as_ref()
+                .and_then(|a| a.locate(record, size))
+                .or_else(|| offsets.first().copied())
+                .and_then(|offset| Self::apply_trim(record, offset, size, &Position::Null));
+            u.insert(record, window)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the assignment of a record to the [`ReadLog`] if provided
+    fn write_log(log: &Option<ReadLog>, record: &Record, assignment: Assignment) -> Result<()> {
+        if let Some(l) = log {
+            l.write(
+                record.id(),
+                assignment
+                    .matched()
+                    .map(|(alias, match_type, offset)| (alias.as_slice(), match_type, offset)),
+                assignment.missed(),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Flushes the [`Unmapped`] if provided and returns its tally of window sequences
+    fn finish_unmapped(unmapped: Option<Unmapped>) -> Result<Option<HashMap<Vec<u8>, usize>>> {
+        unmapped.map(Unmapped::finish).transpose()
+    }
+
+    /// Resolves the assignments of both mates of a read pair into a single
+    /// assignment depending on the [`MateMode`].
+    fn resolve_mates<'a>(
+        primary: Option<&'a Vec<u8>>,
+        mate: Option<&'a Vec<u8>>,
+        mate_mode: MateMode,
+    ) -> Option<&'a Vec<u8>> {
+        match (primary, mate, mate_mode) {
+            (Some(x), Some(y), _) => (x == y).then_some(x),
+            (Some(x), None, MateMode::Either) | (None, Some(x), MateMode::Either) => Some(x),
+            _ => None,
         }
     }
 
@@ -164,16 +997,13 @@ impl Counter {
         let (min, max) = match position {
             Position::Plus => (offset + 1, offset + 1 + size),
             Position::Minus => {
-                let min = match offset.checked_sub(1) {
-                    Some(m) => m,
-                    None => return None,
-                };
+                let min = offset.checked_sub(1)?;
                 (min, min + size)
             }
             _ => (offset, offset + size),
         };
         if max > seq.len() {
-            return None;
+            None
         } else {
             Some((min, max))
         }
@@ -203,38 +1033,6 @@ impl Counter {
             .map(|(min, max)| record.seq_rev_comp()[min..max].to_vec())
     }
 
-    /// Main functionality of the struct. Performs the counting operation.
-    /// Input sequences are assigned to their respective match within the
-    /// library or the permutations.
-    /// Finally they are folded into a [`HashMap`] whose keys are the sequence
-    /// alias within the library and the values the number of observed counts.
-    fn count(
-        reader: Box<dyn FastxRead<Item = Record>>,
-        library: &Library,
-        permuter: &Option<Permuter>,
-        offset: Offset,
-        size: usize,
-        position: &Position,
-        total_reads: &mut usize,
-        matched_reads: &mut usize,
-    ) -> HashMap<Vec<u8>, usize> {
-        reader
-            .into_iter()
-            .map(|x| {
-                *total_reads += 1;
-                x
-            })
-            .filter_map(|x| Self::assign(&x, library, permuter, offset, size, position))
-            .map(|x| {
-                *matched_reads += 1;
-                x
-            })
-            .fold(HashMap::new(), |mut accum, x| {
-                *accum.entry(x.clone()).or_insert(0) += 1;
-                accum
-            })
-    }
-
     /// Returns the total number of reads processed
     pub fn total_reads(&self) -> usize {
         self.total_reads
@@ -245,6 +1043,39 @@ impl Counter {
         self.matched_reads
     }
 
+    /// Returns the number of reads whose guides matched the library
+    /// but were paired in a combination not found in the design
+    pub fn recombined_reads(&self) -> usize {
+        self.recombined_reads
+    }
+
+    /// Returns the number of matched reads which were recovered with a
+    /// single insertion or deletion
+    pub fn indel_reads(&self) -> usize {
+        self.indel_reads
+    }
+
+    /// Returns the number of assigned reads excluded from the UMI counts because their
+    /// UMI ran past the end of the read or contained an ambiguous nucleotide
+    pub fn invalid_umi_reads(&self) -> usize {
+        self.invalid_umi_reads
+    }
+
+    /// Returns the number of reads rejected for the base qualities of their guide window
+    pub fn low_quality_reads(&self) -> usize {
+        self.miss_count(MissReason::LowQuality)
+    }
+
+    /// Returns the number of assigned reads matched with the provided [`MatchType`]
+    pub fn match_count(&self, match_type: MatchType) -> usize {
+        *self.outcomes.matches.get(&match_type).unwrap_or(&0)
+    }
+
+    /// Returns the number of unassigned reads missed for the provided [`MissReason`]
+    pub fn miss_count(&self, reason: MissReason) -> usize {
+        *self.outcomes.misses.get(&reason).unwrap_or(&0)
+    }
+
     /// Returns the fraction of reads that matched the library
     pub fn fraction_mapped(&self) -> f64 {
         self.matched_reads as f64 / self.total_reads as f64
@@ -254,9 +1085,12 @@ impl Counter {
 #[cfg(test)]
 mod test {
 
-    use super::{Counter, Library, Permuter, Position};
-    use crate::Offset;
-    use fxread::{FastaReader, FastxRead, Record};
+    use super::{
+        AssignmentOptions, Counter, DualLibrary, Library, MatchType, MateMode, MissReason,
+        Permuter, Position, ReadSinks,
+    };
+    use crate::{Anchor, Offset, QualityFilter, ReadLog, Umi, Unmapped};
+    use fxread::{FastaReader, FastqReader, FastxRead, Record};
 
     fn trim_reader(distance: bool) -> Box<dyn FastxRead<Item = Record>> {
         let sequence: &'static [u8] = match distance {
@@ -283,7 +1117,14 @@ mod test {
     fn count_no_distance_no_permute() {
         let trimmer = trim_reader(false);
         let library = library();
-        let count = Counter::new(trimmer, &library, &None, Offset::Forward(0), 4, false);
+        let count = Counter::new(
+            trimmer,
+            &library,
+            &[Offset::Forward(0)],
+            &AssignmentOptions::new(false),
+            ReadSinks::default(),
+        )
+        .unwrap();
         assert_eq!(*count.get_value(b"seq.0"), 1);
     }
 
@@ -291,7 +1132,14 @@ mod test {
     fn count_no_distance_with_permute() {
         let trimmer = trim_reader(true);
         let library = library();
-        let count = Counter::new(trimmer, &library, &None, Offset::Forward(0), 4, false);
+        let count = Counter::new(
+            trimmer,
+            &library,
+            &[Offset::Forward(0)],
+            &AssignmentOptions::new(false),
+            ReadSinks::default(),
+        )
+        .unwrap();
         assert_eq!(*count.get_value(b"seq.0"), 0);
     }
 
@@ -299,7 +1147,14 @@ mod test {
     fn count_with_distance_no_permute() {
         let trimmer = trim_reader(true);
         let library = library();
-        let count = Counter::new(trimmer, &library, &None, Offset::Forward(0), 4, false);
+        let count = Counter::new(
+            trimmer,
+            &library,
+            &[Offset::Forward(0)],
+            &AssignmentOptions::new(false),
+            ReadSinks::default(),
+        )
+        .unwrap();
         assert_eq!(*count.get_value(b"seq.0"), 0);
     }
 
@@ -311,12 +1166,509 @@ mod test {
         let count = Counter::new(
             trimmer,
             &library,
-            &Some(permuter),
+            &[Offset::Forward(0)],
+            &AssignmentOptions::new(false).with_permuter(Some(permuter)),
+            ReadSinks::default(),
+        )
+        .unwrap();
+        assert_eq!(*count.get_value(b"seq.0"), 1);
+    }
+
+    fn mate_reader(matching: bool) -> Box<dyn FastxRead<Item = Record>> {
+        let sequence: &'static [u8] = match matching {
+            false => b">seq.0\nTTTT\n",
+            true => b">seq.0\nCAGT\n",
+        };
+        Box::new(FastaReader::new(sequence))
+    }
+
+    #[test]
+    fn count_paired_either() {
+        let library = library();
+        let count = Counter::new_paired(
+            trim_reader(true),
+            mate_reader(true),
+            &library,
+            &[Offset::Forward(0)],
+            &[Offset::Reverse(0)],
+            &AssignmentOptions::new(false),
+            ReadSinks::default(),
+        )
+        .unwrap();
+        assert_eq!(*count.get_value(b"seq.0"), 1);
+        assert_eq!(count.total_reads(), 1);
+    }
+
+    #[test]
+    fn count_paired_agree() {
+        let library = library();
+        let count = Counter::new_paired(
+            trim_reader(false),
+            mate_reader(false),
+            &library,
+            &[Offset::Forward(0)],
+            &[Offset::Reverse(0)],
+            &AssignmentOptions::new(false).with_mate_mode(MateMode::Agree),
+            ReadSinks::default(),
+        )
+        .unwrap();
+        assert_eq!(*count.get_value(b"seq.0"), 0);
+
+        let count = Counter::new_paired(
+            trim_reader(false),
+            mate_reader(true),
+            &library,
+            &[Offset::Forward(0)],
+            &[Offset::Reverse(0)],
+            &AssignmentOptions::new(false).with_mate_mode(MateMode::Agree),
+            ReadSinks::default(),
+        )
+        .unwrap();
+        assert_eq!(*count.get_value(b"seq.0"), 1);
+    }
+
+    #[test]
+    fn count_paired_out_of_sync() {
+        let library = library();
+        let mates: &'static [u8] = b">seq.0\nCAGT\n>seq.1\nCAGT\n";
+        let count = Counter::new_paired(
+            trim_reader(true),
+            Box::new(FastaReader::new(mates)),
+            &library,
+            &[Offset::Forward(0)],
+            &[Offset::Reverse(0)],
+            &AssignmentOptions::new(false),
+            ReadSinks::default(),
+        );
+        assert!(count.is_err());
+    }
+
+    #[test]
+    fn count_dual_mates_out_of_sync() {
+        let library = dual_library();
+        let reads: &'static [u8] = b">r.0\nACTG\n>r.1\nACTG\n";
+        let mates: &'static [u8] = b">r.0\nTTAA\n";
+        let count = Counter::new_dual(
+            Box::new(FastaReader::new(reads)),
+            Some(Box::new(FastaReader::new(mates))),
+            &library,
+            Offset::Forward(0),
             Offset::Forward(0),
-            4,
-            false,
+            &AssignmentOptions::new(false),
+            &None,
         );
+        assert!(count.is_err());
+    }
+
+    fn dual_library() -> DualLibrary {
+        let first: &'static [u8] = b">c.0\nACTG\n>c.1\nACTG\n>c.2\nGGGG\n";
+        let second: &'static [u8] = b">c.0\nTTAA\n>c.1\nGGCC\n>c.2\nTTAA\n";
+        DualLibrary::from_readers(
+            Box::new(FastaReader::new(first)),
+            Box::new(FastaReader::new(second)),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn count_dual_same_read() {
+        let library = dual_library();
+        let reads: &'static [u8] = b">r.0\nACTGGGCC\n>r.1\nGGGGGGCC\n>r.2\nACTGAAAA\n";
+        let count = Counter::new_dual(
+            Box::new(FastaReader::new(reads)),
+            None,
+            &library,
+            Offset::Forward(0),
+            Offset::Forward(4),
+            &AssignmentOptions::new(false),
+            &None,
+        )
+        .unwrap();
+        assert_eq!(*count.get_value(b"c.1"), 1);
+        assert_eq!(count.matched_reads(), 1);
+        assert_eq!(count.recombined_reads(), 1);
+        assert_eq!(*count.get_recombined_value(b"GGGG_GGCC"), 1);
+        assert_eq!(*count.get_value(b"GGGG_GGCC"), 0);
+        assert_eq!(count.total_reads(), 3);
+
+        // every read is recorded as a single outcome
+        assert_eq!(count.match_count(MatchType::Exact), 1);
+        assert_eq!(count.miss_count(MissReason::Recombined), 1);
+        assert_eq!(count.miss_count(MissReason::NoMatch), 1);
+    }
+
+    #[test]
+    fn count_dual_mates_with_permute() {
+        let library = dual_library();
+        let reads: &'static [u8] = b">r.0\nACTA\n";
+        let mates: &'static [u8] = b">r.0\nTTAA\n";
+        let count = Counter::new_dual(
+            Box::new(FastaReader::new(reads)),
+            Some(Box::new(FastaReader::new(mates))),
+            &library,
+            Offset::Forward(0),
+            Offset::Forward(0),
+            &AssignmentOptions::new(false)
+                .with_permuter(Some(Permuter::new(library.first().keys()))),
+            &Some(Permuter::new(library.second().keys())),
+        )
+        .unwrap();
+        assert_eq!(*count.get_value(b"c.0"), 1);
+    }
+
+    #[test]
+    fn count_with_umi() {
+        let library = library();
+        let reads: &'static [u8] = b">r.0\nACTGAA\n>r.1\nACTGAA\n>r.2\nACTGAT\n>r.3\nACTGCC\n";
+        let umi = Some(Umi::new(4, 2, false));
+        let count = Counter::new(
+            Box::new(FastaReader::new(reads)),
+            &library,
+            &[Offset::Forward(0)],
+            &AssignmentOptions::new(false).with_umi(umi),
+            ReadSinks::default(),
+        )
+        .unwrap();
+        assert!(count.has_umis());
+        assert_eq!(*count.get_value(b"seq.0"), 4);
+        assert_eq!(*count.get_umi_value(b"seq.0"), 3);
+
+        let umi = Some(Umi::new(4, 2, true));
+        let count = Counter::new(
+            Box::new(FastaReader::new(reads)),
+            &library,
+            &[Offset::Forward(0)],
+            &AssignmentOptions::new(false).with_umi(umi),
+            ReadSinks::default(),
+        )
+        .unwrap();
+        assert_eq!(*count.get_umi_value(b"seq.0"), 2);
+    }
+
+    #[test]
+    fn count_with_invalid_umi() {
+        let library = library();
+        let reads: &'static [u8] = b">r.0\nACTGAA\n>r.1\nACTGNA\n>r.2\nACTGA\n";
+        let count = Counter::new(
+            Box::new(FastaReader::new(reads)),
+            &library,
+            &[Offset::Forward(0)],
+            &AssignmentOptions::new(false).with_umi(Some(Umi::new(4, 2, false))),
+            ReadSinks::default(),
+        )
+        .unwrap();
+        assert_eq!(*count.get_value(b"seq.0"), 3);
+        assert_eq!(*count.get_umi_value(b"seq.0"), 1);
+        assert_eq!(count.invalid_umi_reads(), 2);
+    }
+
+    #[test]
+    fn count_with_indels() {
+        let library = Library::from_reader(Box::new(FastaReader::new(
+            b">seq.0\nACGTAC\n>seq.1\nTTGGCC\n".as_slice(),
+        )))
+        .unwrap();
+        let permuter = Some(Permuter::new(library.keys()).with_indels(library.keys()));
+        let reads: &'static [u8] = b">r.0\nAGTACAA\n>r.1\nTTGAGCCA\n>r.2\nACGTACA\n";
+        let count = Counter::new(
+            Box::new(FastaReader::new(reads)),
+            &library,
+            &[Offset::Forward(0)],
+            &AssignmentOptions::new(false).with_permuter(permuter),
+            ReadSinks::default(),
+        )
+        .unwrap();
+        assert_eq!(*count.get_value(b"seq.0"), 2);
+        assert_eq!(*count.get_value(b"seq.1"), 1);
+        assert_eq!(count.indel_reads(), 2);
+    }
+
+    #[test]
+    fn count_variable_lengths() {
+        let records: &'static [u8] = b">g.0\nACTGAC\n>g.1\nTTGCA\n>g.2\nACTG\n";
+        let library = Library::from_reader(Box::new(FastaReader::new(records))).unwrap();
+        let permuter = Some(Permuter::new(library.keys()));
+        let reads: &'static [u8] =
+            b">r.0\nACTGACGG\n>r.1\nTTGCAGGG\n>r.2\nACTGTTTT\n>r.3\nTTGGAGGG\n>r.4\nACTGAGGG\n";
+        let count = Counter::new(
+            Box::new(FastaReader::new(reads)),
+            &library,
+            &[Offset::Forward(0)],
+            &AssignmentOptions::new(false).with_permuter(permuter),
+            ReadSinks::default(),
+        )
+        .unwrap();
+
+        // the longest length bucket is tried first
+        assert_eq!(*count.get_value(b"g.0"), 2);
+        assert_eq!(*count.get_value(b"g.1"), 2);
+        assert_eq!(*count.get_value(b"g.2"), 1);
+        assert_eq!(count.match_count(MatchType::Mismatch), 2);
+    }
+
+    #[test]
+    fn count_multiple_offsets() {
+        let library = library();
+        let reads: &'static [u8] = b">r.0\nGACTGA\n>r.1\nACTGAA\n>r.2\nGACTGA\n>r.3\nGGGGGG\n";
+        let count = Counter::new(
+            Box::new(FastaReader::new(reads)),
+            &library,
+            &[Offset::Forward(1), Offset::Forward(0)],
+            &AssignmentOptions::new(false),
+            ReadSinks::default(),
+        )
+        .unwrap();
+        assert_eq!(*count.get_value(b"seq.0"), 3);
+        assert_eq!(
+            count.offset_counts(),
+            vec![(Offset::Forward(1), 2), (Offset::Forward(0), 1)]
+        );
+    }
+
+    #[test]
+    fn count_adjacent_offsets_with_recursion() {
+        let library = library();
+        let reads: &'static [u8] = b">r.0\nGACTGA\n>r.1\nACTGAA\n>r.2\nGACTGA\n";
+        let count = Counter::new(
+            Box::new(FastaReader::new(reads)),
+            &library,
+            &[Offset::Forward(0), Offset::Forward(1)],
+            &AssignmentOptions::new(true),
+            ReadSinks::default(),
+        )
+        .unwrap();
+        assert_eq!(*count.get_value(b"seq.0"), 3);
+        assert_eq!(
+            count.offset_counts(),
+            vec![(Offset::Forward(1), 2), (Offset::Forward(0), 1)]
+        );
+        assert_eq!(count.match_count(MatchType::Exact), 3);
+        assert_eq!(count.match_count(MatchType::PositionPlus), 0);
+    }
+
+    #[test]
+    fn count_mixed_orientations() {
+        let library = library();
+        let reads: &'static [u8] = b">r.0\nACTGAA\n>r.1\nCAGTAA\n>r.2\nACTGTT\n";
+        let count = Counter::new(
+            Box::new(FastaReader::new(reads)),
+            &library,
+            &[Offset::Forward(0), Offset::Reverse(2)],
+            &AssignmentOptions::new(false),
+            ReadSinks::default(),
+        )
+        .unwrap();
+        assert_eq!(*count.get_value(b"seq.0"), 3);
+        assert_eq!(count.forward_reads(), 2);
+        assert_eq!(count.reverse_reads(), 1);
+    }
+
+    #[test]
+    fn count_with_quality() {
+        let library = library();
+        let reads: &'static [u8] =
+            b"@r.0\nACTG\n+\nIIII\n@r.1\nACTG\n+\nII#I\n@r.2\nACTT\n+\nIII#\n@r.3\nACTT\n+\nIIII\n";
+        let quality = Some(QualityFilter::new(Some(20), None, Some(20)));
+        let count = Counter::new(
+            Box::new(FastqReader::new(reads)),
+            &library,
+            &[Offset::Forward(0)],
+            &AssignmentOptions::new(false)
+                .with_permuter(Some(permuter()))
+                .with_quality(quality),
+            ReadSinks::default(),
+        )
+        .unwrap();
+        // the low quality window and the high quality mismatch are both rejected
         assert_eq!(*count.get_value(b"seq.0"), 1);
+        assert_eq!(count.low_quality_reads(), 3);
+        assert_eq!(count.miss_count(MissReason::LowQuality), 3);
+        assert_eq!(count.miss_count(MissReason::NoMatch), 0);
+        assert_eq!(count.matched_reads(), 1);
+
+        let quality = Some(QualityFilter::new(None, None, Some(20)));
+        let count = Counter::new(
+            Box::new(FastqReader::new(reads)),
+            &library,
+            &[Offset::Forward(0)],
+            &AssignmentOptions::new(false)
+                .with_permuter(Some(permuter()))
+                .with_quality(quality),
+            ReadSinks::default(),
+        )
+        .unwrap();
+        // only the mismatch at a high quality base is rejected
+        assert_eq!(*count.get_value(b"seq.0"), 3);
+        assert_eq!(count.low_quality_reads(), 1);
+        assert_eq!(count.miss_count(MissReason::LowQuality), 1);
+
+        // the rejected mismatch is not reassigned through a shifted window
+        let reads: &'static [u8] = b"@r.0\nACTTG\n+\nIIIII\n";
+        let permuter = Permuter::new(library.keys()).with_indels(library.keys());
+        let count = Counter::new(
+            Box::new(FastqReader::new(reads)),
+            &library,
+            &[Offset::Forward(0)],
+            &AssignmentOptions::new(true)
+                .with_permuter(Some(permuter))
+                .with_quality(Some(QualityFilter::new(None, None, Some(20)))),
+            ReadSinks::default(),
+        )
+        .unwrap();
+        assert_eq!(count.matched_reads(), 0);
+        assert_eq!(count.miss_count(MissReason::LowQuality), 1);
+    }
+
+    #[test]
+    fn count_with_unmapped() {
+        let library = library();
+        let reads: &'static [u8] =
+            b">r.0\nACTGAA\n>r.1\nGGGGAA\n>r.2\nTTTTAA\n>r.3\nGGGGCC\n>r.4\nAC\n";
+        let count = Counter::new(
+            Box::new(FastaReader::new(reads)),
+            &library,
+            &[Offset::Forward(0)],
+            &AssignmentOptions::new(false),
+            ReadSinks::new(
+                Some(Unmapped::new(Box::new(std::io::sink()), false, false)),
+                None,
+            ),
+        )
+        .unwrap();
+        let top = count.top_unmapped(2).unwrap();
+        assert_eq!(top, vec![(&b"GGGG".to_vec(), 2), (&b"".to_vec(), 1)]);
+    }
+
+    #[test]
+    fn count_unmapped_write_error() {
+        let library = library();
+        let reads: &'static [u8] = b">r.0\nGGGGAA\n";
+        // a full buffer fails every write
+        let writer = Box::new(std::io::Cursor::new([0u8; 0]));
+        let count = Counter::new(
+            Box::new(FastaReader::new(reads)),
+            &library,
+            &[Offset::Forward(0)],
+            &AssignmentOptions::new(false),
+            ReadSinks::new(Some(Unmapped::new(writer, false, false)), None),
+        );
+        assert!(count.is_err());
+    }
+
+    #[test]
+    fn assign_match_types() {
+        let library = library();
+        let permuter = Some(permuter());
+        let match_type = |sequence: &'static [u8]| {
+            let record = FastaReader::new(sequence).next().unwrap();
+            let options = AssignmentOptions::new(true);
+            Counter::assign_tolerant(
+                &record,
+                &library,
+                &permuter,
+                &[Offset::Forward(1)],
+                &options,
+            )
+            .matched()
+            .map(|(_, match_type, _)| match_type)
+        };
+        assert_eq!(match_type(b">r\nAACTGA\n"), Some(MatchType::Exact));
+        assert_eq!(match_type(b">r\nAACTTA\n"), Some(MatchType::Mismatch));
+        assert_eq!(match_type(b">r\nAAACTG\n"), Some(MatchType::PositionPlus));
+        assert_eq!(match_type(b">r\nACTGAA\n"), Some(MatchType::PositionMinus));
+        assert_eq!(match_type(b">r\nAAAAAA\n"), None);
+    }
+
+    #[test]
+    fn count_outcomes() {
+        let sequence: &'static [u8] = b">seq.0\nACTG\n>seq.1\nACTT\n";
+        let library = Library::from_reader(Box::new(FastaReader::new(sequence))).unwrap();
+        let permuter = Some(Permuter::new(library.keys()));
+        let reads: &'static [u8] = b">r.0\nACTG\n>r.1\nACGG\n>r.2\nACTA\n>r.3\nGGGG\n>r.4\nAC\n";
+        let count = Counter::new(
+            Box::new(FastaReader::new(reads)),
+            &library,
+            &[Offset::Forward(0)],
+            &AssignmentOptions::new(false).with_permuter(permuter),
+            ReadSinks::default(),
+        )
+        .unwrap();
+        assert_eq!(count.match_count(MatchType::Exact), 1);
+        assert_eq!(count.match_count(MatchType::Mismatch), 1);
+        assert_eq!(count.match_count(MatchType::PositionPlus), 0);
+        assert_eq!(count.miss_count(MissReason::Ambiguous), 1);
+        assert_eq!(count.miss_count(MissReason::NoMatch), 1);
+        assert_eq!(count.miss_count(MissReason::OutOfBounds), 1);
+        assert_eq!(count.miss_count(MissReason::LowQuality), 0);
+    }
+
+    #[test]
+    fn count_with_log() {
+        let library = library();
+        let buffer = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = ReadLog::from_writer(Box::new(SharedBuffer(buffer.clone()))).unwrap();
+        let reads: &'static [u8] = b">r.0\nACTG\n>r.1\nGGGG\n";
+        let count = Counter::new(
+            Box::new(FastaReader::new(reads)),
+            &library,
+            &[Offset::Reverse(0), Offset::Forward(0)],
+            &AssignmentOptions::new(false),
+            ReadSinks::new(None, Some(log.for_sample("s"))),
+        )
+        .unwrap();
+        assert_eq!(count.matched_reads(), 1);
+        assert_eq!(
+            buffer.lock().unwrap().as_slice(),
+            b"Read\tSample\tGuide\tMatch\tOffset\tOrientation\nr.0\ts\tseq.0\texact\t0\tforward\nr.1\ts\tNA\tno_match\tNA\tNA\n"
+        );
+    }
+
+    /// A writer sharing its buffer so the written output can be inspected
+    struct SharedBuffer(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+    impl std::io::Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn count_with_anchor() {
+        let library = library();
+        let anchor = Some(Anchor::new(b"GGTT", None, 0));
+        let reads: &'static [u8] =
+            b">r.0\nGGTTACTG\n>r.1\nAGGTTACTGA\n>r.2\nCAGTAACC\n>r.3\nACTG\n";
+        let count = Counter::new(
+            Box::new(FastaReader::new(reads)),
+            &library,
+            &[Offset::Forward(0)],
+            &AssignmentOptions::new(false).with_anchor(anchor),
+            ReadSinks::default(),
+        )
+        .unwrap();
+        // the final read has no anchor and falls back to the offset
+        assert_eq!(*count.get_value(b"seq.0"), 4);
+        assert_eq!(count.matched_reads(), 4);
+    }
+
+    #[test]
+    fn resolve_discordant_mates() {
+        let x = b"seq.0".to_vec();
+        let y = b"seq.1".to_vec();
+        assert_eq!(
+            Counter::resolve_mates(Some(&x), Some(&y), MateMode::Either),
+            None
+        );
+        assert_eq!(
+            Counter::resolve_mates(None, Some(&y), MateMode::Either),
+            Some(&y)
+        );
+        assert_eq!(
+            Counter::resolve_mates(None, Some(&y), MateMode::Agree),
+            None
+        );
     }
 
     #[test]
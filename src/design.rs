@@ -0,0 +1,285 @@
+use crate::permutes::SeedIndex;
+use crate::{Library, Permuter};
+use rayon::prelude::*;
+
+/// Maximum hamming distance between guides considered by the design checks
+pub const MAX_DISTANCE: usize = 3;
+
+/// Describes the sequence design of a single library guide: how close it is to the
+/// other guides of the library and its sequence composition
+#[derive(Debug, Clone)]
+pub struct GuideDesign {
+    alias: Vec<u8>,
+    sequence: Vec<u8>,
+    neighbours: [usize; MAX_DISTANCE],
+    nearest: Option<(Vec<u8>, usize)>,
+    one_offs: usize,
+    ambiguous_one_offs: usize,
+    reverse_complement: Option<Vec<u8>>,
+    homopolymer: usize,
+    gc_content: f64,
+}
+impl GuideDesign {
+    /// Returns the alias of the guide
+    #[must_use]
+    pub fn alias(&self) -> &[u8] {
+        &self.alias
+    }
+
+    /// Returns the sequence of the guide
+    #[must_use]
+    pub fn sequence(&self) -> &[u8] {
+        &self.sequence
+    }
+
+    /// Returns the number of other guides at exactly the provided hamming distance
+    /// (up to [`MAX_DISTANCE`])
+    #[must_use]
+    pub fn neighbours(&self, distance: usize) -> usize {
+        self.neighbours[distance - 1]
+    }
+
+    /// Returns the alias and distance of the nearest other guide within [`MAX_DISTANCE`]
+    #[must_use]
+    pub fn nearest(&self) -> Option<(&[u8], usize)> {
+        self.nearest
+            .as_ref()
+            .map(|(alias, distance)| (alias.as_slice(), *distance))
+    }
+
+    /// Returns the number of one-mismatch neighbours of the guide
+    #[must_use]
+    pub fn one_offs(&self) -> usize {
+        self.one_offs
+    }
+
+    /// Returns the number of one-mismatch neighbours which are ambiguous with another
+    /// guide and so are never assigned
+    #[must_use]
+    pub fn ambiguous_one_offs(&self) -> usize {
+        self.ambiguous_one_offs
+    }
+
+    /// Returns the fraction of the one-mismatch neighbourhood lost to ambiguity
+    #[must_use]
+    pub fn ambiguous_fraction(&self) -> f64 {
+        self.ambiguous_one_offs as f64 / self.one_offs.max(1) as f64
+    }
+
+    /// Returns the alias of the guide whose sequence is the reverse complement of
+    /// this guide (which may be itself if the guide is palindromic)
+    #[must_use]
+    pub fn reverse_complement(&self) -> Option<&[u8]> {
+        self.reverse_complement.as_deref()
+    }
+
+    /// Returns the length of the longest homopolymer run of the guide
+    #[must_use]
+    pub fn homopolymer(&self) -> usize {
+        self.homopolymer
+    }
+
+    /// Returns the GC content of the guide
+    #[must_use]
+    pub fn gc_content(&self) -> f64 {
+        self.gc_content
+    }
+}
+
+/// Quality control of the sequence design of a [`Library`].
+///
+/// Guides within a small hamming distance of each other share one-mismatch
+/// neighbours, which the [`Permuter`] places in its ambiguous null set, so reads
+/// with a sequencing error in those guides are silently lost.
+#[derive(Debug, Clone)]
+pub struct LibraryDesign {
+    guides: Vec<GuideDesign>,
+}
+impl LibraryDesign {
+    /// Calculates the design of every guide in the library ordered by alias.
+    /// Guides are only compared to the guides of their own length bucket.
+    #[must_use]
+    pub fn new(library: &Library) -> Self {
+        let sequences = library.keys().collect::<Vec<_>>();
+        let permuter = Permuter::new(sequences.iter().copied());
+        let indices = library
+            .sizes()
+            .iter()
+            .map(|size| (*size, SeedIndex::new(library.bucket(*size), MAX_DISTANCE)))
+            .collect::<Vec<_>>();
+        let mut guides = sequences
+            .par_iter()
+            .map(|sequence| {
+                let (_, index) = indices
+                    .iter()
+                    .find(|(size, _)| *size == sequence.len())
+                    .expect("missing length bucket");
+                Self::guide(library, &permuter, index, sequence)
+            })
+            .collect::<Vec<_>>();
+        guides.sort_unstable_by(|x, y| x.alias.cmp(&y.alias));
+        Self { guides }
+    }
+
+    /// Calculates the design of a single guide
+    fn guide(
+        library: &Library,
+        permuter: &Permuter,
+        index: &SeedIndex,
+        sequence: &[u8],
+    ) -> GuideDesign {
+        let mut neighbours = [0; MAX_DISTANCE];
+        let mut nearest: Option<(&Vec<u8>, usize)> = None;
+        for (neighbour, distance) in index.within(sequence) {
+            if distance == 0 {
+                continue;
+            }
+            neighbours[distance - 1] += 1;
+            if nearest.is_none_or(|(_, d)| distance < d) {
+                nearest = Some((neighbour, distance));
+            }
+        }
+        let one_offs = Permuter::one_off(sequence);
+        GuideDesign {
+            alias: library.alias(sequence).cloned().unwrap_or_default(),
+            sequence: sequence.to_vec(),
+            neighbours,
+            nearest: nearest.and_then(|(neighbour, distance)| {
+                library.alias(neighbour).map(|a| (a.clone(), distance))
+            }),
+            one_offs: one_offs.len(),
+            ambiguous_one_offs: one_offs.iter().filter(|x| permuter.is_ambiguous(x)).count(),
+            reverse_complement: library.alias(&reverse_complement(sequence)).cloned(),
+            homopolymer: homopolymer(sequence),
+            gc_content: gc_content(sequence),
+        }
+    }
+
+    /// Returns the design of every guide
+    #[must_use]
+    pub fn guides(&self) -> &[GuideDesign] {
+        &self.guides
+    }
+
+    /// Returns the number of guide pairs at exactly the provided hamming distance
+    #[must_use]
+    pub fn pairs(&self, distance: usize) -> usize {
+        self.guides
+            .iter()
+            .map(|g| g.neighbours(distance))
+            .sum::<usize>()
+            / 2
+    }
+
+    /// Returns the number of guides whose reverse complement is a library guide
+    #[must_use]
+    pub fn reverse_complement_collisions(&self) -> usize {
+        self.guides
+            .iter()
+            .filter(|g| g.reverse_complement.is_some())
+            .count()
+    }
+
+    /// Returns the fraction of all one-mismatch neighbours lost to ambiguity
+    #[must_use]
+    pub fn ambiguous_fraction(&self) -> f64 {
+        let ambiguous = self
+            .guides
+            .iter()
+            .map(|g| g.ambiguous_one_offs)
+            .sum::<usize>();
+        let total = self.guides.iter().map(|g| g.one_offs).sum::<usize>();
+        ambiguous as f64 / total.max(1) as f64
+    }
+}
+
+/// Calculates the reverse complement of a sequence
+fn reverse_complement(sequence: &[u8]) -> Vec<u8> {
+    sequence
+        .iter()
+        .rev()
+        .map(|b| match b {
+            b'A' => b'T',
+            b'C' => b'G',
+            b'G' => b'C',
+            b'T' => b'A',
+            b'a' => b't',
+            b'c' => b'g',
+            b'g' => b'c',
+            b't' => b'a',
+            x => *x,
+        })
+        .collect()
+}
+
+/// Calculates the length of the longest run of a single base
+fn homopolymer(sequence: &[u8]) -> usize {
+    sequence
+        .chunk_by(|a, b| a.eq_ignore_ascii_case(b))
+        .map(<[u8]>::len)
+        .max()
+        .unwrap_or(0)
+}
+
+/// Calculates the fraction of G and C bases
+fn gc_content(sequence: &[u8]) -> f64 {
+    let gc = sequence
+        .iter()
+        .filter(|b| matches!(b.to_ascii_uppercase(), b'G' | b'C'))
+        .count();
+    gc as f64 / sequence.len().max(1) as f64
+}
+
+#[cfg(test)]
+mod testing {
+    use super::{gc_content, homopolymer, reverse_complement, LibraryDesign};
+    use crate::Library;
+    use hashbrown::HashMap;
+
+    fn build_library() -> Library {
+        let table = HashMap::from([
+            (b"ACGTAC".to_vec(), b"g1".to_vec()),
+            (b"ACGTAA".to_vec(), b"g2".to_vec()),
+            (b"ACGTTT".to_vec(), b"g3".to_vec()),
+            (b"GTACGT".to_vec(), b"g4".to_vec()),
+            (b"GGGGCC".to_vec(), b"g5".to_vec()),
+        ]);
+        Library::from_hashmap(table).unwrap()
+    }
+
+    #[test]
+    fn test_sequence_composition() {
+        assert_eq!(reverse_complement(b"AACGT"), b"ACGTT");
+        assert_eq!(homopolymer(b"ACGGGTA"), 3);
+        assert_eq!(homopolymer(b""), 0);
+        assert_eq!(gc_content(b"GGCA"), 0.75);
+    }
+
+    #[test]
+    fn test_library_design() {
+        let design = LibraryDesign::new(&build_library());
+        let guides = design.guides();
+        assert_eq!(guides[0].alias(), b"g1");
+
+        // g1 and g2 differ by a single base and g1 and g3 by two
+        assert_eq!(guides[0].neighbours(1), 1);
+        assert_eq!(guides[0].neighbours(2), 1);
+        assert_eq!(guides[0].nearest(), Some((b"g2".as_slice(), 1)));
+        assert_eq!(design.pairs(1), 1);
+        assert_eq!(design.pairs(2), 2);
+
+        // g1 and g2 share every one-off at the differing position (including g2
+        // itself) and g1 and g3 share the one-off ACGTTC
+        assert_eq!(guides[0].one_offs(), 24);
+        assert_eq!(guides[0].ambiguous_one_offs(), 5);
+        assert_eq!(guides[0].ambiguous_fraction(), 5.0 / 24.0);
+        assert_eq!(guides[4].ambiguous_one_offs(), 0);
+        assert_eq!(guides[4].nearest(), None);
+
+        // g1 (ACGTAC) is the reverse complement of g4 (GTACGT)
+        assert_eq!(guides[0].reverse_complement(), Some(b"g4".as_slice()));
+        assert_eq!(guides[3].reverse_complement(), Some(b"g1".as_slice()));
+        assert_eq!(design.reverse_complement_collisions(), 2);
+        assert_eq!(guides[4].homopolymer(), 4);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::Library;
+use crate::{DualLibrary, Library};
 use anyhow::{anyhow, Result};
 use bstr::{io::BufReadExt, ByteSlice};
 use hashbrown::HashMap;
@@ -67,6 +67,30 @@ impl GeneMap {
         Ok(map)
     }
 
+    /// Adds the genes of merged library aliases (i.e. `g1|g2` of a sequence shared by
+    /// several ids) which are missing from the map. The gene of a merged alias is the
+    /// gene of its ids if they share one, otherwise their unique genes merged alike.
+    #[must_use]
+    pub fn with_merged_aliases<'a>(&self, aliases: impl Iterator<Item = &'a Vec<u8>>) -> Self {
+        let mut map = self.map.clone();
+        for alias in aliases.filter(|a| a.contains(&b'|') && !self.map.contains_key(*a)) {
+            let genes = alias
+                .split(|b| *b == b'|')
+                .map(|id| self.map.get(id))
+                .collect::<Option<Vec<_>>>();
+            if let Some(genes) = genes {
+                let mut unique: Vec<&[u8]> = Vec::new();
+                for gene in genes {
+                    if !unique.contains(&gene.as_slice()) {
+                        unique.push(gene);
+                    }
+                }
+                map.insert(alias.clone(), unique.join(&b'|'));
+            }
+        }
+        Self { map }
+    }
+
     /// Gets the associated gene for a provided `sgRNA`
     #[must_use]
     pub fn get(&self, sgrna: &[u8]) -> Option<&Vec<u8>> {
@@ -79,8 +103,21 @@ impl GeneMap {
     /// If all aliases are found, returns None, otherwise returns the first alias that is not found
     #[must_use]
     pub fn missing_aliases(&self, library: &Library) -> Option<Vec<u8>> {
-        library
-            .values()
+        self.first_missing(library.values())
+    }
+
+    /// Validates that all construct aliases found within the dual-guide library
+    /// have an associated gene within this gene map
+    ///
+    /// If all aliases are found, returns None, otherwise returns the first alias that is not found
+    #[must_use]
+    pub fn missing_constructs(&self, library: &DualLibrary) -> Option<Vec<u8>> {
+        self.first_missing(library.aliases())
+    }
+
+    /// Returns the first alias without an associated gene
+    fn first_missing<'a>(&self, mut aliases: impl Iterator<Item = &'a Vec<u8>>) -> Option<Vec<u8>> {
+        aliases
             .find(|alias| self.get(alias).is_none())
             .map(|alias| alias.to_vec())
     }
@@ -147,6 +184,21 @@ mod testing {
         assert_eq!(missing.unwrap(), b"sgrna4");
     }
 
+    #[test]
+    fn test_merged_aliases() {
+        let buffer = build_example_buffer();
+        let genemap = super::GeneMap::new_from_buffer(buffer.as_bytes()).unwrap();
+        let aliases = [
+            b"sgrna1|sgrna2".to_vec(),
+            b"sgrna1|sgrna1".to_vec(),
+            b"sgrna1|sgrna4".to_vec(),
+        ];
+        let merged = genemap.with_merged_aliases(aliases.iter());
+        assert_eq!(merged.get(b"sgrna1|sgrna2").unwrap(), &b"gene1|gene2"[..]);
+        assert_eq!(merged.get(b"sgrna1|sgrna1").unwrap(), &b"gene1"[..]);
+        assert_eq!(merged.get(b"sgrna1|sgrna4"), None);
+    }
+
     #[test]
     fn test_from_file() {
         let filepath = "example/g2s.txt";
@@ -1,31 +1,53 @@
-use anyhow;
-use anyhow::Result;
+use anyhow::{bail, Result};
+use clap::ValueEnum;
 use fxread::{FastxRead, Record};
-use hashbrown::HashMap;
+use hashbrown::{HashMap, HashSet};
 
 type FxReader = Box<dyn FastxRead<Item = Record>>;
 
+/// Describes how duplicate sequences (the same sequence under several ids)
+/// and duplicate aliases (the same id on several sequences) of a library are handled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum DuplicatePolicy {
+    /// Fails with the full list of duplicates
+    #[default]
+    Error,
+    /// Keeps the first record of each duplicate and skips the rest
+    First,
+    /// Merges the ids of a shared sequence into a combined alias (i.e. `g1|g2`)
+    /// and counts sequences sharing an id together
+    Merge,
+}
+
 /// Container for input library sequences.
+///
+/// Sequences may be of several lengths (i.e. a mix of 19, 20, and 21-mers) in
+/// which case they are grouped into length buckets that are matched independently.
 pub struct Library {
     table: HashMap<Vec<u8>, Vec<u8>>,
-    size: usize,
+    sizes: Vec<usize>,
 }
 impl Library {
     /// Creates a library from a [`fxread::FastxRead`] capable object.
-    /// Reads the records from iterator then confirms that all values
-    /// are of equivalent size.
+    /// Reads the records from iterator then groups the values into their
+    /// length buckets. Any duplicate sequence or alias is an error.
     pub fn from_reader(reader: FxReader) -> Result<Self> {
-        let table = Self::table_from_reader(reader);
-        let size = Self::calculate_base_size(&table)?;
-        Ok(Self { table, size })
+        Self::from_reader_with(reader, DuplicatePolicy::Error)
+    }
+
+    /// Creates a library from a [`fxread::FastxRead`] capable object, handling
+    /// duplicate sequences and aliases with the provided [`DuplicatePolicy`]
+    pub fn from_reader_with(reader: FxReader, duplicates: DuplicatePolicy) -> Result<Self> {
+        let table = Self::table_from_reader(reader, duplicates)?;
+        let sizes = Self::calculate_sizes(&table)?;
+        Ok(Self { table, sizes })
     }
 
     /// Creates a library from a [`HashMap`] of sequences and aliases.
-    /// Confirms that all values are of equivalent size.
     /// Used for testing
     pub fn from_hashmap(table: HashMap<Vec<u8>, Vec<u8>>) -> Result<Self> {
-        let size = Self::calculate_base_size(&table)?;
-        Ok(Self { table, size })
+        let sizes = Self::calculate_sizes(&table)?;
+        Ok(Self { table, sizes })
     }
 
     /// Publically exposes the internal [`HashMap`] and returns
@@ -50,59 +72,285 @@ impl Library {
         self.table.keys()
     }
 
-    /// An iteratory over the aliases within the library
+    /// An iteratory over the unique aliases within the library
     pub fn values(&self) -> impl Iterator<Item = &Vec<u8>> {
-        self.table.values()
+        let mut seen = HashSet::new();
+        self.table.values().filter(move |x| seen.insert(*x))
     }
 
-    /// The unique sequence size of all elements within the library
+    /// The sequence size of the longest elements within the library
     #[must_use]
     pub fn size(&self) -> usize {
-        self.size
+        self.sizes[0]
+    }
+
+    /// The sequence size of the shortest elements within the library
+    #[must_use]
+    pub fn min_size(&self) -> usize {
+        self.sizes[self.sizes.len() - 1]
     }
 
-    /// Validates that all sequences are of equivalent length
-    fn validate_unique_size<'a>(keys: impl Iterator<Item = &'a Vec<u8>>) -> bool {
-        keys.collect::<Vec<&Vec<u8>>>()
-            .windows(2)
-            .map(|x| (x[0], x[1]))
-            .all(|(x, y)| x.len() == y.len())
+    /// The unique sequence sizes of the length buckets within the library
+    /// ordered from longest to shortest
+    #[must_use]
+    pub fn sizes(&self) -> &[usize] {
+        &self.sizes
     }
 
-    /// Returns the basepair size of one of the sequences
-    fn get_key_size(table: &HashMap<Vec<u8>, Vec<u8>>) -> usize {
-        table.keys().next().unwrap().len()
+    /// An iterator over the sequences of a single length bucket
+    pub fn bucket(&self, size: usize) -> impl Iterator<Item = &Vec<u8>> {
+        self.keys().filter(move |x| x.len() == size)
     }
 
-    /// Validates that all sequences are of equivalent length and returns
-    /// that length
-    fn calculate_base_size(table: &HashMap<Vec<u8>, Vec<u8>>) -> Result<usize> {
-        if Self::validate_unique_size(table.keys()) {
-            Ok(Self::get_key_size(table))
-        } else {
-            Err(anyhow::anyhow!("Library sequence sizes are inconsistent"))
+    /// Validates that the library is not empty and returns the unique
+    /// sequence lengths from longest to shortest
+    fn calculate_sizes(table: &HashMap<Vec<u8>, Vec<u8>>) -> Result<Vec<usize>> {
+        if table.is_empty() {
+            bail!("Library is empty")
+        }
+        if table.keys().any(Vec::is_empty) {
+            bail!("Library contains an empty sequence")
         }
+        let mut sizes = table
+            .keys()
+            .map(Vec::len)
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        sizes.sort_unstable_by(|x, y| y.cmp(x));
+        Ok(sizes)
     }
 
     /// Main init iterator which reads in all sequences fromthe reader and
-    /// imports them into the internal [`HashMap`]
-    fn table_from_reader(reader: FxReader) -> HashMap<Vec<u8>, Vec<u8>> {
-        reader.into_iter().fold(HashMap::new(), |mut map, x| {
-            match map.insert(x.seq().to_owned(), x.id().to_owned()) {
-                Some(_) => panic!(
-                    "Unexpected duplicate sequence in library found: {}",
-                    std::str::from_utf8(x.seq()).unwrap()
-                ),
-                None => map,
+    /// imports them into the internal [`HashMap`], handling duplicates with
+    /// the provided [`DuplicatePolicy`]
+    fn table_from_reader(
+        reader: FxReader,
+        duplicates: DuplicatePolicy,
+    ) -> Result<HashMap<Vec<u8>, Vec<u8>>> {
+        if duplicates == DuplicatePolicy::Merge {
+            return Ok(Self::merged_table_from_reader(reader));
+        }
+        let mut table: HashMap<Vec<u8>, Vec<u8>> = HashMap::new();
+        let mut aliases = HashSet::new();
+        let mut shared_sequences: Vec<(Vec<u8>, Vec<Vec<u8>>)> = Vec::new();
+        let mut shared_aliases = Vec::new();
+        let mut skipped = 0;
+        for record in reader {
+            let (seq, id) = (record.seq(), record.id());
+            if let Some(existing) = table.get_mut(seq) {
+                match duplicates {
+                    DuplicatePolicy::Error => {
+                        match shared_sequences.iter_mut().find(|(s, _)| s == seq) {
+                            Some((_, ids)) => ids.push(id.to_vec()),
+                            None => shared_sequences
+                                .push((seq.to_vec(), vec![existing.clone(), id.to_vec()])),
+                        }
+                    }
+                    _ => skipped += 1,
+                }
+                continue;
+            }
+            if !aliases.insert(id.to_vec()) {
+                match duplicates {
+                    DuplicatePolicy::Error => shared_aliases.push(id.to_vec()),
+                    _ => {
+                        skipped += 1;
+                        continue;
+                    }
+                }
+            }
+            table.insert(seq.to_vec(), id.to_vec());
+        }
+
+        if !shared_sequences.is_empty() || !shared_aliases.is_empty() {
+            let mut message = String::from("Unexpected duplicates in library found:");
+            for (seq, ids) in &shared_sequences {
+                let ids = ids
+                    .iter()
+                    .map(|x| String::from_utf8_lossy(x))
+                    .collect::<Vec<_>>();
+                message.push_str(&format!(
+                    "\n  sequence {} shared by: {}",
+                    String::from_utf8_lossy(seq),
+                    ids.join(", ")
+                ));
             }
+            for id in &shared_aliases {
+                message.push_str(&format!(
+                    "\n  id {} used by multiple sequences",
+                    String::from_utf8_lossy(id)
+                ));
+            }
+            message.push_str("\n\nConsider keeping the first of each duplicate or merging them (--duplicates first|merge)");
+            bail!(message)
+        }
+        if skipped > 0 {
+            eprintln!(
+                "WARNING: Skipped {} duplicate library records (keeping the first of each)",
+                skipped
+            );
+        }
+        Ok(table)
+    }
+
+    /// Reads all sequences from the reader into the internal [`HashMap`], merging
+    /// the ids of a shared sequence into a combined alias (i.e. `g1|g2`). Sequences
+    /// sharing an id with any part of a combined alias are counted under it as well.
+    fn merged_table_from_reader(reader: FxReader) -> HashMap<Vec<u8>, Vec<u8>> {
+        let mut table: HashMap<Vec<u8>, Vec<u8>> = HashMap::new();
+        // the combined alias of each id and the sequences of each combined alias
+        let mut groups: HashMap<Vec<u8>, Vec<u8>> = HashMap::new();
+        let mut members: HashMap<Vec<u8>, Vec<Vec<u8>>> = HashMap::new();
+        for record in reader {
+            let (seq, id) = (record.seq().to_vec(), record.id().to_vec());
+            let id_alias = groups.get(&id).cloned().unwrap_or(id);
+            let merged = match table.get(&seq) {
+                Some(existing) if *existing == id_alias => continue,
+                Some(existing) => vec![existing.clone(), id_alias],
+                None => vec![id_alias],
+            };
+            let alias = merged.join(&b'|');
+            let mut sequences = vec![seq];
+            for old in &merged {
+                sequences.extend(members.remove(old).unwrap_or_default());
+            }
+            for sequence in &sequences {
+                table.insert(sequence.clone(), alias.clone());
+            }
+            for id in alias.split(|b| *b == b'|') {
+                groups.insert(id.to_vec(), alias.clone());
+            }
+            members.insert(alias, sequences);
+        }
+        table
+    }
+}
+
+/// Container for dual-guide constructs where each construct is identified
+/// by a pair of sequences read from two separate positions.
+///
+/// Each position is held in its own [`Library`] whose aliases are the
+/// sequences themselves (a guide may be shared across many constructs),
+/// and constructs are recovered from the pair of matched sequences.
+pub struct DualLibrary {
+    first: Library,
+    second: Library,
+    constructs: HashMap<Vec<u8>, HashMap<Vec<u8>, Vec<u8>>>,
+    aliases: Vec<Vec<u8>>,
+}
+impl DualLibrary {
+    /// Creates a dual-guide library from two [`fxread::FastxRead`] capable objects.
+    /// Records are paired by their sequence id, and every id must be found exactly
+    /// once in each reader.
+    pub fn from_readers(first: FxReader, second: FxReader) -> Result<Self> {
+        let first_records = Self::records_from_reader(first)?;
+        let mut second_records = Self::records_from_reader(second)?
+            .into_iter()
+            .collect::<HashMap<Vec<u8>, Vec<u8>>>();
+
+        let mut constructs: HashMap<Vec<u8>, HashMap<Vec<u8>, Vec<u8>>> = HashMap::new();
+        let mut aliases = Vec::with_capacity(first_records.len());
+        for (alias, seq) in first_records {
+            let Some(mate_seq) = second_records.remove(&alias) else {
+                bail!(
+                    "Construct missing from second guide library: {}",
+                    std::str::from_utf8(&alias)?
+                )
+            };
+            if let Some(existing) = constructs
+                .entry(seq)
+                .or_default()
+                .insert(mate_seq, alias.clone())
+            {
+                bail!(
+                    "Unexpected duplicate guide pair in library found: {} and {}",
+                    std::str::from_utf8(&existing)?,
+                    std::str::from_utf8(&alias)?
+                )
+            }
+            aliases.push(alias);
+        }
+        if let Some(alias) = second_records.keys().next() {
+            bail!(
+                "Construct missing from first guide library: {}",
+                std::str::from_utf8(alias)?
+            )
+        }
+
+        let first = Library::from_hashmap(Self::position_table(constructs.keys()))?;
+        let second = Library::from_hashmap(Self::position_table(
+            constructs.values().flat_map(|x| x.keys()),
+        ))?;
+        Ok(Self {
+            first,
+            second,
+            constructs,
+            aliases,
         })
     }
+
+    /// Reads all `(id, sequence)` records from a reader, rejecting duplicate ids
+    fn records_from_reader(reader: FxReader) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut seen = HashSet::new();
+        let mut records = Vec::new();
+        for record in reader {
+            if !seen.insert(record.id().to_owned()) {
+                bail!(
+                    "Unexpected duplicate construct id in library found: {}",
+                    std::str::from_utf8(record.id())?
+                )
+            }
+            records.push((record.id().to_owned(), record.seq().to_owned()));
+        }
+        Ok(records)
+    }
+
+    /// Builds the table of unique sequences at a single position
+    fn position_table<'a>(
+        sequences: impl Iterator<Item = &'a Vec<u8>>,
+    ) -> HashMap<Vec<u8>, Vec<u8>> {
+        sequences.map(|x| (x.clone(), x.clone())).collect()
+    }
+
+    /// The library of unique sequences at the first guide position
+    #[must_use]
+    pub fn first(&self) -> &Library {
+        &self.first
+    }
+
+    /// The library of unique sequences at the second guide position
+    #[must_use]
+    pub fn second(&self) -> &Library {
+        &self.second
+    }
+
+    /// Returns the construct alias for a pair of guide sequences if it is
+    /// found within the design.
+    #[must_use]
+    pub fn construct(&self, first: &[u8], second: &[u8]) -> Option<&Vec<u8>> {
+        self.constructs.get(first)?.get(second)
+    }
+
+    /// An iterator over the construct aliases in the order they were provided
+    pub fn aliases(&self) -> impl Iterator<Item = &Vec<u8>> {
+        self.aliases.iter()
+    }
+
+    /// Builds the alias of a guide pair that is not found within the design
+    #[must_use]
+    pub fn recombined_alias(first: &[u8], second: &[u8]) -> Vec<u8> {
+        let mut alias = first.to_vec();
+        alias.push(b'_');
+        alias.extend_from_slice(second);
+        alias
+    }
 }
 
 #[cfg(test)]
 mod test {
 
-    use super::Library;
+    use super::{DualLibrary, DuplicatePolicy, Library};
     use fxread::{FastaReader, FastxRead, Record};
 
     fn reader() -> Box<dyn FastxRead<Item = Record>> {
@@ -129,9 +377,91 @@ mod test {
         assert_eq!(library.contains(b"ACTT"), None);
     }
 
+    fn dual_reader(sequence: &'static [u8]) -> Box<dyn FastxRead<Item = Record>> {
+        Box::new(FastaReader::new(sequence))
+    }
+
+    #[test]
+    fn build_dual() {
+        let library = DualLibrary::from_readers(
+            dual_reader(b">c.0\nACTG\n>c.1\nACTG\n"),
+            dual_reader(b">c.1\nGGCC\n>c.0\nTTAA\n"),
+        )
+        .unwrap();
+        assert_eq!(library.first().keys().count(), 1);
+        assert_eq!(library.second().keys().count(), 2);
+        assert_eq!(library.construct(b"ACTG", b"TTAA").unwrap(), b"c.0");
+        assert_eq!(library.construct(b"ACTG", b"GGCC").unwrap(), b"c.1");
+        assert_eq!(library.construct(b"GGCC", b"ACTG"), None);
+        assert_eq!(library.aliases().count(), 2);
+    }
+
+    #[test]
+    fn dual_missing_construct() {
+        let library = DualLibrary::from_readers(
+            dual_reader(b">c.0\nACTG\n>c.1\nACTG\n"),
+            dual_reader(b">c.0\nTTAA\n"),
+        );
+        assert!(library.is_err());
+    }
+
+    #[test]
+    fn dual_duplicate_pair() {
+        let library = DualLibrary::from_readers(
+            dual_reader(b">c.0\nACTG\n>c.1\nACTG\n"),
+            dual_reader(b">c.0\nTTAA\n>c.1\nTTAA\n"),
+        );
+        assert!(library.is_err());
+    }
+
+    #[test]
+    fn variable_sizes() {
+        let sequence: &'static [u8] = b">g1\nACTGA\n>g2\nACTG\n>g3\nTTTT\n>g4\nACTGAC\n";
+        let library = Library::from_reader(Box::new(FastaReader::new(sequence))).unwrap();
+        assert_eq!(library.size(), 6);
+        assert_eq!(library.sizes(), [6, 5, 4]);
+        assert_eq!(library.min_size(), 4);
+        assert_eq!(library.bucket(4).count(), 2);
+        assert_eq!(library.contains(b"ACTGA").unwrap(), b"g1");
+    }
+
     #[test]
-    #[should_panic]
     fn duplicates() {
-        Library::from_reader(duplicate_reader()).unwrap();
+        assert!(Library::from_reader(duplicate_reader()).is_err());
+    }
+
+    #[test]
+    fn duplicate_policies() {
+        let records: &'static [u8] = b">g1\nACTG\n>g2\nACTG\n>g3\nTTTT\n>g3\nGGGG\n";
+        let reader =
+            || -> Box<dyn FastxRead<Item = Record>> { Box::new(FastaReader::new(records)) };
+
+        let error = Library::from_reader(reader()).err().unwrap().to_string();
+        assert!(error.contains("ACTG shared by: g1, g2"));
+        assert!(error.contains("id g3"));
+
+        let library = Library::from_reader_with(reader(), DuplicatePolicy::First).unwrap();
+        assert_eq!(library.contains(b"ACTG").unwrap(), b"g1");
+        assert_eq!(library.contains(b"GGGG"), None);
+
+        let library = Library::from_reader_with(reader(), DuplicatePolicy::Merge).unwrap();
+        assert_eq!(library.contains(b"ACTG").unwrap(), b"g1|g2");
+        assert_eq!(library.contains(b"GGGG").unwrap(), b"g3");
+        assert_eq!(library.values().count(), 2);
+    }
+
+    #[test]
+    fn merged_ids_reused() {
+        let records: &'static [u8] =
+            b">g1\nACTG\n>g2\nACTG\n>g2\nCCCC\n>g3\nTTTT\n>g1\nTTTT\n>g4\nGGGG\n";
+        let library =
+            Library::from_reader_with(Box::new(FastaReader::new(records)), DuplicatePolicy::Merge)
+                .unwrap();
+        // the later reuses of g2 and g1 are counted under their combined alias
+        assert_eq!(library.contains(b"ACTG").unwrap(), b"g3|g1|g2");
+        assert_eq!(library.contains(b"CCCC").unwrap(), b"g3|g1|g2");
+        assert_eq!(library.contains(b"TTTT").unwrap(), b"g3|g1|g2");
+        assert_eq!(library.contains(b"GGGG").unwrap(), b"g4");
+        assert_eq!(library.values().count(), 2);
     }
 }
@@ -10,24 +10,60 @@
 #![warn(missing_docs)]
 use std::path::Path;
 
-use anyhow::Result;
-use clap::Parser;
+use anyhow::{bail, Result};
+use clap::{CommandFactory, Parser, Subcommand};
 
 /// Module for Sequence Library
 pub mod library;
 
+/// Module for Reading Delimited Libraries
+pub mod tabular;
+
+/// Module for Trimming Constant Library Regions
+pub mod trim;
+
+/// Module for Locating Guides with Flanking Anchor Sequences
+pub mod anchor;
+
+/// Module for Filtering Reads on the Base Qualities of the Guide
+pub mod quality;
+
+/// Module for Writing Unmapped Reads
+pub mod unmapped;
+
+/// Module for Writing Per-Read Assignments
+pub mod audit;
+
 /// Module for Matching Sequences Against a Library
 pub mod counter;
 
 /// Module for Handling Results
 pub mod results;
 
+/// Module for Reading and Merging Count Tables
+pub mod table;
+
+/// Module for Summarizing the Mapping and Count Distribution of Samples
+pub mod summary;
+
+/// Module for Library Design Quality Control
+pub mod design;
+
 /// Module for Unambiguous One-Off Sequence Generation
 pub mod permutes;
 
 /// Module for Determining Entropy Offset of Reads
 pub mod offsetter;
 
+/// Module for Subsampling Reads
+pub mod sampling;
+
+/// Module for Reading Sample Sheets
+pub mod sheet;
+
+/// Module for Rendering the HTML Report of a Run
+pub mod report;
+
 /// Module for Performing Individual Sample Counting
 pub mod count;
 
@@ -40,37 +76,270 @@ pub mod progress;
 /// Module for utilities in the library
 pub mod utils;
 
-pub use count::count;
-pub use counter::Counter;
+/// Module for Extracting and Deduplicating Unique Molecular Identifiers
+pub mod umi;
+
+/// Module for Counting Single-Cell Feature Barcode Reads
+pub mod cells;
+
+pub use anchor::Anchor;
+pub use audit::ReadLog;
+pub use cells::{CellBarcode, CellCounter, Whitelist};
+pub use count::{count, count_cells, count_dual, CountOutputs, Samples};
+pub use counter::{AssignmentOptions, Counter, MatchType, MateMode, MissReason, ReadSinks};
+pub use design::{GuideDesign, LibraryDesign};
 pub use fxread::initialize_reader;
 pub use genemap::GeneMap;
-pub use library::Library;
-use offsetter::entropy_offset_group;
-pub use offsetter::{entropy_offset, Offset};
+pub use library::{DualLibrary, DuplicatePolicy, Library};
+pub use offsetter::{entropy_offset, Offset, OffsetDiagnostics, OffsetValidation};
+use offsetter::{
+    entropy_offset_diagnostics, mixed_offset_group, trial_offset_group, validated_offset_group,
+};
 pub use permutes::Permuter;
 use progress::{finish_progress_bar, initialize_progress_bar, start_progress_bar};
-use utils::{generate_sample_names, set_threads};
+pub use quality::QualityFilter;
+pub use report::Report;
+use results::{write_library_design, write_offset_diagnostics, write_offsets, write_summary};
+pub use sampling::Subsample;
+pub use sheet::{Metadata, SampleSheet};
+pub use summary::SampleSummary;
+pub use table::CountTable;
+pub use tabular::{Column, LibraryFormat, LibraryTable};
+pub use trim::ConstantRegions;
+pub use umi::Umi;
+pub use unmapped::{Unmapped, UnmappedOutput};
+use utils::{generate_sample_names, set_threads, vec_to_nuc};
 
 #[derive(Parser, Debug)]
-#[clap(author, version, about, long_about = None)]
-struct Args {
-    /// Filepath of the library
+#[clap(
+    author,
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+struct Cli {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    /// Arguments of the default count command
+    #[clap(flatten)]
+    count: Option<CountArgs>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Count the sgRNAs of each sample (the default if no command is provided)
+    Count(Box<CountArgs>),
+
+    /// Summarize the guide count distribution of each sample in a count table
+    Qc(QcArgs),
+
+    /// Detect the adapter offset of each sample
+    Offset(OffsetArgs),
+
+    /// Inspect a library
+    Library {
+        #[clap(subcommand)]
+        command: LibraryCommand,
+    },
+
+    /// Combine count tables into a single table
+    Merge(MergeArgs),
+}
+
+#[derive(Subcommand, Debug)]
+enum LibraryCommand {
+    /// Validate a library (and optionally its gene map)
+    Check(LibraryCheckArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct QcArgs {
+    /// Filepath of the count table
+    #[clap(short, long, value_parser)]
+    input_path: String,
+
+    /// Path prefix of the JSON and TSV summaries (`{prefix}.summary.json`)
+    #[clap(short, long, value_parser)]
+    output_prefix: String,
+}
+
+#[derive(clap::Args, Debug)]
+struct OffsetArgs {
+    /// Filepath of the library (fastx, or a delimited *.csv / *.tsv table)
     #[clap(short, long, value_parser)]
     library_path: String,
 
-    /// Filepath(s) of fastx (fastq, fasta, *.gz) sequences to map
+    /// Options describing how the library is read
+    #[command(flatten)]
+    library: LibraryArgs,
+
+    /// Filepath(s) of fastx (fastq, fasta, *.gz) sequences
     #[clap(short, long, value_parser, required = true, num_args = 1..)]
     input_paths: Vec<String>,
 
     /// Sample Names
-    #[clap(short = 'n', long, value_parser, required = false, num_args = 1..)]
+    #[clap(short = 'n', long, value_parser, num_args = 1..)]
     sample_names: Option<Vec<String>>,
 
     /// Output filepath [default: stdout]
     #[clap(short, long, value_parser)]
     output_path: Option<String>,
 
-    /// Gene to sgRNA mapping
+    /// Number of reads to subsample for offset estimation
+    #[clap(short = 's', long, default_value = "5000")]
+    subsample: usize,
+
+    /// Subsample reads uniformly at random with reservoir sampling instead of
+    /// taking the first reads of each file
+    #[clap(long)]
+    reservoir: bool,
+
+    /// Only reservoir sample from the first number of reads of each file [default: whole file]
+    #[clap(long, requires = "reservoir")]
+    subsample_window: Option<usize>,
+
+    /// Seed of the reservoir sampling
+    #[clap(long, default_value = "42", requires = "reservoir")]
+    seed: u64,
+
+    /// Number of candidate offsets of lowest entropy MSE validated by trial mapping
+    #[clap(short = 'c', long, default_value = "3")]
+    candidates: usize,
+
+    /// Filepath to write the library and sample positional entropy vectors and the
+    /// forward and reverse windowed MSE curves of every sample
+    #[clap(short = 'd', long, value_parser)]
+    diagnostics: Option<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct LibraryCheckArgs {
+    /// Filepath of the library (fastx, or a delimited *.csv / *.tsv table)
+    #[clap(short, long, value_parser)]
+    library_path: String,
+
+    /// Options describing how the library is read
+    #[command(flatten)]
+    library: LibraryArgs,
+
+    /// Gene to sgRNA mapping to validate against the library
+    #[clap(short, long, value_parser)]
+    genemap: Option<String>,
+
+    /// Filepath to write the per-guide design report (hamming distance neighbours,
+    /// ambiguous one-offs, reverse complement collisions, homopolymers, GC content)
+    #[clap(short, long, value_parser)]
+    output_path: Option<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct LibraryArgs {
+    /// Column of the sgRNA ids in a delimited library, by header name or 1-based
+    /// position [default: id or the first column]
+    #[clap(long, value_parser)]
+    id_column: Option<Column>,
+
+    /// Column of the sequences in a delimited library [default: sequence or the second column]
+    #[clap(long, value_parser)]
+    sequence_column: Option<Column>,
+
+    /// Column of the genes in a delimited library [default: gene or the third column]
+    #[clap(long, value_parser)]
+    gene_column: Option<Column>,
+
+    /// Handling of duplicate library sequences and ids (dual-guide libraries
+    /// must not hold duplicates)
+    #[clap(long, value_enum, default_value = "error")]
+    duplicates: DuplicatePolicy,
+
+    /// Strip the constant prefix and suffix shared by all library sequences
+    /// (i.e. the scaffold of full oligo sequences) leaving their variable regions
+    #[clap(long)]
+    trim_library: bool,
+}
+impl LibraryArgs {
+    /// Describes how the columns of a delimited library are read
+    fn library_format(&self) -> LibraryFormat {
+        LibraryFormat::new(
+            self.id_column.clone(),
+            self.sequence_column.clone(),
+            self.gene_column.clone(),
+            self.duplicates,
+            self.trim_library,
+        )
+    }
+}
+
+#[derive(clap::Args, Debug)]
+struct MergeArgs {
+    /// Filepaths of the count tables
+    #[clap(short, long, value_parser, required = true, num_args = 1..)]
+    input_paths: Vec<String>,
+
+    /// Output filepath [default: stdout]
+    #[clap(short, long, value_parser)]
+    output_path: Option<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct CountArgs {
+    /// Filepath of the library (fastx, or a delimited *.csv / *.tsv table)
+    // clap leaves the group of args with a flattened field empty, so the library
+    // path is what marks the default count command as present
+    #[clap(short, long, value_parser, group = "CountArgs")]
+    library_path: String,
+
+    /// Options describing how the library is read
+    #[command(flatten)]
+    library: LibraryArgs,
+
+    /// Filepath of the library for the second guide position of dual-guide constructs
+    /// (sequence ids must match the library)
+    #[clap(short = 'L', long, value_parser)]
+    dual_library_path: Option<String>,
+
+    /// Filepath(s) of fastx (fastq, fasta, *.gz) sequences to map
+    #[clap(
+        short,
+        long,
+        value_parser,
+        required_unless_present = "sample_sheet",
+        num_args = 1..
+    )]
+    input_paths: Vec<String>,
+
+    /// Filepath(s) of paired mates (R2) in the same order as the input paths
+    #[clap(short = 'I', long, value_parser, num_args = 1..)]
+    mate_paths: Option<Vec<String>>,
+
+    /// Filepath of a tab (or comma if *.csv) delimited sample sheet with a header
+    /// of `name`, `R1`, and optionally `R2`, `offset`, `direction`, `mate_offset`,
+    /// `mate_direction`, and metadata columns which are carried into the summary tables.
+    /// Single and paired samples cannot be mixed: `R2` is provided for every sample or none
+    #[clap(
+        short = 'X',
+        long,
+        value_parser,
+        conflicts_with_all = ["input_paths", "mate_paths", "sample_names"]
+    )]
+    sample_sheet: Option<String>,
+
+    /// Strategy for assigning read pairs when mate paths are provided
+    #[clap(short = 'm', long, value_enum, default_value = "either")]
+    mate_mode: MateMode,
+
+    /// Sample Names
+    #[clap(short = 'n', long, value_parser, required = false, num_args = 1..)]
+    sample_names: Option<Vec<String>>,
+
+    /// Output filepath [default: stdout] (output directory in single-cell mode)
+    #[clap(short, long, value_parser)]
+    output_path: Option<String>,
+
+    /// Gene to sgRNA mapping [default: the gene column of a delimited library]
     #[clap(short, long, value_parser)]
     genemap: Option<String>,
 
@@ -78,6 +347,46 @@ struct Args {
     #[clap(short = 'a', long, value_parser)]
     offset: Option<usize>,
 
+    /// Adapter Offset of the paired mates (R2) [default: detected independently of
+    /// the first mates]
+    #[clap(long, value_parser)]
+    mate_offset: Option<usize>,
+
+    /// Read Direction of the paired mates when their offset (or the dual offset) is
+    /// provided (reverse complement mate reads)
+    #[clap(long)]
+    mate_reverse: bool,
+
+    /// Adapter Offset of the second guide position
+    #[clap(short = 'A', long, value_parser)]
+    dual_offset: Option<usize>,
+
+    /// Include counts of recombined guide pairs not found in the dual-guide design
+    #[clap(short = 'R', long)]
+    recombined: bool,
+
+    /// Constant sequence directly upstream of the sgRNA, used to locate the sgRNA
+    /// within each read (falls back to the adapter offset when not found)
+    #[clap(short = 'f', long, value_parser, conflicts_with = "dual_library_path")]
+    anchor: Option<String>,
+
+    /// Constant sequence directly downstream of the sgRNA, used when the upstream anchor is not found
+    #[clap(short = 'F', long, value_parser, requires = "anchor")]
+    downstream_anchor: Option<String>,
+
+    /// Maximum number of mismatches allowed when locating the anchor sequences
+    #[clap(long, default_value = "1", requires = "anchor")]
+    anchor_mismatches: usize,
+
+    /// Locate the sgRNA within each read using the constant regions stripped from the
+    /// library as anchors
+    #[clap(
+        long,
+        requires = "trim_library",
+        conflicts_with_all = ["anchor", "dual_library_path"]
+    )]
+    trim_anchors: bool,
+
     /// Remove Position Recursion (i.e. offseting sequences by +/- 1 on mismatch condition)
     #[clap(short = 'p', long)]
     no_position_recursion: bool,
@@ -90,14 +399,142 @@ struct Args {
     #[clap(short = 'x', long)]
     exact: bool,
 
+    /// Maximum number of mismatches allowed between a read and its assigned sgRNA
+    #[clap(short = 'M', long, default_value = "1", conflicts_with = "exact")]
+    max_mismatches: usize,
+
+    /// Allow a single insertion or deletion within the sgRNA (i.e. oligo synthesis errors)
+    #[clap(short = 'e', long)]
+    indels: bool,
+
+    /// Reject reads with any base of the sgRNA window below this Phred quality
+    #[clap(short = 'Q', long, value_parser)]
+    min_quality: Option<u8>,
+
+    /// Reject reads whose sgRNA window has a mean Phred quality below this threshold
+    #[clap(long, value_parser)]
+    mean_quality: Option<f64>,
+
+    /// Only allow mismatches at bases of the sgRNA window below this Phred quality
+    #[clap(long, value_parser)]
+    mismatch_quality: Option<u8>,
+
+    /// Positional offset of the UMI within each read (as sequenced)
+    #[clap(short = 'u', long, value_parser, requires = "umi_size")]
+    umi_offset: Option<usize>,
+
+    /// Length of the UMI within each read
+    #[clap(short = 'U', long, value_parser, requires = "umi_offset")]
+    umi_size: Option<usize>,
+
+    /// Collapse UMIs within a single mismatch of a more abundant UMI of the same guide
+    #[clap(short = 'c', long, requires = "umi_offset")]
+    umi_collapse: bool,
+
+    /// Length of the cell barcode within each read, enables single-cell mode where
+    /// the cell barcode and UMI are read from the input paths and the guide from the mates.
+    /// A sparse cell-by-guide matrix is written for each sample into the output directory
+    /// (the mates are provided with `-I` or the `R2` column of the sample sheet)
+    #[clap(short = 'b', long, value_parser, requires = "output_path")]
+    cell_size: Option<usize>,
+
+    /// Positional offset of the cell barcode within each read
+    #[clap(short = 'B', long, value_parser, default_value = "0")]
+    cell_offset: usize,
+
+    /// Filepath of the cell barcode whitelist (one barcode per line).
+    /// Barcodes not found are corrected to an unambiguous one-off whitelisted barcode.
+    #[clap(short = 'w', long, value_parser, requires = "cell_size")]
+    whitelist: Option<String>,
+
+    /// Detect every significant offset within each sample (i.e. pooled staggered primers)
+    /// by trial matching reads against the library. Offsets are tried in order of frequency
+    #[clap(short = 'S', long, conflicts_with_all = ["offset", "dual_library_path", "cell_size"])]
+    stagger: bool,
+
+    /// Minimum fraction of subsampled reads matching at an offset for it to be considered significant
+    #[clap(long, default_value = "0.05", requires = "stagger")]
+    min_offset_fraction: f64,
+
+    /// Filepath to write the number of reads assigned at each offset for every sample
+    #[clap(long, value_parser)]
+    offset_summary: Option<String>,
+
+    /// Filepath to write the number of reads of every sample by match type (exact,
+    /// mismatch, shifted position, indel) and miss reason (low quality, out of bounds,
+    /// ambiguous, no match)
+    #[clap(long, value_parser, conflicts_with = "cell_size")]
+    qc_summary: Option<String>,
+
+    /// Path prefix of the JSON and TSV run summaries (`{prefix}.summary.json`).
+    /// Defaults to the output path, so summaries are written next to the count table
+    /// (i.e. `counts.tsv.summary.json`)
+    #[clap(long, value_parser, conflicts_with = "cell_size")]
+    summary: Option<String>,
+
+    /// Filepath to write a self-contained HTML report of the mapping rates, guide count
+    /// distributions, positional entropy profiles, offsets, and replicate correlations
+    #[clap(long, value_parser, conflicts_with_all = ["dual_library_path", "cell_size"])]
+    report: Option<String>,
+
+    /// Directory to write the unmapped reads of each sample into, alongside a ranked
+    /// table of their most frequent sgRNA window sequences
+    #[clap(long, value_parser, conflicts_with_all = ["dual_library_path", "cell_size"])]
+    unmapped: Option<String>,
+
+    /// Only write the extracted sgRNA windows of the unmapped reads
+    #[clap(long, requires = "unmapped")]
+    unmapped_windows: bool,
+
+    /// Number of most frequent unmapped sgRNA window sequences reported per sample
+    #[clap(long, default_value = "20", requires = "unmapped")]
+    top_unmapped: usize,
+
+    /// Filepath to stream the assignment of every read to (read, sample, guide, match type,
+    /// offset, and orientation)
+    #[clap(long, value_parser, conflicts_with_all = ["dual_library_path", "cell_size"])]
+    assignments: Option<String>,
+
     /// Number of Reads to Subsample in Determining Offset [default: 5000]
     #[clap(short = 's', long)]
     subsample: Option<usize>,
 
+    /// Subsample reads uniformly at random with reservoir sampling instead of
+    /// taking the first reads of each file
+    #[clap(long)]
+    reservoir: bool,
+
+    /// Only reservoir sample from the first number of reads of each file [default: whole file]
+    #[clap(long, requires = "reservoir")]
+    subsample_window: Option<usize>,
+
+    /// Seed of the reservoir sampling
+    #[clap(long, default_value = "42", requires = "reservoir")]
+    seed: u64,
+
     /// Number of Threads to Use for Parallel Jobs
     #[clap(short = 't', long, default_value = "1")]
     threads: usize,
 
+    /// Reads are in mixed orientations; every read is matched in both the forward
+    /// and reverse complement directions
+    #[clap(short = 'O', long, conflicts_with_all = ["dual_library_path", "cell_size"])]
+    mixed_orientation: bool,
+
+    /// Number of candidate offsets of lowest entropy MSE validated by trial mapping
+    /// the subsampled reads against the library
+    #[clap(long, default_value = "3")]
+    offset_candidates: usize,
+
+    /// Minimum fraction of subsampled reads exactly matching the library at the
+    /// detected offset, below which a warning is shown
+    #[clap(long, default_value = "0.2")]
+    min_offset_rate: f64,
+
+    /// Fail instead of warning when the detected offset is below the minimum match rate
+    #[clap(long)]
+    strict_offset: bool,
+
     /// Does not show progress
     #[clap(short = 'q', long)]
     quiet: bool,
@@ -107,97 +544,601 @@ struct Args {
     include_zero: bool,
 }
 
+/// Describes how reads are subsampled for offset detection
+fn subsampling(size: usize, reservoir: bool, window: Option<usize>, seed: u64) -> Subsample {
+    if reservoir {
+        Subsample::reservoir(size, window, seed)
+    } else {
+        Subsample::head(size)
+    }
+}
+
+impl CountArgs {
+    /// Describes how reads are subsampled for offset detection
+    fn subsampling(&self) -> Subsample {
+        subsampling(
+            self.subsample.unwrap_or(5000),
+            self.reservoir,
+            self.subsample_window,
+            self.seed,
+        )
+    }
+
+    /// Describes how detected offsets are validated
+    fn offset_validation(&self) -> OffsetValidation {
+        OffsetValidation::new(
+            self.offset_candidates,
+            self.min_offset_rate,
+            self.strict_offset,
+        )
+    }
+}
+
 /// Calculates Offset if Required
 fn calculate_offset(
-    library_path: &str,
+    table: &LibraryTable,
     input_paths: &[String],
-    subsample: Option<usize>,
+    subsample: &Subsample,
+    validation: &OffsetValidation,
     quiet: bool,
 ) -> Result<Vec<Offset>> {
-    let subsample = subsample.unwrap_or(5000);
     let pb = if quiet {
         None
     } else {
         Some(initialize_progress_bar())
     };
     start_progress_bar(&pb, "Calculating Offset".to_string());
-    let offset = entropy_offset_group(library_path, input_paths, subsample)?;
+    let offset = validated_offset_group(table, input_paths, subsample, validation)?;
+    finish_progress_bar(&pb, format!("Calculated Offsets: {:?}", offset));
+    Ok(offset)
+}
+
+/// Calculates every significant offset for each input by trial matching against the library
+fn calculate_staggered_offset(
+    table: &LibraryTable,
+    input_paths: &[String],
+    subsample: &Subsample,
+    min_fraction: f64,
+    quiet: bool,
+) -> Result<Vec<Vec<Offset>>> {
+    let pb = if quiet {
+        None
+    } else {
+        Some(initialize_progress_bar())
+    };
+    start_progress_bar(&pb, "Calculating Staggered Offsets".to_string());
+    let offset = trial_offset_group(table, input_paths, subsample, min_fraction)?;
+    finish_progress_bar(&pb, format!("Calculated Offsets: {:?}", offset));
+    Ok(offset)
+}
+
+/// Calculates the offset in both directions for each input
+fn calculate_mixed_offset(
+    table: &LibraryTable,
+    input_paths: &[String],
+    subsample: &Subsample,
+    quiet: bool,
+) -> Result<Vec<Vec<Offset>>> {
+    let pb = if quiet {
+        None
+    } else {
+        Some(initialize_progress_bar())
+    };
+    start_progress_bar(&pb, "Calculating Mixed Orientation Offsets".to_string());
+    let offset = mixed_offset_group(table, input_paths, subsample)?;
     finish_progress_bar(&pb, format!("Calculated Offsets: {:?}", offset));
     Ok(offset)
 }
 
+/// Calculates the offsets of each input, either the provided fixed offset, a single
+/// offset per input, every significant offset if staggered primers are expected, or
+/// an offset in each direction if reads are in mixed orientations
+fn sample_offsets(
+    args: &CountArgs,
+    table: &LibraryTable,
+    input_paths: &[String],
+    fixed: Option<Offset>,
+) -> Result<Vec<Vec<Offset>>> {
+    if args.stagger {
+        // trial matching already considers both directions
+        return calculate_staggered_offset(
+            table,
+            input_paths,
+            &args.subsampling(),
+            args.min_offset_fraction,
+            args.quiet,
+        );
+    }
+    match (fixed, args.mixed_orientation) {
+        (Some(o), true) => Ok(vec![vec![o, o.flip()]; input_paths.len()]),
+        (Some(o), false) => Ok(vec![vec![o]; input_paths.len()]),
+        (None, true) => calculate_mixed_offset(table, input_paths, &args.subsampling(), args.quiet),
+        (None, false) => Ok(calculate_offset(
+            table,
+            input_paths,
+            &args.subsampling(),
+            &args.offset_validation(),
+            args.quiet,
+        )?
+        .into_iter()
+        .map(|x| vec![x])
+        .collect()),
+    }
+}
+
+/// Calculates the offsets of each input as in `sample_offsets`, except for the
+/// inputs given a fixed offset in the sample sheet
+fn sheet_offsets(
+    args: &CountArgs,
+    table: &LibraryTable,
+    input_paths: &[String],
+    fixed: &[Option<Offset>],
+    default: Option<Offset>,
+) -> Result<Vec<Vec<Offset>>> {
+    merge_fixed_offsets(
+        input_paths,
+        fixed,
+        |undetermined| sample_offsets(args, table, undetermined, default),
+        |o| {
+            if args.mixed_orientation && !args.stagger {
+                vec![o, o.flip()]
+            } else {
+                vec![o]
+            }
+        },
+    )
+}
+
+/// Merges the fixed offsets of the sample sheet with the offsets detected over
+/// the inputs without a fixed offset (in the order of the inputs)
+fn merge_fixed_offsets<T>(
+    input_paths: &[String],
+    fixed: &[Option<Offset>],
+    detect: impl FnOnce(&[String]) -> Result<Vec<T>>,
+    from_fixed: impl Fn(Offset) -> T,
+) -> Result<Vec<T>> {
+    let undetermined = input_paths
+        .iter()
+        .zip(fixed)
+        .filter(|(_, f)| f.is_none())
+        .map(|(p, _)| p.clone())
+        .collect::<Vec<_>>();
+    let mut detected = if undetermined.is_empty() {
+        Vec::new()
+    } else {
+        detect(&undetermined)?
+    }
+    .into_iter();
+    Ok(fixed
+        .iter()
+        .map(|f| match f {
+            Some(o) => from_fixed(*o),
+            None => detected
+                .next()
+                .expect("an offset is detected for every input without a fixed offset"),
+        })
+        .collect())
+}
+
+/// Calculates the offsets of each paired mate (R2) as in `sheet_offsets`, which
+/// are only fixed if a mate offset is provided on the command line or sample sheet
+fn mate_offsets(
+    args: &CountArgs,
+    table: &LibraryTable,
+    mates: &[String],
+    fixed: &[Option<Offset>],
+) -> Result<Vec<Vec<Offset>>> {
+    let default = args
+        .mate_offset
+        .map(|o| directed_offset(o, args.mate_reverse));
+    sheet_offsets(args, table, mates, fixed, default)
+}
+
+/// Builds a user provided offset in the provided direction
+fn directed_offset(index: usize, reverse: bool) -> Offset {
+    if reverse {
+        Offset::Reverse(index)
+    } else {
+        Offset::Forward(index)
+    }
+}
+
+/// Builds a user provided offset for each input
+fn fixed_offset(index: usize, reverse: bool, num_inputs: usize) -> Vec<Offset> {
+    vec![directed_offset(index, reverse); num_inputs]
+}
+
 /// Validate Paths Exist
-fn validate_paths(input_paths: &[String]) {
+fn validate_paths(input_paths: &[String]) -> Result<()> {
     for x in input_paths.iter() {
         if !Path::new(x).exists() {
-            assert!(
-                Path::new(x).exists(),
-                "Provided filepath does not exist: {}",
-                x
+            bail!("Provided filepath does not exist: {}", x);
+        }
+    }
+    Ok(())
+}
+
+/// Validates the provided sample names or generates them from the input paths
+fn resolve_sample_names(
+    sample_names: Option<Vec<String>>,
+    input_paths: &[String],
+) -> Result<Vec<String>> {
+    match sample_names {
+        Some(s) => {
+            if s.len() != input_paths.len() {
+                bail!(
+                    "Must provide as many sample names as there are input files ({} names for {} files)",
+                    s.len(),
+                    input_paths.len()
+                )
+            }
+            Ok(s)
+        }
+        None => Ok(generate_sample_names(input_paths)),
+    }
+}
+
+/// Summarizes the count distribution of each sample in a count table
+fn run_qc(args: QcArgs) -> Result<()> {
+    let table = CountTable::from_path(&args.input_path)?;
+    write_summary(
+        Path::new(&args.output_prefix),
+        &table.summaries(),
+        &Metadata::default(),
+    )
+}
+
+impl OffsetArgs {
+    /// Describes how reads are subsampled for offset detection
+    fn subsampling(&self) -> Subsample {
+        subsampling(
+            self.subsample,
+            self.reservoir,
+            self.subsample_window,
+            self.seed,
+        )
+    }
+}
+
+/// Reports the constant regions stripped from the library sequences
+fn report_constant_regions(regions: &ConstantRegions) {
+    eprintln!(
+        "Trimmed constant library regions: prefix {} ({} bp), suffix {} ({} bp)",
+        String::from_utf8_lossy(regions.prefix()),
+        regions.prefix().len(),
+        String::from_utf8_lossy(regions.suffix()),
+        regions.suffix().len()
+    );
+}
+
+/// Detects and writes the offset of each sample alongside its diagnostics
+fn run_offset(args: OffsetArgs) -> Result<()> {
+    validate_paths(&args.input_paths)?;
+    let table = args.library.library_format().load(&args.library_path)?;
+    if let Some(regions) = table.constant_regions() {
+        report_constant_regions(regions);
+    }
+    let sample_names = resolve_sample_names(args.sample_names.clone(), &args.input_paths)?;
+    let diagnostics = entropy_offset_diagnostics(
+        &table,
+        &args.input_paths,
+        &args.subsampling(),
+        args.candidates,
+    )?;
+    write_offsets(args.output_path, &diagnostics, &sample_names)?;
+    if let Some(path) = &args.diagnostics {
+        write_offset_diagnostics(path, &diagnostics, &sample_names)?;
+    }
+    Ok(())
+}
+
+/// Validates that a library can be loaded and that its gene map covers every alias,
+/// and reports how distinguishable its guides are
+fn run_library_check(args: LibraryCheckArgs) -> Result<()> {
+    validate_paths(std::slice::from_ref(&args.library_path))?;
+    let table = args.library.library_format().load(&args.library_path)?;
+    let library = table.library()?;
+    let genemap = match &args.genemap {
+        Some(g) => Some(GeneMap::new(g)?),
+        None => table.genemap()?,
+    };
+    if let Some(genemap) = genemap {
+        let genemap = genemap.with_merged_aliases(library.values());
+        if let Some(missing) = genemap.missing_aliases(&library) {
+            bail!(
+                "Missing sgRNA aliases in gene map: {:?}",
+                vec_to_nuc(&missing)?
             );
         }
     }
+    let design = LibraryDesign::new(&library);
+    if let Some(regions) = table.constant_regions() {
+        println!(
+            "TrimmedPrefix\t{}",
+            String::from_utf8_lossy(regions.prefix())
+        );
+        println!(
+            "TrimmedSuffix\t{}",
+            String::from_utf8_lossy(regions.suffix())
+        );
+    }
+    println!("Sequences\t{}", library.keys().count());
+    println!(
+        "Length\t{}",
+        library
+            .sizes()
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(",")
+    );
+    for distance in 1..=design::MAX_DISTANCE {
+        println!("PairsDistance{}\t{}", distance, design.pairs(distance));
+    }
+    println!("AmbiguousOneOffs\t{:.6}", design.ambiguous_fraction());
+    println!(
+        "ReverseComplementCollisions\t{}",
+        design.reverse_complement_collisions()
+    );
+    if let Some(path) = args.output_path {
+        write_library_design(Some(path), &design)?;
+    }
+    Ok(())
+}
+
+/// Combines count tables into a single table
+fn run_merge(args: MergeArgs) -> Result<()> {
+    validate_paths(&args.input_paths)?;
+    let tables = args
+        .input_paths
+        .iter()
+        .map(|p| CountTable::from_path(p))
+        .collect::<Result<Vec<_>>>()?;
+    CountTable::merge(&tables)?.write(args.output_path)
 }
 
 fn main() -> Result<()> {
-    let args = Args::parse();
+    let cli = Cli::parse();
+    match cli.command {
+        Some(Command::Count(args)) => run_count(*args),
+        Some(Command::Qc(args)) => run_qc(args),
+        Some(Command::Offset(args)) => run_offset(args),
+        Some(Command::Library {
+            command: LibraryCommand::Check(args),
+        }) => run_library_check(args),
+        Some(Command::Merge(args)) => run_merge(args),
+        None => match cli.count {
+            Some(args) => run_count(args),
+            None => {
+                Cli::command().print_help()?;
+                Ok(())
+            }
+        },
+    }
+}
 
+/// Counts the sgRNAs of each sample
+fn run_count(mut args: CountArgs) -> Result<()> {
     set_threads(args.threads);
 
+    // reads the samples from the sample sheet if provided
+    let sheet = match &args.sample_sheet {
+        Some(path) => Some(SampleSheet::from_path(path)?),
+        None => None,
+    };
+    if let Some(s) = &sheet {
+        args.input_paths = s.r1().to_vec();
+        args.mate_paths = s.r2().map(<[String]>::to_vec);
+        args.sample_names = Some(s.names().to_vec());
+    }
+    let metadata = sheet
+        .as_ref()
+        .map(|s| s.metadata().clone())
+        .unwrap_or_default();
+    let fixed_offsets = sheet.as_ref().map_or_else(
+        || vec![None; args.input_paths.len()],
+        |s| s.offsets().to_vec(),
+    );
+    let fixed_mate_offsets = sheet.as_ref().map_or_else(
+        || vec![None; args.input_paths.len()],
+        |s| s.mate_offsets().to_vec(),
+    );
+    if args.cell_size.is_some() && args.mate_paths.is_none() {
+        bail!("Single-cell counting requires mates (R2) holding the guides")
+    }
+    let sheet_fixed = fixed_offsets.iter().any(Option::is_some);
+    let sheet_mate_fixed = fixed_mate_offsets.iter().any(Option::is_some);
+    if args.dual_library_path.is_some() && (sheet_fixed || sheet_mate_fixed) {
+        bail!("Sample sheet offsets are not supported in dual-guide counting")
+    }
+    if args.dual_library_path.is_some() && args.library.duplicates != DuplicatePolicy::Error {
+        bail!("Duplicate library records are not supported in dual-guide counting: every construct id must be paired exactly once")
+    }
+    if args.cell_size.is_some() && sheet_fixed {
+        bail!("The first mates only hold the cell barcode in single-cell counting: provide the guide offsets in the mate_offset column of the sample sheet")
+    }
+
     // validates all input paths
-    validate_paths(&args.input_paths);
+    validate_paths(&args.input_paths)?;
+    if let Some(mates) = &args.mate_paths {
+        validate_paths(mates)?;
+    }
 
     // generates sample names if required
-    let sample_names = match args.sample_names {
-        Some(s) => {
-            if s.len() == args.input_paths.len() {
-                s
-            } else {
-                panic!("Must provide as many sample names as there are input files")
-            }
-        }
-        None => generate_sample_names(&args.input_paths),
+    let sample_names = resolve_sample_names(args.sample_names.clone(), &args.input_paths)?;
+
+    // parses the library once, trimming its constant regions if required
+    let table = args.library.library_format().load(&args.library_path)?;
+
+    // builds gene map is provided or from the gene column of a delimited library
+    let genemap = match &args.genemap {
+        Some(g) => Some(GeneMap::new(g)?),
+        None => table.genemap()?,
     };
 
-    // calculates offset if required
-    let offset = match args.offset {
-        Some(o) => {
-            if args.reverse {
-                vec![Offset::Reverse(o); args.input_paths.len()]
-            } else {
-                vec![Offset::Forward(o); args.input_paths.len()]
-            }
-        }
-        None => calculate_offset(
-            &args.library_path,
-            &args.input_paths,
-            args.subsample,
-            args.quiet,
-        )?,
+    // builds UMI description if provided
+    let umi = match (args.umi_offset, args.umi_size) {
+        (Some(o), Some(s)) => Some(Umi::new(o, s, args.umi_collapse)),
+        _ => None,
     };
 
-    // builds gene map is provided
-    let genemap = match args.genemap {
-        Some(g) => Some(GeneMap::new(&g)?),
+    // reports the constant regions stripped from the library if trimming
+    let regions = table.constant_regions();
+    if let (Some(r), false) = (regions, args.quiet) {
+        report_constant_regions(r);
+    }
+
+    // builds anchor description if provided or from the stripped constant regions
+    let anchor = match &args.anchor {
+        Some(a) => Some(Anchor::new(
+            a.as_bytes(),
+            args.downstream_anchor.as_ref().map(String::as_bytes),
+            args.anchor_mismatches,
+        )),
+        None if args.trim_anchors => regions.and_then(|r| r.anchor(args.anchor_mismatches)),
+        None => None,
+    };
+
+    // builds base quality filter if any threshold is provided
+    let quality = match (args.min_quality, args.mean_quality, args.mismatch_quality) {
+        (None, None, None) => None,
+        (min, mean, mismatch) => Some(QualityFilter::new(min, mean, mismatch)),
+    };
+
+    // builds unmapped read output if provided
+    let unmapped = args
+        .unmapped
+        .as_ref()
+        .map(|u| UnmappedOutput::new(u, args.unmapped_windows, args.top_unmapped));
+
+    // opens per-read assignment log if provided
+    let log = match &args.assignments {
+        Some(path) => Some(ReadLog::new(path)?),
         None => None,
     };
 
+    // the run summaries are written next to the count table unless a prefix is provided
+    let summary = args.summary.clone().or_else(|| args.output_path.clone());
+
+    let report = args
+        .report
+        .as_ref()
+        .map(|r| Report::new(r, &table, args.subsampling()));
+
+    // exact matching disallows any mismatches
+    let max_mismatches = if args.exact { 0 } else { args.max_mismatches };
+
     // default position recursion is true; flag flips this bool
-    let position_recursion = !args.no_position_recursion;
+    let options = AssignmentOptions::new(!args.no_position_recursion)
+        .with_mismatches(max_mismatches, args.indels)
+        .with_umi(umi)
+        .with_anchor(anchor)
+        .with_quality(quality)
+        .with_mate_mode(args.mate_mode);
 
-    // perform counting
-    count(
-        &args.library_path,
-        args.input_paths,
-        &sample_names,
-        args.output_path,
-        offset,
-        args.exact,
-        &genemap,
-        position_recursion,
-        args.include_zero,
-        args.quiet,
+    let outputs = CountOutputs::new(args.output_path.clone())
+        .with_genemap(genemap)
+        .with_include_zero(args.include_zero)
+        .with_include_recombined(args.recombined)
+        .with_offset_summary(args.offset_summary.clone())
+        .with_qc_summary(args.qc_summary.clone())
+        .with_summary(summary)
+        .with_report(report)
+        .with_unmapped(unmapped)
+        .with_log(log)
+        .with_quiet(args.quiet);
+
+    // perform single-cell counting if a cell barcode is provided
+    if let (Some(cell_size), Some(mates)) = (args.cell_size, &args.mate_paths) {
+        // the guide is only read from the mates
+        let mate_offset = merge_fixed_offsets(
+            mates,
+            &fixed_mate_offsets,
+            |undetermined| match args.mate_offset {
+                Some(o) => Ok(fixed_offset(o, args.mate_reverse, undetermined.len())),
+                None => calculate_offset(
+                    &table,
+                    undetermined,
+                    &args.subsampling(),
+                    &args.offset_validation(),
+                    args.quiet,
+                ),
+            },
+            |o| o,
+        )?;
+        let whitelist = match &args.whitelist {
+            Some(w) => Some(Whitelist::new(w)?),
+            None => None,
+        };
+        let barcode = CellBarcode::new(args.cell_offset, cell_size, whitelist);
+        let samples = Samples::new(args.input_paths, args.mate_paths, sample_names, metadata)?;
+        return count_cells(&table, &samples, mate_offset, &barcode, options, &outputs);
+    }
+
+    // perform dual-guide counting if a second library is provided
+    if let Some(dual_library_path) = &args.dual_library_path {
+        let dual_table = args.library.library_format().load(dual_library_path)?;
+
+        // calculates offset if required
+        let offset = match args.offset {
+            Some(o) => fixed_offset(o, args.reverse, args.input_paths.len()),
+            None => calculate_offset(
+                &table,
+                &args.input_paths,
+                &args.subsampling(),
+                &args.offset_validation(),
+                args.quiet,
+            )?,
+        };
+
+        // the second guide is read from the mates if provided
+        let dual_paths = args.mate_paths.as_ref().unwrap_or(&args.input_paths);
+        let dual_reverse = if args.mate_paths.is_some() {
+            args.mate_reverse
+        } else {
+            args.reverse
+        };
+        let dual_offset = match args.dual_offset {
+            Some(o) => fixed_offset(o, dual_reverse, dual_paths.len()),
+            None => calculate_offset(
+                &dual_table,
+                dual_paths,
+                &args.subsampling(),
+                &args.offset_validation(),
+                args.quiet,
+            )?,
+        };
+        let samples = Samples::new(args.input_paths, args.mate_paths, sample_names, metadata)?;
+        return count_dual(
+            &table,
+            &dual_table,
+            &samples,
+            offset,
+            dual_offset,
+            options,
+            &outputs,
+        );
+    }
+
+    // calculates offsets if required
+    let default_offset = args.offset.map(|o| directed_offset(o, args.reverse));
+    let offset = sheet_offsets(
+        &args,
+        &table,
+        &args.input_paths,
+        &fixed_offsets,
+        default_offset,
     )?;
 
+    // calculates the offsets of each mate independently if required
+    let mate_offset = match &args.mate_paths {
+        Some(mates) => Some(mate_offsets(&args, &table, mates, &fixed_mate_offsets)?),
+        None => None,
+    };
+
+    // perform counting
+    let samples = Samples::new(args.input_paths, args.mate_paths, sample_names, metadata)?;
+    count(&table, &samples, offset, mate_offset, options, &outputs)?;
+
     Ok(())
 }
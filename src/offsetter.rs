@@ -1,12 +1,15 @@
-use anyhow::{bail, Result};
+use crate::{Library, LibraryTable, Subsample};
+use anyhow::{bail, Context, Result};
 use fxread::{initialize_reader, Record};
+use hashbrown::{HashMap, HashSet};
 use ndarray::{Array1, Array2, ArrayBase, Axis, Dim, ViewRepr};
 use ndarray_stats::{DeviationExt, EntropyExt, QuantileExt};
+use std::borrow::Borrow;
 
 /// An enumeration describing whether the sequences
 /// are offset in a forward direction or if the offset
 /// is better described from the reverse complement
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum Offset {
     /// Reads will be processed with an offset in the forward direction
     Forward(usize),
@@ -31,11 +34,24 @@ impl Offset {
     pub fn is_reverse(&self) -> bool {
         matches!(self, Self::Reverse(_))
     }
+
+    /// Returns the offset at the same index in the opposite direction
+    #[must_use]
+    pub fn flip(&self) -> Self {
+        match self {
+            Self::Forward(index) => Self::Reverse(*index),
+            Self::Reverse(index) => Self::Forward(*index),
+        }
+    }
 }
 
-/// Calculates the size of the first sequence in a [`fxread::FastxRead`] Iterator.
-fn get_sequence_size(reader: &mut dyn Iterator<Item = Record>) -> usize {
-    reader.next().expect("empty reader").seq().len()
+/// Calculates the size of the longest sequence among the records
+fn get_sequence_size<R: Borrow<Record>>(records: &[R]) -> usize {
+    records
+        .iter()
+        .map(|r| r.borrow().seq().len())
+        .max()
+        .unwrap_or(0)
 }
 
 /// Assigns a stable index to each nucleotide
@@ -51,43 +67,50 @@ fn base_map(c: u8) -> Option<usize> {
 
 /// Creates a 2D matrix of shape (`seq_size`, 4) where each row represents the positional
 /// index of the sequence and each column represents the number of observed nucleotides
-/// at that position
-fn position_counts(reader: &mut dyn Iterator<Item = Record>) -> Array2<f64> {
-    // skips the first record to calculate size
-    let size = get_sequence_size(reader);
-    reader.fold(Array2::<f64>::zeros((size, 4)), |mut posmat, record| {
-        record
-            .seq()
-            .iter()
-            .enumerate()
-            .take(size)
-            .map(|(idx, c)| (idx, base_map(*c)))
-            .for_each(|(idx, jdx)| {
-                if let Some(j) = jdx {
-                    // increment the nucleotide index and at the position
-                    posmat[[idx, j]] += 1.;
-                } else {
-                    // increment each nucleotide index if an `N` is found (as it could be anything)
-                    posmat[[idx, 0]] += 1.;
-                    posmat[[idx, 1]] += 1.;
-                    posmat[[idx, 2]] += 1.;
-                    posmat[[idx, 3]] += 1.;
-                };
-            });
-        posmat
-    })
+/// at that position. The matrix spans the longest sequence so that the positions past
+/// the end of shorter sequences only count the longer ones.
+fn position_counts<R: Borrow<Record>>(reader: &mut dyn Iterator<Item = R>) -> Array2<f64> {
+    let records = reader.collect::<Vec<_>>();
+    let size = get_sequence_size(&records);
+    records
+        .iter()
+        .fold(Array2::<f64>::zeros((size, 4)), |mut posmat, record| {
+            record
+                .borrow()
+                .seq()
+                .iter()
+                .enumerate()
+                .map(|(idx, c)| (idx, base_map(*c)))
+                .for_each(|(idx, jdx)| {
+                    if let Some(j) = jdx {
+                        // increment the nucleotide index and at the position
+                        posmat[[idx, j]] += 1.;
+                    } else {
+                        // increment each nucleotide index if an `N` is found (as it could be anything)
+                        posmat[[idx, 0]] += 1.;
+                        posmat[[idx, 1]] += 1.;
+                        posmat[[idx, 2]] += 1.;
+                        posmat[[idx, 3]] += 1.;
+                    };
+                });
+            posmat
+        })
 }
 
-/// Normalizes the nucleotide counts across each row (i.e. sequence positional index)
-fn normalize_counts(matrix: Array2<f64>) -> Array2<f64> {
-    let (x, y) = matrix.dim();
-    let sums = matrix.sum_axis(Axis(1));
-    let norm = matrix / sums.broadcast((y, x)).expect("incompatible sizes").t();
-    norm
+/// Normalizes the nucleotide counts across each row (i.e. sequence positional index).
+/// Rows without any observed nucleotide are left empty (i.e. of zero entropy).
+fn normalize_counts(mut matrix: Array2<f64>) -> Array2<f64> {
+    for mut row in matrix.rows_mut() {
+        let sum = row.sum();
+        if sum > 0.0 {
+            row /= sum;
+        }
+    }
+    matrix
 }
 
 /// Calculates the nucleotide entropy for each basepair position in an [`fxread::FastxRead`] iterator.
-fn positional_entropy(reader: &mut dyn Iterator<Item = Record>) -> Array1<f64> {
+fn positional_entropy<R: Borrow<Record>>(reader: &mut dyn Iterator<Item = R>) -> Array1<f64> {
     let pos_prob = normalize_counts(position_counts(reader));
     pos_prob.map_axis(Axis(1), |axis| {
         axis.entropy().expect("Unexpected Negatives in Calculation")
@@ -119,38 +142,29 @@ fn windowed_mse(array1: &Array1<f64>, array2: &Array1<f64>) -> Array1<f64> {
     )
 }
 
-fn assign_offset(mse_forward: &Array1<f64>, mse_reverse: &Array1<f64>) -> Offset {
-    let argmin_forward = match mse_forward.argmin() {
-        Ok(m) => m,
-        Err(why) => panic!("Unexpected minmax error in entropy: {}", why),
-    };
-
-    let argmin_reverse = match mse_reverse.argmin() {
-        Ok(m) => m,
-        Err(why) => panic!("Unexpected minmax error in entropy: {}", why),
-    };
-
-    let min_forward = match mse_forward.min() {
-        Ok(m) => m,
-        Err(why) => panic!("Unexpected minmax error in entropy: {}", why),
-    };
-
-    let min_reverse = match mse_reverse.min() {
-        Ok(m) => m,
-        Err(why) => panic!("Unexpected minmax error in entropy: {}", why),
-    };
+/// Chooses the direction and position of lowest MSE
+fn assign_offset(mse_forward: &Array1<f64>, mse_reverse: &Array1<f64>) -> Result<Offset> {
+    let context = "Unexpected minmax error in entropy";
+    let argmin_forward = mse_forward.argmin().context(context)?;
+    let argmin_reverse = mse_reverse.argmin().context(context)?;
+    let min_forward = mse_forward.min().context(context)?;
+    let min_reverse = mse_reverse.min().context(context)?;
 
     if min_forward < min_reverse {
         // Reads are in forward directionality
-        Offset::Forward(argmin_forward)
+        Ok(Offset::Forward(argmin_forward))
     } else {
         // Reads are in reverse directionality
-        Offset::Reverse(argmin_reverse)
+        Ok(Offset::Reverse(argmin_reverse))
     }
 }
 
-/// Calculates the starting position which minimizes the entropy between two entropy arrays
-fn minimize_mse(reference: &Array1<f64>, comparison: &Array1<f64>) -> Result<Offset> {
+/// Calculates the windowed MSE of the reference entropy across the comparison entropy
+/// in both the forward and reverse directions
+fn directional_mse(
+    reference: &Array1<f64>,
+    comparison: &Array1<f64>,
+) -> Result<(Array1<f64>, Array1<f64>)> {
     if comparison.len() < reference.len() {
         bail!("Sequences in reference library are larger than the sequences in input.\nConsider reducing the length of your reference sequences (i.e. extracting the variable region of the sgRNA or reducing the length of the adapters.)")
     }
@@ -158,19 +172,324 @@ fn minimize_mse(reference: &Array1<f64>, comparison: &Array1<f64>) -> Result<Off
 
     let mse_forward = windowed_mse(reference, comparison);
     let mse_reverse = windowed_mse(reference, &rev_comparison);
+    Ok((mse_forward, mse_reverse))
+}
+
+/// Calculates the starting position which minimizes the entropy between two entropy arrays
+fn minimize_mse(reference: &Array1<f64>, comparison: &Array1<f64>) -> Result<Offset> {
+    let (mse_forward, mse_reverse) = directional_mse(reference, comparison)?;
+    assign_offset(&mse_forward, &mse_reverse)
+}
+
+/// Calculates the starting position which minimizes the entropy between two entropy arrays
+/// in each direction, returning the best offset first followed by the best offset in the
+/// opposite direction.
+fn minimize_mse_both(reference: &Array1<f64>, comparison: &Array1<f64>) -> Result<Vec<Offset>> {
+    let (mse_forward, mse_reverse) = directional_mse(reference, comparison)?;
+    let best = assign_offset(&mse_forward, &mse_reverse)?;
+    let other = match best {
+        Offset::Forward(_) => Offset::Reverse(mse_reverse.argmin()?),
+        Offset::Reverse(_) => Offset::Forward(mse_forward.argmin()?),
+    };
+    Ok(vec![best, other])
+}
+
+/// The intermediate values of an entropy offset calculation, used to judge
+/// whether a detected offset is trustworthy
+#[derive(Debug, Clone)]
+pub struct OffsetDiagnostics {
+    library_entropy: Vec<f64>,
+    sample_entropy: Vec<f64>,
+    forward_mse: Vec<f64>,
+    reverse_mse: Vec<f64>,
+    argmin: Offset,
+    offset: Offset,
+    rates: Vec<(Offset, f64)>,
+}
+impl OffsetDiagnostics {
+    /// Calculates the windowed MSE curves of the library entropy across the sample entropy
+    pub fn new(library_entropy: &Array1<f64>, sample_entropy: &Array1<f64>) -> Result<Self> {
+        let (mse_forward, mse_reverse) = directional_mse(library_entropy, sample_entropy)?;
+        let argmin = assign_offset(&mse_forward, &mse_reverse)?;
+        Ok(Self {
+            argmin,
+            offset: argmin,
+            library_entropy: library_entropy.to_vec(),
+            sample_entropy: sample_entropy.to_vec(),
+            forward_mse: mse_forward.to_vec(),
+            reverse_mse: mse_reverse.to_vec(),
+            rates: Vec::new(),
+        })
+    }
+
+    /// Trial maps the records against the [`Library`] at the `candidates` offsets of
+    /// lowest MSE and chooses the offset with the highest exact match rate. Ties are
+    /// broken by the lower MSE. The MSE argmin is chosen if no candidates are mapped.
+    pub fn validate(&mut self, library: &Library, records: &[Record], candidates: usize) {
+        self.offset = self.argmin;
+        self.rates = self
+            .candidates(candidates)
+            .into_iter()
+            .map(|offset| (offset, exact_match_rate(library, records, offset)))
+            .collect();
+        if let Some((offset, _)) = self.rates.iter().fold(
+            None,
+            |best: Option<(Offset, f64)>, (offset, rate)| match best {
+                Some((_, b)) if b >= *rate => best,
+                _ => Some((*offset, *rate)),
+            },
+        ) {
+            self.offset = offset;
+        }
+    }
+
+    /// Returns up to `n` offsets in order of increasing MSE across both directions,
+    /// starting with the MSE argmin
+    fn candidates(&self, n: usize) -> Vec<Offset> {
+        let mut offsets = self
+            .forward_mse
+            .iter()
+            .enumerate()
+            .map(|(idx, mse)| (Offset::Forward(idx), *mse))
+            .chain(
+                self.reverse_mse
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, mse)| (Offset::Reverse(idx), *mse)),
+            )
+            .filter(|(offset, _)| *offset != self.argmin)
+            .collect::<Vec<_>>();
+        offsets.sort_by(|(_, x), (_, y)| x.total_cmp(y));
+        std::iter::once(self.argmin)
+            .chain(offsets.into_iter().map(|(offset, _)| offset))
+            .take(n)
+            .collect()
+    }
+
+    /// Returns the exact match rate of each trial mapped candidate offset
+    #[must_use]
+    pub fn rates(&self) -> &[(Offset, f64)] {
+        &self.rates
+    }
+
+    /// Returns the exact match rate of the chosen offset if it was trial mapped
+    #[must_use]
+    pub fn match_rate(&self) -> Option<f64> {
+        self.rates
+            .iter()
+            .find(|(offset, _)| *offset == self.offset)
+            .map(|(_, rate)| *rate)
+    }
+
+    /// Returns the positional entropy of the library
+    #[must_use]
+    pub fn library_entropy(&self) -> &[f64] {
+        &self.library_entropy
+    }
+
+    /// Returns the positional entropy of the sample
+    #[must_use]
+    pub fn sample_entropy(&self) -> &[f64] {
+        &self.sample_entropy
+    }
+
+    /// Returns the windowed MSE at each offset in the forward direction
+    #[must_use]
+    pub fn forward_mse(&self) -> &[f64] {
+        &self.forward_mse
+    }
+
+    /// Returns the windowed MSE at each offset in the reverse direction
+    #[must_use]
+    pub fn reverse_mse(&self) -> &[f64] {
+        &self.reverse_mse
+    }
+
+    /// Returns the chosen offset (i.e. the argmin across both directions unless
+    /// trial mapping validated another candidate)
+    #[must_use]
+    pub fn offset(&self) -> Offset {
+        self.offset
+    }
+
+    /// Returns the offset of lowest MSE across both directions
+    #[must_use]
+    pub fn argmin(&self) -> Offset {
+        self.argmin
+    }
+
+    /// Returns the MSE of an offset
+    #[must_use]
+    pub fn mse(&self, offset: Offset) -> f64 {
+        match offset {
+            Offset::Forward(x) => self.forward_mse[x],
+            Offset::Reverse(x) => self.reverse_mse[x],
+        }
+    }
+
+    /// Returns the offset with the second lowest MSE across both directions
+    #[must_use]
+    pub fn second_offset(&self) -> Option<Offset> {
+        self.forward_mse
+            .iter()
+            .enumerate()
+            .map(|(idx, mse)| (Offset::Forward(idx), *mse))
+            .chain(
+                self.reverse_mse
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, mse)| (Offset::Reverse(idx), *mse)),
+            )
+            .filter(|(offset, _)| *offset != self.argmin)
+            .min_by(|(_, x), (_, y)| x.total_cmp(y))
+            .map(|(offset, _)| offset)
+    }
+
+    /// Returns the difference in MSE between the second best offset and the argmin.
+    /// Larger margins indicate a more trustworthy MSE ranking.
+    #[must_use]
+    pub fn margin(&self) -> Option<f64> {
+        self.second_offset()
+            .map(|second| self.mse(second) - self.mse(self.argmin))
+    }
+}
+
+/// Describes how detected offsets are validated by trial mapping the subsampled
+/// reads against the [`Library`]
+#[derive(Debug, Clone, Copy)]
+pub struct OffsetValidation {
+    candidates: usize,
+    min_rate: f64,
+    strict: bool,
+}
+impl OffsetValidation {
+    /// Creates a new validation of the `candidates` offsets of lowest MSE. Offsets
+    /// with an exact match rate below `min_rate` are warned about, or are an error
+    /// if `strict` is set.
+    #[must_use]
+    pub fn new(candidates: usize, min_rate: f64, strict: bool) -> Self {
+        Self {
+            candidates,
+            min_rate,
+            strict,
+        }
+    }
+
+    /// Returns the number of candidate offsets trial mapped
+    #[must_use]
+    pub fn candidates(&self) -> usize {
+        self.candidates
+    }
 
-    Ok(assign_offset(&mse_forward, &mse_reverse))
+    /// Checks the exact match rate of the chosen offset of a path if it was trial mapped
+    pub fn check(&self, diagnostics: &OffsetDiagnostics, path: &str) -> Result<()> {
+        let Some(rate) = diagnostics.match_rate() else {
+            return Ok(());
+        };
+        if rate >= self.min_rate {
+            return Ok(());
+        }
+        let message = format!(
+            "Best offset ({:?}) of {} exactly matches only {:.1}% of subsampled reads (minimum {:.1}%)",
+            diagnostics.offset(),
+            path,
+            100.0 * rate,
+            100.0 * self.min_rate
+        );
+        if self.strict {
+            bail!(message)
+        }
+        eprintln!("WARNING: {}", message);
+        Ok(())
+    }
+}
+
+/// Calculates the fraction of records whose window at the offset exactly
+/// matches a [`Library`] sequence of any length
+fn exact_match_rate(library: &Library, records: &[Record], offset: Offset) -> f64 {
+    let matches = records
+        .iter()
+        .filter(|record| {
+            let (index, sequence) = match offset {
+                Offset::Forward(x) => (x, record.seq().to_vec()),
+                Offset::Reverse(x) => (x, record.seq_rev_comp()),
+            };
+            library.sizes().iter().any(|size| {
+                sequence
+                    .get(index..index + size)
+                    .is_some_and(|window| library.contains(window).is_some())
+            })
+        })
+        .count();
+    matches as f64 / records.len().max(1) as f64
+}
+
+/// Calculates the offset diagnostics of each provided path against the library,
+/// validating the `candidates` offsets of lowest MSE by trial mapping
+pub fn entropy_offset_diagnostics(
+    table: &LibraryTable,
+    input_paths: &[String],
+    subsample: &Subsample,
+    candidates: usize,
+) -> Result<Vec<OffsetDiagnostics>> {
+    let library = table.library()?;
+    let mut reference = table.reader()?;
+    let reference_entropy = positional_entropy(&mut reference);
+    input_paths
+        .iter()
+        .map(|path| {
+            let records = subsample.sample(initialize_reader(path)?);
+            let comparison_entropy = positional_entropy(&mut records.iter());
+            let mut diagnostics =
+                OffsetDiagnostics::new(&reference_entropy, &comparison_entropy)
+                    .with_context(|| format!("Error in entropy offset calculation of {}", path))?;
+            diagnostics.validate(&library, &records, candidates);
+            Ok(diagnostics)
+        })
+        .collect()
+}
+
+/// Calculates the offset of each provided path by trial mapping the candidate
+/// offsets of lowest entropy MSE and checking the exact match rate of the best
+pub fn validated_offset_group(
+    table: &LibraryTable,
+    input_paths: &[String],
+    subsample: &Subsample,
+    validation: &OffsetValidation,
+) -> Result<Vec<Offset>> {
+    let diagnostics =
+        entropy_offset_diagnostics(table, input_paths, subsample, validation.candidates())?;
+    diagnostics
+        .iter()
+        .zip(input_paths)
+        .map(|(d, path)| {
+            validation.check(d, path)?;
+            Ok(d.offset())
+        })
+        .collect()
+}
+
+/// Calculates the positional entropy of every sequence of a library
+pub fn library_entropy_profile(table: &LibraryTable) -> Result<Vec<f64>> {
+    let mut reference = table.reader()?;
+    Ok(positional_entropy(&mut reference).to_vec())
+}
+
+/// Calculates the positional entropy of the subsampled records of a file
+pub fn entropy_profile(path: &str, subsample: &Subsample) -> Result<Vec<f64>> {
+    let mut reader = subsample.reader(path)?;
+    Ok(positional_entropy(&mut reader).to_vec())
 }
 
 /// Calculates the Offset in the Comparison by Minimizing
 /// the MSE of Positional Entropy Observed in the Reference.
 pub fn entropy_offset(
-    library_path: &str,
+    table: &LibraryTable,
     input_paths: &[String],
-    subsample: usize,
+    subsample: &Subsample,
 ) -> Result<Offset> {
-    let mut reference = initialize_reader(library_path)?;
-    let mut comparison = initialize_reader(&input_paths[0])?.take(subsample);
+    let mut reference = table.reader()?;
+    let mut comparison = subsample.reader(&input_paths[0])?;
 
     let reference_entropy = positional_entropy(&mut reference);
     let comparison_entropy = positional_entropy(&mut comparison);
@@ -179,31 +498,108 @@ pub fn entropy_offset(
     Ok(index)
 }
 
-/// Calculates the Offset in the Comparison by Minimizing
-/// the MSE of Positional Entropy Observed in the Reference
-/// For Each Provided Path
-pub fn entropy_offset_group(
-    library_path: &str,
+/// Calculates the Offset in both directions for each provided path (i.e. for runs
+/// with reads in mixed orientations) by minimizing the MSE of Positional Entropy
+/// observed in the Reference. The offset of the better matching direction is first.
+pub fn mixed_offset_group(
+    table: &LibraryTable,
     input_paths: &[String],
-    subsample: usize,
-) -> Result<Vec<Offset>> {
-    let mut reference = initialize_reader(library_path)?;
+    subsample: &Subsample,
+) -> Result<Vec<Vec<Offset>>> {
+    let mut reference = table.reader()?;
     let reference_entropy = positional_entropy(&mut reference);
-    let result = input_paths
+    let mut results = vec![];
+    for path in input_paths {
+        let comparison_entropy = positional_entropy(&mut subsample.reader(path)?);
+        match minimize_mse_both(&reference_entropy, &comparison_entropy) {
+            Ok(offsets) => results.push(offsets),
+            Err(why) => bail!("Error in entropy offset calculation:\n\n{}", why),
+        }
+    }
+    Ok(results)
+}
+
+/// Tallies the number of reads exactly matching a [`Library`] sequence of any
+/// length at every offset in both the forward and reverse directions
+fn trial_matches(
+    library: &Library,
+    reader: &mut dyn Iterator<Item = Record>,
+) -> (HashMap<Offset, usize>, usize) {
+    let mut matches = HashMap::new();
+    let mut total = 0;
+    for record in reader {
+        total += 1;
+        let reverse = record.seq_rev_comp();
+        let mut matched = HashSet::new();
+        for size in library.sizes() {
+            let forward = record
+                .seq()
+                .windows(*size)
+                .enumerate()
+                .map(|(idx, w)| (Offset::Forward(idx), library.contains(w).is_some()));
+            let reverse = reverse
+                .windows(*size)
+                .enumerate()
+                .map(|(idx, w)| (Offset::Reverse(idx), library.contains(w).is_some()));
+            matched.extend(
+                forward
+                    .chain(reverse)
+                    .filter(|(_, matched)| *matched)
+                    .map(|(offset, _)| offset),
+            );
+        }
+        matched
+            .into_iter()
+            .for_each(|offset| *matches.entry(offset).or_insert(0) += 1);
+    }
+    (matches, total)
+}
+
+/// Selects every offset matched by at least `min_fraction` of the reads,
+/// ordered by decreasing number of matches
+fn significant_offsets(
+    matches: &HashMap<Offset, usize>,
+    total: usize,
+    min_fraction: f64,
+) -> Vec<Offset> {
+    let mut offsets = matches
         .iter()
-        .map(|x| {
-            initialize_reader(x)
-                .unwrap_or_else(|_| panic!("Unable to open file: {}", x))
-                .take(subsample)
-        })
-        .map(|mut x| positional_entropy(&mut x))
-        .map(|x| minimize_mse(&reference_entropy, &x));
+        .filter(|(_, count)| total > 0 && **count as f64 / total as f64 >= min_fraction)
+        .map(|(offset, count)| (*offset, *count))
+        .collect::<Vec<_>>();
+    offsets.sort_unstable_by_key(|(offset, count)| {
+        (
+            std::cmp::Reverse(*count),
+            offset.is_reverse(),
+            *offset.index(),
+        )
+    });
+    offsets.into_iter().map(|(offset, _)| offset).collect()
+}
 
+/// Detects every significant offset (i.e. from pooled staggered primers) in each
+/// provided path by trial matching the subsampled reads against the [`Library`]
+/// at every position. Offsets are ordered by decreasing number of exact matches
+/// and a sample without any significant offset falls back to the entropy offset.
+pub fn trial_offset_group(
+    table: &LibraryTable,
+    input_paths: &[String],
+    subsample: &Subsample,
+    min_fraction: f64,
+) -> Result<Vec<Vec<Offset>>> {
+    let library = table.library()?;
     let mut results = vec![];
-    for x in result {
-        match x {
-            Ok(y) => results.push(y),
-            Err(why) => bail!("Error in entropy offset calculation:\n\n{}", why),
+    for path in input_paths {
+        let (matches, total) = trial_matches(&library, &mut subsample.reader(path)?);
+        let offsets = significant_offsets(&matches, total, min_fraction);
+        if offsets.is_empty() {
+            results.push(vec![entropy_offset(
+                table,
+                std::slice::from_ref(path),
+                subsample,
+            )?]);
+        } else {
+            results.push(offsets);
         }
     }
     Ok(results)
@@ -214,9 +610,11 @@ mod test {
     use crate::offsetter::base_map;
 
     use super::{
-        get_sequence_size, minimize_mse, normalize_counts, position_counts, positional_entropy,
-        Offset,
+        get_sequence_size, minimize_mse, minimize_mse_both, normalize_counts, position_counts,
+        positional_entropy, significant_offsets, trial_matches, Offset, OffsetDiagnostics,
+        OffsetValidation,
     };
+    use crate::Library;
     use fxread::{FastaReader, FastxRead, Record};
     use ndarray::Array1;
 
@@ -226,6 +624,12 @@ mod test {
         Box::new(FastaReader::new(sequence))
     }
 
+    // create reader of mixed length sequences with the longest first
+    fn mixed_reader() -> Box<dyn FastxRead<Item = Record>> {
+        let sequence: &'static [u8] = b">seq.0\nACTGA\n>seq.1\nACC\n>seq.2\nAGT\n";
+        Box::new(FastaReader::new(sequence))
+    }
+
     // create reader with an `AC` static prefix
     fn reader_with_n() -> Box<dyn FastxRead<Item = Record>> {
         let sequence: &'static [u8] = b">seq.0\nACT\n>seq.1\nACC\n>seq.2\nACT\n>seq.3\nACN\n";
@@ -251,7 +655,7 @@ mod test {
         let brr = Array1::linspace(10., 20., 100);
         match minimize_mse(&arr, &brr).unwrap() {
             Offset::Forward(x) => assert_eq!(x, 0),
-            Offset::Reverse(_) => assert!(false),
+            Offset::Reverse(_) => panic!("Unexpected reverse"),
         }
     }
 
@@ -264,22 +668,32 @@ mod test {
 
     #[test]
     fn sequence_size() {
-        let mut reader = reader();
-        let size = get_sequence_size(&mut reader);
-        assert_eq!(size, 3);
-        assert_eq!(reader.count(), 2);
+        let records = mixed_reader().collect::<Vec<_>>();
+        assert_eq!(get_sequence_size(&records), 5);
+        assert_eq!(get_sequence_size::<Record>(&[]), 0);
+    }
+
+    #[test]
+    fn mixed_length_entropy() {
+        // the longer guide is listed first and the shorter guides last
+        let entropy = positional_entropy(&mut mixed_reader());
+        assert_eq!(entropy.len(), 5);
+        assert!(entropy.iter().all(|e| e.is_finite()));
+        let mut reversed = mixed_reader().collect::<Vec<_>>();
+        reversed.reverse();
+        assert_eq!(positional_entropy(&mut reversed.into_iter()), entropy);
+        assert!(minimize_mse(&entropy, &positional_entropy(&mut offset_reader())).is_ok());
     }
 
     #[test]
     fn positional_counts() {
         let posmat = position_counts(&mut reader());
         let expected = ndarray::array![
-            [2.0, 0.0, 0.0, 0.0],
-            [0.0, 2.0, 0.0, 0.0],
-            [0.0, 1.0, 0.0, 1.0]
+            [3.0, 0.0, 0.0, 0.0],
+            [0.0, 3.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 2.0]
         ];
-        let diff = posmat - expected;
-        assert_eq!(diff.sum(), 0.);
+        assert_eq!(posmat, expected);
     }
 
     #[test]
@@ -299,6 +713,15 @@ mod test {
         assert_eq!(diff.sum(), 0.);
     }
 
+    #[test]
+    fn normalize_empty_row() {
+        let norm = normalize_counts(ndarray::array![[2.0, 0.0, 2.0, 0.0], [0.0, 0.0, 0.0, 0.0]]);
+        assert_eq!(
+            norm,
+            ndarray::array![[0.5, 0.0, 0.5, 0.0], [0.0, 0.0, 0.0, 0.0]]
+        );
+    }
+
     #[test]
     fn offset() {
         let mut reference = reader();
@@ -327,16 +750,99 @@ mod test {
         assert_eq!(index, 5);
     }
 
+    #[test]
+    fn rc_offset_both() {
+        let reference_entropy = positional_entropy(&mut reader());
+        let comparison_entropy = positional_entropy(&mut rc_offset_reader());
+        let offsets = minimize_mse_both(&reference_entropy, &comparison_entropy).unwrap();
+        assert_eq!(offsets.len(), 2);
+        assert_eq!(offsets[0], Offset::Reverse(5));
+        assert!(offsets[1].is_forward());
+    }
+
+    #[test]
+    fn offset_diagnostics() {
+        let reference_entropy = positional_entropy(&mut reader());
+        let comparison_entropy = positional_entropy(&mut offset_reader());
+        let diagnostics = OffsetDiagnostics::new(&reference_entropy, &comparison_entropy).unwrap();
+        assert_eq!(diagnostics.offset(), Offset::Forward(5));
+        assert_eq!(diagnostics.forward_mse().len(), 6);
+        assert_eq!(diagnostics.reverse_mse().len(), 6);
+        assert_eq!(diagnostics.library_entropy().len(), 3);
+        assert_eq!(diagnostics.sample_entropy().len(), 8);
+        let second = diagnostics.second_offset().unwrap();
+        assert_ne!(second, diagnostics.offset());
+        assert!(diagnostics.margin().unwrap() >= 0.0);
+    }
+
+    #[test]
+    fn offset_validation() {
+        let library = |sequence: &'static [u8]| {
+            Library::from_reader(Box::new(FastaReader::new(sequence))).unwrap()
+        };
+        let records = offset_reader().collect::<Vec<_>>();
+        let reference_entropy = positional_entropy(&mut reader());
+        let comparison_entropy = positional_entropy(&mut records.iter());
+        let mut diagnostics =
+            OffsetDiagnostics::new(&reference_entropy, &comparison_entropy).unwrap();
+        diagnostics.validate(&library(b">l.0\nACT\n>l.1\nACC\n"), &records, 3);
+        assert_eq!(diagnostics.rates().len(), 3);
+        assert_eq!(diagnostics.offset(), Offset::Forward(5));
+        assert_eq!(diagnostics.match_rate(), Some(1.0));
+        assert!(OffsetValidation::new(3, 0.5, true)
+            .check(&diagnostics, "offset")
+            .is_ok());
+
+        // records never matching the library fail a strict validation
+        diagnostics.validate(&library(b">l.0\nGGG\n"), &records, 3);
+        assert_eq!(diagnostics.match_rate(), Some(0.0));
+        assert!(OffsetValidation::new(3, 0.5, true)
+            .check(&diagnostics, "offset")
+            .is_err());
+        assert!(OffsetValidation::new(3, 0.5, false)
+            .check(&diagnostics, "offset")
+            .is_ok());
+
+        // without any trial mapped candidates the argmin is kept and not checked
+        diagnostics.validate(&library(b">l.0\nGGG\n"), &records, 0);
+        assert_eq!(diagnostics.match_rate(), None);
+        assert!(OffsetValidation::new(0, 0.5, true)
+            .check(&diagnostics, "offset")
+            .is_ok());
+    }
+
+    #[test]
+    fn offset_validation_overrides_argmin() {
+        // the library entropy matches the sample best at offset 5 but its
+        // sequences are only found at offset 1
+        let records = offset_reader().collect::<Vec<_>>();
+        let library =
+            Library::from_reader(Box::new(FastaReader::new(b">l.0\nACA\n" as &[u8]))).unwrap();
+        let reference_entropy = positional_entropy(&mut reader());
+        let comparison_entropy = positional_entropy(&mut records.iter());
+        let mut diagnostics =
+            OffsetDiagnostics::new(&reference_entropy, &comparison_entropy).unwrap();
+        diagnostics.validate(&library, &records, 12);
+        assert_eq!(diagnostics.argmin(), Offset::Forward(5));
+        assert_eq!(diagnostics.offset(), Offset::Forward(1));
+        assert_eq!(diagnostics.match_rate(), Some(1.0));
+
+        // the margin describes the MSE ranking regardless of the chosen offset
+        assert_ne!(diagnostics.second_offset().unwrap(), diagnostics.argmin());
+        assert!(diagnostics.margin().unwrap() >= 0.0);
+    }
+
     #[test]
     fn test_offset_enum() {
         let offset = Offset::Forward(5);
         assert_eq!(offset.index(), &5);
-        assert_eq!(offset.is_forward(), true);
-        assert_eq!(offset.is_reverse(), false);
+        assert!(offset.is_forward());
+        assert!(!offset.is_reverse());
         let offset = Offset::Reverse(5);
         assert_eq!(offset.index(), &5);
-        assert_eq!(offset.is_forward(), false);
-        assert_eq!(offset.is_reverse(), true);
+        assert!(!offset.is_forward());
+        assert!(offset.is_reverse());
+        assert_eq!(offset.flip(), Offset::Forward(5));
     }
 
     #[test]
@@ -353,11 +859,31 @@ mod test {
     fn test_position_counts_with_n() {
         let posmat = position_counts(&mut reader_with_n());
         let expected = ndarray::array![
-            [3.0, 0.0, 0.0, 0.0],
-            [0.0, 3.0, 0.0, 0.0],
-            [2.0, 1.0, 2.0, 1.0]
+            [4.0, 0.0, 0.0, 0.0],
+            [0.0, 4.0, 0.0, 0.0],
+            [1.0, 2.0, 1.0, 3.0]
         ];
-        let diff = posmat - expected;
-        assert_eq!(diff.sum(), 0.);
+        assert_eq!(posmat, expected);
+    }
+
+    #[test]
+    fn test_trial_offsets() {
+        let library =
+            Library::from_reader(Box::new(FastaReader::new(b">seq.0\nACT\n".as_slice()))).unwrap();
+        let staggered: &'static [u8] =
+            b">r.0\nGACTAA\n>r.1\nGACTAA\n>r.2\nGGACTA\n>r.3\nTAGTCC\n>r.4\nGGGGGG\n";
+        let (matches, total) = trial_matches(&library, &mut FastaReader::new(staggered));
+        assert_eq!(total, 5);
+        assert_eq!(matches[&Offset::Forward(1)], 2);
+        assert_eq!(matches[&Offset::Forward(2)], 1);
+        assert_eq!(matches[&Offset::Reverse(2)], 1);
+
+        let offsets = significant_offsets(&matches, total, 0.2);
+        assert_eq!(
+            offsets,
+            vec![Offset::Forward(1), Offset::Forward(2), Offset::Reverse(2)]
+        );
+        let offsets = significant_offsets(&matches, total, 0.3);
+        assert_eq!(offsets, vec![Offset::Forward(1)]);
     }
 }
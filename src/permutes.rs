@@ -31,9 +31,33 @@ type PermuteMap = HashMap<Vec<u8>, Vec<u8>>;
 ///  and be associated with their parent sequence (either `AC` or
 ///  `CG`).
 ///
+/// # Larger hamming distances
+/// Enumerating every neighbour beyond a single mismatch grows combinatorially,
+/// so sequences within a larger hamming distance are instead recovered at query
+/// time with a [`SeedIndex`]. A query is only assigned if a single parent sequence
+/// is nearest to it, and queries found in the `_null` are never reassigned.
+///
+/// # Variable length sequences
+/// Sequences of different lengths are never within a hamming distance of each
+/// other, so the parent sequences are grouped into length buckets. The one-off
+/// sequences of every bucket share the `map` (as they differ in length), while a
+/// [`SeedIndex`] and the insertion and deletion tables are built for each bucket.
 pub struct Permuter {
     map: PermuteMap,
     _null: NullSet,
+    seeds: Vec<SeedIndex>,
+    indels: Vec<Indels>,
+}
+
+/// Unambiguous single insertion and deletion sequences of the parent sequences
+/// of a single length. Deletions are one basepair shorter and insertions one
+/// basepair longer than their parents so each is held in its own table, and both
+/// share a `null` set of ambiguous sequences.
+struct Indels {
+    size: usize,
+    deletions: PermuteMap,
+    insertions: PermuteMap,
+    _null: NullSet,
 }
 
 impl Permuter {
@@ -46,14 +70,119 @@ impl Permuter {
     /// one-offs.
     pub fn new<'a>(sequences: impl Iterator<Item = &'a Vec<u8>>) -> Self {
         let (map, null) = Self::build(sequences);
-        Self { map, _null: null }
+        Self {
+            map,
+            _null: null,
+            seeds: Vec::new(),
+            indels: Vec::new(),
+        }
+    }
+
+    /// Initiates the algorithm to determine all unambiguous sequences within a maximum
+    /// hamming distance of the provided sequences.
+    ///
+    /// One-off sequences are built as in [`Permuter::new`] and if `max_mismatches` is
+    /// greater than one the sequences of each length are additionally indexed in a [`SeedIndex`].
+    /// If `max_mismatches` is zero no mismatched sequences are built.
+    pub fn with_mismatches<'a>(
+        sequences: impl Iterator<Item = &'a Vec<u8>>,
+        max_mismatches: usize,
+    ) -> Self {
+        let sequences = sequences.collect::<Vec<_>>();
+        let mut permuter = if max_mismatches > 0 {
+            Self::new(sequences.iter().copied())
+        } else {
+            Self::new(std::iter::empty())
+        };
+        if max_mismatches > 1 {
+            permuter.seeds = Self::buckets(sequences.into_iter())
+                .into_iter()
+                .map(|bucket| SeedIndex::new(bucket.into_iter(), max_mismatches))
+                .collect();
+        }
+        permuter
+    }
+
+    /// Additionally determines all unambiguous sequences with a single insertion
+    /// or deletion from the provided sequences of each length.
+    #[must_use]
+    pub fn with_indels<'a>(mut self, sequences: impl Iterator<Item = &'a Vec<u8>>) -> Self {
+        self.indels = Self::buckets(sequences)
+            .into_iter()
+            .map(|bucket| {
+                let size = bucket[0].len();
+                let (deletions, insertions, null) = Self::build_indels(bucket.into_iter());
+                Indels {
+                    size,
+                    deletions,
+                    insertions,
+                    _null: null,
+                }
+            })
+            .collect();
+        self
+    }
+
+    /// Returns `true` if insertion and deletion sequences were built
+    #[must_use]
+    pub fn has_indels(&self) -> bool {
+        !self.indels.is_empty()
+    }
+
+    /// Recovers the parent sequence of a token which is one basepair shorter
+    /// than its parent (i.e. a single deletion).
+    #[must_use]
+    pub fn contains_deletion(&self, token: &[u8]) -> Option<&Vec<u8>> {
+        self.indels
+            .iter()
+            .find(|x| x.size == token.len() + 1)
+            .and_then(|x| x.deletions.get(token))
+    }
+
+    /// Recovers the parent sequence of a token which is one basepair longer
+    /// than its parent (i.e. a single insertion).
+    #[must_use]
+    pub fn contains_insertion(&self, token: &[u8]) -> Option<&Vec<u8>> {
+        self.indels
+            .iter()
+            .find(|x| x.size + 1 == token.len())
+            .and_then(|x| x.insertions.get(token))
     }
 
     /// Publically exposes the internal [`HashMap`] to recover the parent sequence
-    /// of a potential permuted sequence.
+    /// of a potential permuted sequence. If a [`SeedIndex`] was built then
+    /// unambiguous sequences beyond a single mismatch are also recovered.
     #[must_use]
     pub fn contains(&self, token: &[u8]) -> Option<&Vec<u8>> {
-        self.map.get(token)
+        match self.map.get(token) {
+            Some(parent) => Some(parent),
+            None if self._null.contains(token) => None,
+            None => self.seeds.iter().find_map(|s| s.search(token)),
+        }
+    }
+
+    /// Returns `true` if the token is in the null set (i.e. it is a parent sequence or
+    /// equally near several parent sequences)
+    #[must_use]
+    pub fn is_ambiguous(&self, token: &[u8]) -> bool {
+        self._null.contains(token)
+    }
+
+    /// Generates all one-off permutations of a provided sequence
+    #[must_use]
+    pub fn one_off(sequence: &[u8]) -> Vec<Vec<u8>> {
+        Self::permute_sequence(sequence, LEXICON)
+    }
+
+    /// Groups sequences into buckets of equal length
+    fn buckets<'a>(sequences: impl Iterator<Item = &'a Vec<u8>>) -> Vec<Vec<&'a Vec<u8>>> {
+        sequences
+            .fold(HashMap::new(), |mut buckets, seq| {
+                buckets.entry(seq.len()).or_insert_with(Vec::new).push(seq);
+                buckets
+            })
+            .into_values()
+            .collect()
     }
 
     /// Main builder for the `map` and `_null` attributes.
@@ -74,6 +203,47 @@ impl Permuter {
             )
     }
 
+    /// Builder for the deletion and insertion tables and their shared `null` set.
+    /// The insertions and deletions of a single parent are deduplicated before
+    /// being folded in (i.e. deleting either base of a homopolymer gives the same
+    /// sequence), so only sequences shared between parents are ambiguous.
+    fn build_indels<'a>(
+        sequences: impl Iterator<Item = &'a Vec<u8>>,
+    ) -> (PermuteMap, PermuteMap, NullSet) {
+        let mut null = HashSet::new();
+        let mut deletions = HashMap::new();
+        let mut insertions = HashMap::new();
+        for seq in sequences {
+            Self::delete_sequence(seq)
+                .iter()
+                .for_each(|x| Self::insert_sequence(seq, x, &mut null, &mut deletions));
+            Self::extend_sequence(seq, LEXICON)
+                .iter()
+                .for_each(|x| Self::insert_sequence(seq, x, &mut null, &mut insertions));
+        }
+        (deletions, insertions, null)
+    }
+
+    /// Generates all unique sequences with a single basepair deleted
+    fn delete_sequence(sequence: &[u8]) -> HashSet<Vec<u8>> {
+        (0..sequence.len())
+            .map(|idx| Self::sequence_regions(sequence, idx))
+            .map(|(p, _, s)| [p, s].concat())
+            .collect()
+    }
+
+    /// Generates all unique sequences with a single basepair inserted
+    fn extend_sequence(sequence: &[u8], lexicon: [u8; 5]) -> HashSet<Vec<u8>> {
+        (0..=sequence.len())
+            .map(|idx| sequence.split_at(idx))
+            .flat_map(|(p, s)| {
+                lexicon
+                    .iter()
+                    .map(move |y| Self::build_permutation(p, s, *y))
+            })
+            .collect()
+    }
+
     /// Generates all possible sequence permutations for a provided sequence and lexicon.
     fn permute_sequence(sequence: &[u8], lexicon: [u8; 5]) -> Vec<Vec<u8>> {
         sequence
@@ -158,6 +328,128 @@ impl Permuter {
     }
 }
 
+/// Index of sequences split into non-overlapping segments used to find all
+/// sequences within a maximum hamming distance of a query.
+///
+/// By the pigeonhole principle a query within `k` mismatches of a sequence must
+/// match it exactly in at least one of `k + 1` segments, so only sequences sharing
+/// a segment with the query need to be compared. This keeps memory linear in the
+/// number of sequences rather than enumerating every neighbour.
+pub(crate) struct SeedIndex {
+    sequences: Vec<Vec<u8>>,
+    segments: Vec<(usize, usize)>,
+    tables: Vec<HashMap<Vec<u8>, Vec<usize>>>,
+    size: usize,
+    max_mismatches: usize,
+}
+impl SeedIndex {
+    /// Builds the segment tables for all sequences
+    pub(crate) fn new<'a>(
+        sequences: impl Iterator<Item = &'a Vec<u8>>,
+        max_mismatches: usize,
+    ) -> Self {
+        let sequences = sequences.cloned().collect::<Vec<_>>();
+        let size = sequences.first().map_or(0, Vec::len);
+        let segments = Self::segments(size, max_mismatches + 1);
+        let tables = segments
+            .iter()
+            .map(|(start, end)| {
+                sequences
+                    .iter()
+                    .enumerate()
+                    .fold(HashMap::new(), |mut table, (idx, seq)| {
+                        table
+                            .entry(seq[*start..*end].to_vec())
+                            .or_insert_with(Vec::new)
+                            .push(idx);
+                        table
+                    })
+            })
+            .collect();
+        Self {
+            sequences,
+            segments,
+            tables,
+            size,
+            max_mismatches,
+        }
+    }
+
+    /// Splits a sequence length into a number of contiguous segments of near equal size
+    fn segments(size: usize, num_segments: usize) -> Vec<(usize, usize)> {
+        let base = size / num_segments;
+        let remainder = size % num_segments;
+        (0..num_segments)
+            .scan(0, |start, idx| {
+                let end = *start + base + usize::from(idx < remainder);
+                let segment = (*start, end);
+                *start = end;
+                Some(segment)
+            })
+            .collect()
+    }
+
+    /// Calculates the hamming distance between two equal length sequences,
+    /// returning [`None`] as soon as it exceeds the maximum number of mismatches
+    fn distance(&self, x: &[u8], y: &[u8]) -> Option<usize> {
+        let mut distance = 0;
+        for (a, b) in x.iter().zip(y) {
+            if a != b {
+                distance += 1;
+                if distance > self.max_mismatches {
+                    return None;
+                }
+            }
+        }
+        Some(distance)
+    }
+
+    /// Returns every sequence within the maximum hamming distance of the query
+    /// alongside its distance
+    pub(crate) fn within(&self, token: &[u8]) -> Vec<(&Vec<u8>, usize)> {
+        if token.len() != self.size {
+            return Vec::new();
+        }
+        let mut visited = HashSet::new();
+        let mut neighbours = Vec::new();
+        for ((start, end), table) in self.segments.iter().zip(self.tables.iter()) {
+            let Some(candidates) = table.get(&token[*start..*end]) else {
+                continue;
+            };
+            for idx in candidates {
+                if !visited.insert(*idx) {
+                    continue;
+                }
+                if let Some(distance) = self.distance(token, &self.sequences[*idx]) {
+                    neighbours.push((&self.sequences[*idx], distance));
+                }
+            }
+        }
+        neighbours
+    }
+
+    /// Returns the unique nearest sequence within the maximum hamming distance
+    /// of the query or [`None`] if there are none or multiple at the same distance.
+    fn search(&self, token: &[u8]) -> Option<&Vec<u8>> {
+        let mut nearest: Option<(usize, &Vec<u8>)> = None;
+        let mut ambiguous = false;
+        for (sequence, distance) in self.within(token) {
+            match nearest {
+                Some((d, _)) if distance > d => {}
+                Some((d, _)) if distance == d => ambiguous = true,
+                _ => {
+                    nearest = Some((distance, sequence));
+                    ambiguous = false;
+                }
+            }
+        }
+        match nearest {
+            Some((_, sequence)) if !ambiguous => Some(sequence),
+            _ => None,
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
 
@@ -181,17 +473,17 @@ mod test {
     ///  All `~` demarcated are ambiguous between the two and should
     ///  be present in the null, alongside the origin sequence `AC`
     ///  and `CG`
-    use super::Permuter;
+    use super::{Permuter, SeedIndex};
 
     #[test]
     fn build() {
-        let sequences = vec![b"AC".to_vec(), b"CG".to_vec()];
+        let sequences = [b"AC".to_vec(), b"CG".to_vec()];
         Permuter::new(sequences.iter());
     }
 
     #[test]
     fn validate_singleton() {
-        let sequences = vec![b"ACTG".to_vec()];
+        let sequences = [b"ACTG".to_vec()];
         let permuter = Permuter::new(sequences.iter());
         let truth: Vec<Vec<u8>> = vec![
             b"AATG", b"ACGG", b"ACAG", b"TCTG", b"ACNG", b"NCTG", b"ACTA", b"GCTG", b"AGTG",
@@ -208,10 +500,10 @@ mod test {
 
     #[test]
     fn validate_positive() {
-        let sequences = vec![b"AC".to_vec(), b"CG".to_vec()];
+        let sequences = [b"AC".to_vec(), b"CG".to_vec()];
         let permuter = Permuter::new(sequences.iter());
 
-        let known_positives: Vec<Vec<u8>> = vec![
+        let known_positives: Vec<Vec<u8>> = [
             b"GC", b"TC", b"NC", b"AA", b"AT", b"AN", b"CA", b"CT", b"CN", b"GG", b"TG", b"NG",
         ]
         .iter()
@@ -232,10 +524,10 @@ mod test {
 
     #[test]
     fn validate_negative() {
-        let sequences = vec![b"AC".to_vec(), b"CG".to_vec()];
+        let sequences = [b"AC".to_vec(), b"CG".to_vec()];
         let permuter = Permuter::new(sequences.iter());
 
-        let known_negatives: Vec<Vec<u8>> = vec![b"AG", b"CG", b"CC", b"AG"]
+        let known_negatives: Vec<Vec<u8>> = [b"AG", b"CG", b"CC", b"AG"]
             .iter()
             .map(|s| s.to_vec())
             .collect();
@@ -251,4 +543,96 @@ mod test {
             .iter()
             .for_each(|x| assert!(!permuter.map.contains_key(x)));
     }
+
+    #[test]
+    fn seed_segments() {
+        assert_eq!(SeedIndex::segments(10, 3), vec![(0, 4), (4, 7), (7, 10)]);
+        assert_eq!(SeedIndex::segments(4, 2), vec![(0, 2), (2, 4)]);
+    }
+
+    #[test]
+    fn validate_two_mismatches() {
+        let sequences = [b"AAAAAAAA".to_vec(), b"CCCCCCCC".to_vec()];
+        let permuter = Permuter::with_mismatches(sequences.iter(), 2);
+
+        // one and two mismatches are recovered
+        assert_eq!(permuter.contains(b"AAAAAAAT").unwrap(), b"AAAAAAAA");
+        assert_eq!(permuter.contains(b"TAAAAAAT").unwrap(), b"AAAAAAAA");
+
+        // three mismatches are not
+        assert!(permuter.contains(b"TAAATAAT").is_none());
+
+        // single mismatch permuter is unaffected
+        let permuter = Permuter::new(sequences.iter());
+        assert!(permuter.contains(b"TAAAAAAT").is_none());
+    }
+
+    #[test]
+    fn validate_two_mismatches_ambiguous() {
+        let sequences = [b"AAAAAA".to_vec(), b"AAAACC".to_vec()];
+        let permuter = Permuter::with_mismatches(sequences.iter(), 2);
+
+        // equidistant to both parents
+        assert!(permuter.contains(b"AAAAGG").is_none());
+
+        // ambiguous one-offs remain ambiguous
+        assert!(permuter.contains(b"AAAAAC").is_none());
+
+        // nearest parent is unique
+        assert_eq!(permuter.contains(b"TTAAAA").unwrap(), b"AAAAAA");
+        assert_eq!(permuter.contains(b"GAAGCC").unwrap(), b"AAAACC");
+    }
+
+    #[test]
+    fn validate_indels() {
+        let sequences = [b"ACGTAC".to_vec(), b"TTGGCC".to_vec()];
+        let permuter = Permuter::new(sequences.iter()).with_indels(sequences.iter());
+        assert!(permuter.has_indels());
+
+        // single deletions
+        assert_eq!(permuter.contains_deletion(b"AGTAC").unwrap(), b"ACGTAC");
+        assert_eq!(permuter.contains_deletion(b"TTGCC").unwrap(), b"TTGGCC");
+
+        // single insertions
+        assert_eq!(permuter.contains_insertion(b"ACGTTAC").unwrap(), b"ACGTAC");
+        assert_eq!(permuter.contains_insertion(b"TTGAGCC").unwrap(), b"TTGGCC");
+
+        // insertions and deletions are not substitutions
+        assert!(permuter.contains(b"AGTAC").is_none());
+        assert!(permuter.contains_deletion(b"ACGTAC").is_none());
+    }
+
+    #[test]
+    fn validate_indels_ambiguous() {
+        let sequences = [b"AACC".to_vec(), b"ACCC".to_vec()];
+        let permuter = Permuter::with_mismatches(sequences.iter(), 0).with_indels(sequences.iter());
+
+        // shared deletion is ambiguous
+        assert!(permuter.contains_deletion(b"ACC").is_none());
+
+        // homopolymer deletion of a single parent is not ambiguous
+        assert_eq!(permuter.contains_deletion(b"AAC").unwrap(), b"AACC");
+
+        // no substitutions without mismatches
+        assert!(permuter.contains(b"AACG").is_none());
+    }
+
+    #[test]
+    fn validate_variable_lengths() {
+        let sequences = [b"AAAAAAAA".to_vec(), b"CCCCCCC".to_vec()];
+        let permuter = Permuter::with_mismatches(sequences.iter(), 2).with_indels(sequences.iter());
+
+        // mismatches are recovered within each length
+        assert_eq!(permuter.contains(b"AAAAAATT").unwrap(), b"AAAAAAAA");
+        assert_eq!(permuter.contains(b"CCCCCTT").unwrap(), b"CCCCCCC");
+
+        // the deletion of the longer sequence is not confused with the shorter
+        assert_eq!(permuter.contains_deletion(b"AAAAAAA").unwrap(), b"AAAAAAAA");
+        assert_eq!(permuter.contains_deletion(b"CCCCCC").unwrap(), b"CCCCCCC");
+        assert_eq!(
+            permuter.contains_insertion(b"CCCCCCCC").unwrap(),
+            b"CCCCCCC"
+        );
+        assert!(permuter.contains_deletion(b"CCCCCCC").is_none());
+    }
 }
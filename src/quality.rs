@@ -0,0 +1,111 @@
+/// Offset of the ASCII encoded Phred scores (Sanger / Illumina 1.8+)
+const PHRED_OFFSET: u8 = 33;
+
+/// Describes the base quality requirements of the guide window extracted from each read.
+///
+/// Qualities are only checked for records which have them (i.e. FASTQ), and are
+/// always taken from the same window (and orientation) as the matched guide.
+#[derive(Debug, Clone, Copy)]
+pub struct QualityFilter {
+    min_quality: Option<u8>,
+    mean_quality: Option<f64>,
+    mismatch_quality: Option<u8>,
+}
+impl QualityFilter {
+    /// Creates a new quality filter.
+    ///
+    /// Windows with any base below `min_quality` or with a mean quality below
+    /// `mean_quality` are rejected. If `mismatch_quality` is provided then a
+    /// mismatch is only tolerated at bases with a quality below it.
+    #[must_use]
+    pub fn new(
+        min_quality: Option<u8>,
+        mean_quality: Option<f64>,
+        mismatch_quality: Option<u8>,
+    ) -> Self {
+        Self {
+            min_quality,
+            mean_quality,
+            mismatch_quality,
+        }
+    }
+
+    /// Returns `true` if the window qualities meet the minimum and mean requirements
+    #[must_use]
+    pub fn passes(&self, qual: &[u8]) -> bool {
+        if qual.is_empty() {
+            return true;
+        }
+        if let Some(min) = self.min_quality {
+            if qual.iter().any(|q| Self::phred(*q) < min) {
+                return false;
+            }
+        }
+        if let Some(mean) = self.mean_quality {
+            let sum = qual.iter().map(|q| f64::from(Self::phred(*q))).sum::<f64>();
+            if sum / (qual.len() as f64) < mean {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Returns `true` if every mismatch between the observed token and its reference
+    /// is at a base whose quality is below the mismatch quality threshold
+    #[must_use]
+    pub fn allows_mismatch(&self, token: &[u8], reference: &[u8], qual: &[u8]) -> bool {
+        let Some(threshold) = self.mismatch_quality else {
+            return true;
+        };
+        if qual.len() != token.len() {
+            return true;
+        }
+        token
+            .iter()
+            .zip(reference)
+            .zip(qual)
+            .filter(|((x, y), _)| x != y)
+            .all(|(_, q)| Self::phred(*q) < threshold)
+    }
+
+    /// Converts an ASCII encoded quality score to its Phred score
+    #[inline]
+    fn phred(q: u8) -> u8 {
+        q.saturating_sub(PHRED_OFFSET)
+    }
+}
+
+#[cfg(test)]
+mod testing {
+    use super::QualityFilter;
+
+    #[test]
+    fn test_min_quality() {
+        let filter = QualityFilter::new(Some(20), None, None);
+        assert!(filter.passes(b"IIII"));
+        assert!(filter.passes(b"5555"));
+        assert!(!filter.passes(b"III#"));
+        assert!(filter.passes(b""));
+    }
+
+    #[test]
+    fn test_mean_quality() {
+        let filter = QualityFilter::new(None, Some(30.0), None);
+        // (40 + 40 + 40 + 2) / 4 = 30.5
+        assert!(filter.passes(b"III#"));
+        // (40 + 40 + 2 + 2) / 4 = 21
+        assert!(!filter.passes(b"II##"));
+    }
+
+    #[test]
+    fn test_allows_mismatch() {
+        let filter = QualityFilter::new(None, None, Some(20));
+        assert!(filter.allows_mismatch(b"ACTT", b"ACTG", b"III#"));
+        assert!(!filter.allows_mismatch(b"ACTT", b"ACTG", b"IIII"));
+        assert!(!filter.allows_mismatch(b"TCTT", b"ACTG", b"III#"));
+
+        // without a threshold any mismatch is allowed
+        let filter = QualityFilter::new(Some(20), None, None);
+        assert!(filter.allows_mismatch(b"ACTT", b"ACTG", b"IIII"));
+    }
+}
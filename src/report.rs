@@ -0,0 +1,435 @@
+use crate::offsetter::{entropy_profile, library_entropy_profile};
+use crate::{Counter, LibraryTable, Offset, SampleSummary, Subsample};
+use anyhow::Result;
+use std::{
+    fmt::Write as fmtWrite,
+    fs::File,
+    io::{BufWriter, Write},
+};
+
+/// Width of each chart in pixels
+const WIDTH: f64 = 480.0;
+
+/// Height of each chart in pixels
+const HEIGHT: f64 = 240.0;
+
+/// Margin around the plotting area of each chart in pixels
+const MARGIN: f64 = 40.0;
+
+/// Colors of the sample and library series
+const SAMPLE_COLOR: &str = "#2b6cb0";
+const LIBRARY_COLOR: &str = "#dd6b20";
+
+/// Describes where the static HTML report of a run is written and how
+/// many reads of each sample are used for its positional entropy profile.
+#[derive(Debug, Clone)]
+pub struct Report {
+    path: String,
+    table: LibraryTable,
+    subsample: Subsample,
+}
+impl Report {
+    /// Creates a new report written to `path`
+    #[must_use]
+    pub fn new(path: &str, table: &LibraryTable, subsample: Subsample) -> Self {
+        Self {
+            path: path.to_string(),
+            table: table.clone(),
+            subsample,
+        }
+    }
+
+    /// Renders the report of the counted samples over the library aliases
+    pub fn write(
+        &self,
+        input_paths: &[String],
+        results: &[Counter],
+        summaries: &[SampleSummary],
+        aliases: &[&Vec<u8>],
+    ) -> Result<()> {
+        let library_entropy = library_entropy_profile(&self.table)?;
+        let mut html = String::new();
+        write!(
+            html,
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>sgcount report</title>\n<style>{}</style>\n</head>\n<body>\n<h1>sgcount report</h1>\n",
+            STYLE
+        )?;
+
+        html.push_str("<h2>Mapping</h2>\n");
+        html.push_str(&summary_table(summaries));
+        let rates = summaries
+            .iter()
+            .map(|s| (s.name().to_string(), s.mapped_fraction()))
+            .collect::<Vec<_>>();
+        html.push_str(&bar_chart(&rates, 1.0, "Mapped fraction"));
+
+        html.push_str("<h2>Guide count distributions</h2>\n<div class=\"grid\">\n");
+        for (summary, counter) in summaries.iter().zip(results) {
+            let bins = histogram(&log_counts(counter, aliases));
+            writeln!(
+                html,
+                "<figure>{}<figcaption>{}</figcaption></figure>",
+                histogram_chart(&bins),
+                escape(summary.name())
+            )?;
+        }
+        html.push_str("</div>\n");
+
+        html.push_str("<h2>Positional entropy</h2>\n");
+        write!(
+            html,
+            "<p><span style=\"color:{}\">&#9632; sample</span> <span style=\"color:{}\">&#9632; library (at the chosen offset)</span></p>\n<div class=\"grid\">\n",
+            SAMPLE_COLOR, LIBRARY_COLOR
+        )?;
+        for (summary, path) in summaries.iter().zip(input_paths) {
+            let sample_entropy = entropy_profile(path, &self.subsample)?;
+            writeln!(
+                html,
+                "<figure>{}<figcaption>{}</figcaption></figure>",
+                entropy_chart(&sample_entropy, &library_entropy, summary.offset()),
+                escape(summary.name())
+            )?;
+        }
+        html.push_str("</div>\n");
+
+        if results.len() > 1 {
+            let logs = results
+                .iter()
+                .map(|c| log_counts(c, aliases))
+                .collect::<Vec<_>>();
+            let matrix = logs
+                .iter()
+                .map(|x| logs.iter().map(|y| pearson(x, y)).collect())
+                .collect::<Vec<Vec<f64>>>();
+            let names = summaries
+                .iter()
+                .map(SampleSummary::name)
+                .collect::<Vec<_>>();
+            html.push_str("<h2>Replicate correlation</h2>\n");
+            html.push_str("<p>Pearson correlation of log2(count + 1) across guides</p>\n");
+            html.push_str(&heatmap(&names, &matrix));
+        }
+
+        html.push_str("</body>\n</html>\n");
+        let mut writer = BufWriter::new(File::create(&self.path)?);
+        writer.write_all(html.as_bytes())?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Stylesheet embedded in the report
+const STYLE: &str = "body{font-family:sans-serif;margin:2em;color:#222}\
+table{border-collapse:collapse}td,th{border:1px solid #ccc;padding:4px 8px;text-align:right}\
+th{background:#f4f4f4}.grid{display:flex;flex-wrap:wrap}figure{margin:0.5em}\
+figcaption{text-align:center}svg text{font-size:11px}";
+
+/// Escapes a string for use in HTML
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Renders the run summary of every sample as a table
+fn summary_table(summaries: &[SampleSummary]) -> String {
+    let mut table = String::from(
+        "<table>\n<tr><th>Sample</th><th>Total reads</th><th>Matched reads</th><th>Mapped</th><th>Offset</th><th>Direction</th><th>Zero-count guides</th><th>Gini index</th><th>90/10 skew</th></tr>\n",
+    );
+    for s in summaries {
+        let (offset, direction) = match s.offset() {
+            Some(o) if o.is_forward() => (o.index().to_string(), "forward"),
+            Some(o) => (o.index().to_string(), "reverse"),
+            None => (String::from("NA"), "NA"),
+        };
+        writeln!(
+            table,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{:.1}%</td><td>{}</td><td>{}</td><td>{}</td><td>{:.3}</td><td>{}</td></tr>",
+            escape(s.name()),
+            s.total_reads(),
+            s.matched_reads(),
+            100.0 * s.mapped_fraction(),
+            offset,
+            direction,
+            s.zero_count_guides(),
+            s.gini_index(),
+            s.skew_ratio().map_or(String::from("NA"), |x| format!("{:.2}", x)),
+        )
+        .expect("unable to write to string");
+    }
+    table.push_str("</table>\n");
+    table
+}
+
+/// Calculates the log2(count + 1) of every library alias in a sample
+fn log_counts(counter: &Counter, aliases: &[&Vec<u8>]) -> Vec<f64> {
+    aliases
+        .iter()
+        .map(|alias| (*counter.get_value(alias) as f64 + 1.0).log2())
+        .collect()
+}
+
+/// Bins log2 counts into unit-width bins starting at zero
+fn histogram(logs: &[f64]) -> Vec<usize> {
+    let size = logs.iter().fold(0.0_f64, |acc, x| acc.max(*x)).floor() as usize + 1;
+    logs.iter().fold(vec![0; size], |mut bins, x| {
+        bins[x.floor() as usize] += 1;
+        bins
+    })
+}
+
+/// Calculates the Pearson correlation of two equally sized arrays.
+/// This is undefined (NaN) if either array is constant.
+fn pearson(x: &[f64], y: &[f64]) -> f64 {
+    let n = x.len() as f64;
+    let (mean_x, mean_y) = (x.iter().sum::<f64>() / n, y.iter().sum::<f64>() / n);
+    let (cov, var_x, var_y) = x
+        .iter()
+        .zip(y)
+        .fold((0.0, 0.0, 0.0), |(c, vx, vy), (a, b)| {
+            let (dx, dy) = (a - mean_x, b - mean_y);
+            (c + dx * dy, vx + dx * dx, vy + dy * dy)
+        });
+    cov / (var_x * var_y).sqrt()
+}
+
+/// Opens an SVG of the chart size with its axes drawn
+fn open_chart(x_label: &str, y_label: &str) -> String {
+    format!(
+        "<svg width=\"{w}\" height=\"{h}\" xmlns=\"http://www.w3.org/2000/svg\">\
+<line x1=\"{m}\" y1=\"{b}\" x2=\"{r}\" y2=\"{b}\" stroke=\"#222\"/>\
+<line x1=\"{m}\" y1=\"{m}\" x2=\"{m}\" y2=\"{b}\" stroke=\"#222\"/>\
+<text x=\"{cx}\" y=\"{lx}\" text-anchor=\"middle\">{xl}</text>\
+<text x=\"12\" y=\"{cy}\" text-anchor=\"middle\" transform=\"rotate(-90 12 {cy})\">{yl}</text>",
+        w = WIDTH,
+        h = HEIGHT,
+        m = MARGIN,
+        r = WIDTH - MARGIN,
+        b = HEIGHT - MARGIN,
+        cx = WIDTH / 2.0,
+        cy = HEIGHT / 2.0,
+        lx = HEIGHT - 8.0,
+        xl = escape(x_label),
+        yl = escape(y_label),
+    )
+}
+
+/// Writes the label of the maximum of the y axis
+fn y_max_label(svg: &mut String, y_max: f64) {
+    write!(
+        svg,
+        "<text x=\"{}\" y=\"{}\" text-anchor=\"end\">{}</text>",
+        MARGIN - 4.0,
+        MARGIN + 4.0,
+        y_max
+    )
+    .expect("unable to write to string");
+}
+
+/// Renders a bar chart of labelled values between zero and `y_max`
+fn bar_chart(values: &[(String, f64)], y_max: f64, y_label: &str) -> String {
+    let mut svg = open_chart("", y_label);
+    let (width, height) = (WIDTH - 2.0 * MARGIN, HEIGHT - 2.0 * MARGIN);
+    let step = width / values.len().max(1) as f64;
+    for (idx, (label, value)) in values.iter().enumerate() {
+        let bar = height * (value / y_max).clamp(0.0, 1.0);
+        let x = MARGIN + idx as f64 * step;
+        write!(
+            svg,
+            "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"{}\"><title>{}: {:.3}</title></rect>\
+<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\">{}</text>",
+            x + 0.1 * step,
+            MARGIN + height - bar,
+            0.8 * step,
+            bar,
+            SAMPLE_COLOR,
+            escape(label),
+            value,
+            x + 0.5 * step,
+            HEIGHT - MARGIN + 14.0,
+            escape(label),
+        )
+        .expect("unable to write to string");
+    }
+    y_max_label(&mut svg, y_max);
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// Renders a histogram of the guides in each log2 count bin
+fn histogram_chart(bins: &[usize]) -> String {
+    let mut svg = open_chart("log2(count + 1)", "Guides");
+    let (width, height) = (WIDTH - 2.0 * MARGIN, HEIGHT - 2.0 * MARGIN);
+    let y_max = bins.iter().max().copied().unwrap_or(0).max(1) as f64;
+    let step = width / bins.len().max(1) as f64;
+    for (idx, count) in bins.iter().enumerate() {
+        let bar = height * *count as f64 / y_max;
+        write!(
+            svg,
+            "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"{}\"><title>[{}, {}): {}</title></rect>\
+<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\">{}</text>",
+            MARGIN + idx as f64 * step,
+            MARGIN + height - bar,
+            step.max(1.0) - 1.0,
+            bar,
+            SAMPLE_COLOR,
+            idx,
+            idx + 1,
+            count,
+            MARGIN + idx as f64 * step,
+            HEIGHT - MARGIN + 14.0,
+            idx,
+        )
+        .expect("unable to write to string");
+    }
+    y_max_label(&mut svg, y_max);
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// Renders a polyline of the points in the chart coordinates
+fn polyline(points: &[(f64, f64)], x_max: f64, y_max: f64, color: &str) -> String {
+    let (width, height) = (WIDTH - 2.0 * MARGIN, HEIGHT - 2.0 * MARGIN);
+    let coordinates = points
+        .iter()
+        .map(|(x, y)| {
+            format!(
+                "{:.1},{:.1}",
+                MARGIN + width * x / x_max,
+                MARGIN + height * (1.0 - y / y_max)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    format!(
+        "<polyline points=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"1.5\"/>",
+        coordinates, color
+    )
+}
+
+/// Renders the positional entropy of a sample alongside the library entropy
+/// placed at the chosen offset (reversed if the offset is in the reverse direction)
+fn entropy_chart(sample: &[f64], library: &[f64], offset: Option<Offset>) -> String {
+    let mut svg = open_chart("Position", "Entropy");
+    let x_max = sample.len().saturating_sub(1).max(1) as f64;
+    let y_max = 4.0_f64.ln();
+    let points = sample
+        .iter()
+        .enumerate()
+        .map(|(idx, e)| (idx as f64, *e))
+        .collect::<Vec<_>>();
+    svg.push_str(&polyline(&points, x_max, y_max, SAMPLE_COLOR));
+    if let Some(offset) = offset {
+        let points = library
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, e)| {
+                let position = match offset {
+                    Offset::Forward(x) => x + idx,
+                    Offset::Reverse(x) => sample.len().checked_sub(1 + x + idx)?,
+                };
+                Some((position as f64, *e))
+            })
+            .filter(|(position, _)| *position <= x_max)
+            .collect::<Vec<_>>();
+        svg.push_str(&polyline(&points, x_max, y_max, LIBRARY_COLOR));
+    }
+    write!(
+        svg,
+        "<text x=\"{}\" y=\"{}\" text-anchor=\"end\">{}</text>",
+        WIDTH - MARGIN,
+        HEIGHT - MARGIN + 14.0,
+        sample.len()
+    )
+    .expect("unable to write to string");
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// Renders a labelled heatmap of a square correlation matrix
+fn heatmap(names: &[&str], matrix: &[Vec<f64>]) -> String {
+    let cell = 48.0;
+    let label = 120.0;
+    let size = label + cell * names.len() as f64;
+    let mut svg = format!(
+        "<svg width=\"{:.0}\" height=\"{:.0}\" xmlns=\"http://www.w3.org/2000/svg\">",
+        size, size
+    );
+    for (idx, name) in names.iter().enumerate() {
+        let center = label + cell * (idx as f64 + 0.5);
+        write!(
+            svg,
+            "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"end\">{}</text>\
+<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"start\" transform=\"rotate(-45 {:.1} {:.1})\">{}</text>",
+            label - 4.0,
+            center + 4.0,
+            escape(name),
+            center,
+            label - 4.0,
+            center,
+            label - 4.0,
+            escape(name),
+        )
+        .expect("unable to write to string");
+    }
+    for (i, row) in matrix.iter().enumerate() {
+        for (j, r) in row.iter().enumerate() {
+            let (fill, text) = if r.is_finite() {
+                // white at or below zero to blue at one
+                let t = r.clamp(0.0, 1.0);
+                let channel = |c: f64| (255.0 - t * (255.0 - c)).round() as u8;
+                (
+                    format!(
+                        "#{:02x}{:02x}{:02x}",
+                        channel(43.0),
+                        channel(108.0),
+                        channel(176.0)
+                    ),
+                    format!("{:.2}", r),
+                )
+            } else {
+                (String::from("#dddddd"), String::from("NA"))
+            };
+            write!(
+                svg,
+                "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"{}\" stroke=\"#fff\"/>\
+<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\">{}</text>",
+                label + cell * j as f64,
+                label + cell * i as f64,
+                cell,
+                cell,
+                fill,
+                label + cell * (j as f64 + 0.5),
+                label + cell * (i as f64 + 0.5) + 4.0,
+                text,
+            )
+            .expect("unable to write to string");
+        }
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+#[cfg(test)]
+mod testing {
+    use super::{escape, histogram, pearson};
+
+    #[test]
+    fn test_histogram() {
+        let bins = histogram(&[0.0, 1.0, 1.5, 3.2]);
+        assert_eq!(bins, vec![1, 2, 0, 1]);
+    }
+
+    #[test]
+    fn test_pearson() {
+        assert!((pearson(&[1.0, 2.0, 3.0], &[2.0, 4.0, 6.0]) - 1.0).abs() < 1e-9);
+        assert!((pearson(&[1.0, 2.0, 3.0], &[3.0, 2.0, 1.0]) + 1.0).abs() < 1e-9);
+        assert!(pearson(&[1.0, 1.0, 1.0], &[1.0, 2.0, 3.0]).is_nan());
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("a<b>&\"c\""), "a&lt;b&gt;&amp;&quot;c&quot;");
+    }
+}
@@ -1,9 +1,14 @@
-use crate::{Counter, GeneMap, Library};
+use crate::{
+    CellCounter, Counter, DualLibrary, GeneMap, Library, LibraryDesign, MatchType, Metadata,
+    MissReason, Offset, OffsetDiagnostics, SampleSummary,
+};
 use anyhow::Result;
+use hashbrown::{HashMap, HashSet};
 use std::{
     fmt::Write as fmtWrite,
-    fs::File,
-    io::{stdout, Write},
+    fs::{create_dir_all, File},
+    io::{stdout, BufWriter, Write},
+    path::{Path, PathBuf},
 };
 
 /// Writes the results to stdout / path
@@ -29,7 +34,8 @@ fn match_output(path: Option<String>) -> Result<Box<dyn Write>> {
 }
 
 /// Creates a Tab Delim String from a List of Names
-fn generate_columns(names: &[String], genemap: &Option<GeneMap>) -> String {
+/// If UMIs are reported each sample is followed by its deduplicated column
+fn generate_columns(names: &[String], genemap: &Option<GeneMap>, umi: bool) -> String {
     names
         .iter()
         .enumerate()
@@ -38,6 +44,9 @@ fn generate_columns(names: &[String], genemap: &Option<GeneMap>) -> String {
                 write!(s, "\tGene").expect("unable to write to string");
             }
             write!(s, "\t{}", x).expect("unable to write to string");
+            if umi {
+                write!(s, "\t{}_umi", x).expect("unable to write to string");
+            }
             s
         })
 }
@@ -66,8 +75,66 @@ fn append_count(alias: &[u8], counter: &Counter, accum: &mut String) {
     write!(accum, "\t{}", counter.get_value(alias)).expect("unable to write to string");
 }
 
+/// appends a samples deduplicated UMI count for a provided alias to the growing string
+fn append_umi_count(alias: &[u8], counter: &Counter, accum: &mut String) {
+    write!(accum, "\t{}", counter.get_umi_value(alias)).expect("unable to write to string");
+}
+
+/// Builds the row of counts across all samples for a provided alias
+/// and returns [`None`] if the row is empty and zeros are not included
+fn build_row(
+    alias: &[u8],
+    results: &[Counter],
+    genemap: &Option<GeneMap>,
+    include_zero: bool,
+    umi: bool,
+) -> Option<String> {
+    let mut total_alias_count = 0;
+    let accum = results.iter().enumerate().fold(
+        String::from_utf8(alias.to_vec()).expect("invalid utf8"),
+        |mut accum, (idx, x)| {
+            append_gene(alias, genemap, idx, &mut accum);
+            append_count(alias, x, &mut accum);
+            if umi {
+                append_umi_count(alias, x, &mut accum);
+            }
+            total_alias_count += x.get_value(alias);
+            accum
+        },
+    );
+    if include_zero || total_alias_count > 0 {
+        Some(accum)
+    } else {
+        None
+    }
+}
+
+/// Builds the row of counts across all samples for a recombined guide pair.
+/// Recombined pairs have no parent gene so are given an `NA` gene if a gene map
+/// is provided. Recombined pairs are not deduplicated so are given a zero UMI count
+/// if UMIs are reported.
+fn build_recombined_row(
+    alias: &[u8],
+    results: &[Counter],
+    genemap: &Option<GeneMap>,
+    umi: bool,
+) -> String {
+    let mut accum = String::from_utf8(alias.to_vec()).expect("invalid utf8");
+    if genemap.is_some() {
+        accum.push_str("\tNA");
+    }
+    results.iter().for_each(|x| {
+        write!(accum, "\t{}", x.get_recombined_value(alias)).expect("unable to write to string");
+        if umi {
+            accum.push_str("\t0");
+        }
+    });
+    accum
+}
+
 /// Writes the results dataframe either to the provided path
-/// or to stdout
+/// or to stdout. If `umi` is set the deduplicated UMI counts of each
+/// sample are reported alongside the raw counts.
 pub fn write_results(
     path: Option<String>,
     results: &[Counter],
@@ -75,32 +142,538 @@ pub fn write_results(
     names: &[String],
     genemap: &Option<GeneMap>,
     include_zero: bool,
+    umi: bool,
 ) -> Result<()> {
-    let iterable = library.values().filter_map(|alias| {
-        let mut total_alias_count = 0;
-        let accum = results.iter().enumerate().fold(
-            String::from_utf8(alias.clone()).expect("invalid utf8"),
-            |mut accum, (idx, x)| {
-                append_gene(alias, genemap, idx, &mut accum);
-                append_count(alias, x, &mut accum);
-                total_alias_count += x.get_value(alias);
-                accum
-            },
-        );
-        if include_zero || total_alias_count > 0 {
-            Some(accum)
-        } else {
-            None
+    let iterable = library
+        .values()
+        .filter_map(|alias| build_row(alias, results, genemap, include_zero, umi));
+
+    let columns = generate_columns(names, genemap, umi);
+    write(path, iterable, &columns)
+}
+
+/// Writes the number of reads assigned at each offset for every sample as a
+/// tab-delimited table to the provided path
+pub fn write_offset_summary(
+    path: &str,
+    results: &[Counter],
+    names: &[String],
+    metadata: &Metadata,
+) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(
+        writer,
+        "Sample{}\tDirection\tOffset\tReads\tFraction",
+        metadata.header()
+    )?;
+    for (idx, (name, counter)) in names.iter().zip(results).enumerate() {
+        for (offset, count) in counter.offset_counts() {
+            let direction = if offset.is_forward() {
+                "forward"
+            } else {
+                "reverse"
+            };
+            writeln!(
+                writer,
+                "{}{}\t{}\t{}\t{}\t{:.3}",
+                name,
+                metadata.row(idx),
+                direction,
+                offset.index(),
+                count,
+                count as f64 / counter.matched_reads().max(1) as f64
+            )?;
         }
-    });
+    }
+    Ok(())
+}
+
+/// Writes the chosen offset of every sample and its exact match rate alongside the
+/// second best offset and the margin in MSE between them to the provided path or stdout
+pub fn write_offsets(
+    path: Option<String>,
+    diagnostics: &[OffsetDiagnostics],
+    names: &[String],
+) -> Result<()> {
+    let mut writer = BufWriter::new(match_output(path)?);
+    writeln!(
+        writer,
+        "Sample\tOffset\tDirection\tMSE\tMatchRate\tSecondOffset\tSecondDirection\tSecondMSE\tMargin"
+    )?;
+    for (name, d) in names.iter().zip(diagnostics) {
+        let (direction, _) = offset_fields(Some(d.offset()));
+        let (second_direction, second_offset) = offset_fields(d.second_offset());
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{:.6}\t{}\t{}\t{}\t{}\t{}",
+            name,
+            d.offset().index(),
+            direction,
+            d.mse(d.offset()),
+            d.match_rate()
+                .map_or(String::from("NA"), |r| format!("{:.6}", r)),
+            second_offset.map_or(String::from("NA"), |o| o.to_string()),
+            second_direction,
+            d.second_offset()
+                .map_or(String::from("NA"), |o| format!("{:.6}", d.mse(o))),
+            d.margin()
+                .map_or(String::from("NA"), |m| format!("{:.6}", m)),
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes the design of every library guide to the provided path or stdout: the
+/// number of guides at each hamming distance, the nearest guide, the ambiguous share
+/// of the one-mismatch neighbourhood, reverse complement collisions, the longest
+/// homopolymer, and the GC content
+pub fn write_library_design(path: Option<String>, design: &LibraryDesign) -> Result<()> {
+    let mut writer = BufWriter::new(match_output(path)?);
+    writeln!(
+        writer,
+        "Guide\tSequence\tDistance1\tDistance2\tDistance3\tNearestGuide\tNearestDistance\tOneOffs\tAmbiguousOneOffs\tAmbiguousFraction\tReverseComplement\tHomopolymer\tGC"
+    )?;
+    for g in design.guides() {
+        let (nearest, distance) = g
+            .nearest()
+            .map_or((String::from("NA"), String::from("NA")), |(a, d)| {
+                (String::from_utf8_lossy(a).to_string(), d.to_string())
+            });
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{:.6}\t{}\t{}\t{:.6}",
+            String::from_utf8_lossy(g.alias()),
+            String::from_utf8_lossy(g.sequence()),
+            g.neighbours(1),
+            g.neighbours(2),
+            g.neighbours(3),
+            nearest,
+            distance,
+            g.one_offs(),
+            g.ambiguous_one_offs(),
+            g.ambiguous_fraction(),
+            g.reverse_complement()
+                .map_or(String::from("NA"), |a| String::from_utf8_lossy(a)
+                    .to_string()),
+            g.homopolymer(),
+            g.gc_content(),
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes the positional entropy vectors and windowed MSE curves of every sample
+/// as a long tab-delimited table to the provided path
+pub fn write_offset_diagnostics(
+    path: &str,
+    diagnostics: &[OffsetDiagnostics],
+    names: &[String],
+) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "Sample\tProfile\tPosition\tValue")?;
+    for (name, d) in names.iter().zip(diagnostics) {
+        let profiles = [
+            ("library_entropy", d.library_entropy()),
+            ("sample_entropy", d.sample_entropy()),
+            ("forward_mse", d.forward_mse()),
+            ("reverse_mse", d.reverse_mse()),
+        ];
+        for (profile, values) in profiles {
+            for (position, value) in values.iter().enumerate() {
+                writeln!(writer, "{}\t{}\t{}\t{:.6}", name, profile, position, value)?;
+            }
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes the number of cells and reads of every single-cell sample as a
+/// tab-delimited table to the provided path
+pub fn write_cell_summary(
+    path: &str,
+    results: &[CellCounter],
+    names: &[String],
+    metadata: &Metadata,
+) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(
+        writer,
+        "Sample{}\tCells\tTotalReads\tBarcodedReads\tMatchedReads\tFractionMapped",
+        metadata.header()
+    )?;
+    for (idx, (name, counter)) in names.iter().zip(results).enumerate() {
+        writeln!(
+            writer,
+            "{}{}\t{}\t{}\t{}\t{}\t{:.3}",
+            name,
+            metadata.row(idx),
+            counter.cells().len(),
+            counter.total_reads(),
+            counter.barcoded_reads(),
+            counter.matched_reads(),
+            counter.fraction_mapped()
+        )?;
+    }
+    Ok(())
+}
+
+/// Writes the number of reads of every sample assigned by each [`MatchType`] and
+/// missed for each [`MissReason`] alongside the number of assigned reads with an
+/// invalid UMI as a tab-delimited table to the provided path
+pub fn write_qc_summary(
+    path: &str,
+    results: &[Counter],
+    names: &[String],
+    metadata: &Metadata,
+) -> Result<()> {
+    let match_types = [
+        MatchType::Exact,
+        MatchType::Mismatch,
+        MatchType::PositionPlus,
+        MatchType::PositionMinus,
+        MatchType::Indel,
+    ];
+    let reasons = [
+        MissReason::LowQuality,
+        MissReason::OutOfBounds,
+        MissReason::Ambiguous,
+        MissReason::NoMatch,
+        MissReason::Recombined,
+    ];
+    let mut writer = BufWriter::new(File::create(path)?);
+    write!(
+        writer,
+        "Sample{}\tTotalReads\tMatchedReads",
+        metadata.header()
+    )?;
+    for m in &match_types {
+        write!(writer, "\t{}", m)?;
+    }
+    for r in &reasons {
+        write!(writer, "\t{}", r)?;
+    }
+    writeln!(writer, "\tinvalid_umi")?;
+    for (idx, (name, counter)) in names.iter().zip(results).enumerate() {
+        write!(
+            writer,
+            "{}{}\t{}\t{}",
+            name,
+            metadata.row(idx),
+            counter.total_reads(),
+            counter.matched_reads()
+        )?;
+        for m in &match_types {
+            write!(writer, "\t{}", counter.match_count(*m))?;
+        }
+        for r in &reasons {
+            write!(writer, "\t{}", counter.miss_count(*r))?;
+        }
+        writeln!(writer, "\t{}", counter.invalid_umi_reads())?;
+    }
+    Ok(())
+}
+
+/// Formats a float as a JSON number, or `null` if it is undefined
+fn json_number(x: Option<f64>) -> String {
+    match x {
+        Some(x) if x.is_finite() => format!("{:.6}", x),
+        _ => String::from("null"),
+    }
+}
+
+/// Escapes a string for use in JSON
+fn json_string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    escaped.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => {
+                write!(escaped, "\\u{:04x}", c as u32).expect("unable to write to string");
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+/// Returns the direction and index of an optional offset as strings
+fn offset_fields(offset: Option<Offset>) -> (&'static str, Option<usize>) {
+    match offset {
+        Some(o) if o.is_forward() => ("forward", Some(*o.index())),
+        Some(o) => ("reverse", Some(*o.index())),
+        None => ("NA", None),
+    }
+}
 
-    let columns = generate_columns(names, genemap);
+/// Appends a suffix to the file name of a path prefix, keeping any extension
+fn suffixed(prefix: &Path, suffix: &str) -> PathBuf {
+    let mut path = prefix.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// Writes the run summary of every sample as JSON to `{prefix}.summary.json`
+/// and as a tab-delimited table to `{prefix}.summary.tsv`
+pub fn write_summary(
+    prefix: &Path,
+    summaries: &[SampleSummary],
+    metadata: &Metadata,
+) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(suffixed(prefix, ".summary.json"))?);
+    writeln!(writer, "{{\n  \"samples\": [")?;
+    for (idx, s) in summaries.iter().enumerate() {
+        let (direction, offset) = offset_fields(s.offset());
+        writeln!(writer, "    {{")?;
+        writeln!(writer, "      \"sample\": {},", json_string(s.name()))?;
+        if !metadata.columns().is_empty() {
+            let fields = metadata
+                .columns()
+                .iter()
+                .zip(metadata.values(idx))
+                .map(|(c, v)| format!("{}: {}", json_string(c), json_string(v)))
+                .collect::<Vec<_>>();
+            writeln!(writer, "      \"metadata\": {{{}}},", fields.join(", "))?;
+        }
+        writeln!(writer, "      \"total_reads\": {},", s.total_reads())?;
+        writeln!(writer, "      \"matched_reads\": {},", s.matched_reads())?;
+        writeln!(
+            writer,
+            "      \"mapped_fraction\": {},",
+            json_number(Some(s.mapped_fraction()))
+        )?;
+        writeln!(
+            writer,
+            "      \"invalid_umi_reads\": {},",
+            s.invalid_umi_reads()
+        )?;
+        writeln!(
+            writer,
+            "      \"offset\": {},",
+            offset.map_or(String::from("null"), |o| o.to_string())
+        )?;
+        writeln!(
+            writer,
+            "      \"direction\": {},",
+            offset.map_or(String::from("null"), |_| json_string(direction))
+        )?;
+        writeln!(writer, "      \"guides\": {},", s.guides())?;
+        writeln!(
+            writer,
+            "      \"zero_count_guides\": {},",
+            s.zero_count_guides()
+        )?;
+        writeln!(
+            writer,
+            "      \"gini_index\": {},",
+            json_number(Some(s.gini_index()))
+        )?;
+        writeln!(
+            writer,
+            "      \"skew_ratio\": {},",
+            json_number(s.skew_ratio())
+        )?;
+        writeln!(
+            writer,
+            "      \"reads_per_guide\": {}",
+            json_number(Some(s.reads_per_guide()))
+        )?;
+        let separator = if idx + 1 < summaries.len() { "," } else { "" };
+        writeln!(writer, "    }}{}", separator)?;
+    }
+    writeln!(writer, "  ]\n}}")?;
+    writer.flush()?;
+
+    let mut writer = BufWriter::new(File::create(suffixed(prefix, ".summary.tsv"))?);
+    writeln!(
+        writer,
+        "Sample{}\tTotalReads\tMatchedReads\tMappedFraction\tInvalidUmiReads\tOffset\tDirection\tGuides\tZeroCountGuides\tGiniIndex\tSkewRatio\tReadsPerGuide",
+        metadata.header()
+    )?;
+    for (idx, s) in summaries.iter().enumerate() {
+        let (direction, offset) = offset_fields(s.offset());
+        writeln!(
+            writer,
+            "{}{}\t{}\t{}\t{:.6}\t{}\t{}\t{}\t{}\t{}\t{:.6}\t{}\t{:.6}",
+            s.name(),
+            metadata.row(idx),
+            s.total_reads(),
+            s.matched_reads(),
+            s.mapped_fraction(),
+            s.invalid_umi_reads(),
+            offset.map_or(String::from("NA"), |o| o.to_string()),
+            direction,
+            s.guides(),
+            s.zero_count_guides(),
+            s.gini_index(),
+            s.skew_ratio()
+                .map_or(String::from("NA"), |x| format!("{:.6}", x)),
+            s.reads_per_guide()
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes the most frequent window sequences of the unmapped reads for every sample
+/// as a tab-delimited table ranked by their count. Reads too short to extract a
+/// window are reported as `NA`.
+pub fn write_unmapped_summary(
+    path: &Path,
+    results: &[Counter],
+    names: &[String],
+    top: usize,
+) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "Sample\tRank\tSequence\tReads\tFraction")?;
+    for (name, counter) in names.iter().zip(results) {
+        let unmapped = counter.total_reads() - counter.matched_reads();
+        for (rank, (sequence, count)) in counter
+            .top_unmapped(top)
+            .unwrap_or_default()
+            .into_iter()
+            .enumerate()
+        {
+            let sequence = if sequence.is_empty() {
+                "NA"
+            } else {
+                std::str::from_utf8(sequence)?
+            };
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}\t{:.3}",
+                name,
+                rank + 1,
+                sequence,
+                count,
+                count as f64 / unmapped.max(1) as f64
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes the dual-guide construct results dataframe either to the provided path
+/// or to stdout. Recombined guide pairs observed in any sample are optionally
+/// appended after the designed constructs.
+pub fn write_dual_results(
+    path: Option<String>,
+    results: &[Counter],
+    library: &DualLibrary,
+    names: &[String],
+    genemap: &Option<GeneMap>,
+    include_zero: bool,
+    include_recombined: bool,
+) -> Result<()> {
+    let umi = results.iter().any(Counter::has_umis);
+    let mut recombined = if include_recombined {
+        results
+            .iter()
+            .flat_map(|x| x.recombined())
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>()
+    } else {
+        Vec::new()
+    };
+    recombined.sort_unstable();
+
+    let iterable = library
+        .aliases()
+        .filter_map(|alias| build_row(alias, results, genemap, include_zero, umi))
+        .chain(
+            recombined
+                .into_iter()
+                .map(|alias| build_recombined_row(alias, results, genemap, umi)),
+        );
+
+    let columns = generate_columns(names, genemap, umi);
     write(path, iterable, &columns)
 }
 
+/// Writes the single-cell results as a sparse cell-by-guide matrix in the
+/// Matrix Market format (`matrix.mtx`) alongside the row (`barcodes.tsv`)
+/// and column (`features.tsv`) labels into the provided directory.
+pub fn write_cell_results(
+    directory: &str,
+    counter: &CellCounter,
+    library: &Library,
+    genemap: &Option<GeneMap>,
+) -> Result<()> {
+    let directory = Path::new(directory);
+    create_dir_all(directory)?;
+
+    let cells = counter.cells();
+    let mut features = library.values().collect::<Vec<_>>();
+    features.sort_unstable();
+
+    let cell_index = cells
+        .iter()
+        .enumerate()
+        .map(|(idx, x)| (x.as_slice(), idx + 1))
+        .collect::<HashMap<_, _>>();
+    let feature_index = features
+        .iter()
+        .enumerate()
+        .map(|(idx, x)| (x.as_slice(), idx + 1))
+        .collect::<HashMap<_, _>>();
+
+    let mut entries = counter
+        .entries()
+        .filter(|(_, count)| **count > 0)
+        .map(|((cell, alias), count)| {
+            (
+                cell_index[cell.as_slice()],
+                feature_index[alias.as_slice()],
+                *count,
+            )
+        })
+        .collect::<Vec<_>>();
+    entries.sort_unstable();
+
+    let mut writer = BufWriter::new(File::create(directory.join("matrix.mtx"))?);
+    writeln!(writer, "%%MatrixMarket matrix coordinate integer general")?;
+    writeln!(
+        writer,
+        "{} {} {}",
+        cells.len(),
+        features.len(),
+        entries.len()
+    )?;
+    for (row, col, count) in entries {
+        writeln!(writer, "{} {} {}", row, col, count)?;
+    }
+
+    let mut writer = BufWriter::new(File::create(directory.join("barcodes.tsv"))?);
+    for cell in cells {
+        writeln!(writer, "{}", std::str::from_utf8(cell)?)?;
+    }
+
+    let mut writer = BufWriter::new(File::create(directory.join("features.tsv"))?);
+    for alias in features {
+        let name = match genemap {
+            Some(g) => g.get(alias).unwrap_or(alias),
+            None => alias,
+        };
+        writeln!(
+            writer,
+            "{}\t{}\tCRISPR Guide Capture",
+            std::str::from_utf8(alias)?,
+            std::str::from_utf8(name)?
+        )?;
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod testing {
     use super::*;
+    use crate::AssignmentOptions;
     use hashbrown::HashMap;
 
     fn build_counter() -> Counter {
@@ -134,30 +707,163 @@ mod testing {
     fn test_generate_columns() {
         let names = vec!["A".to_string(), "B".to_string()];
         let genemap = None;
-        let columns = generate_columns(&names, &genemap);
+        let columns = generate_columns(&names, &genemap, false);
         assert_eq!(columns, "Guide\tA\tB");
     }
 
+    #[test]
+    fn test_generate_columns_umi() {
+        let names = vec!["A".to_string(), "B".to_string()];
+        let columns = generate_columns(&names, &Some(build_gene_map()), true);
+        assert_eq!(columns, "Guide\tGene\tA\tA_umi\tB\tB_umi");
+    }
+
+    #[test]
+    fn test_write_library_design() {
+        let path = std::env::temp_dir().join("sgcount_test_write_library_design.tsv");
+        let table = HashMap::from([
+            (b"ACGTAC".to_vec(), b"g1".to_vec()),
+            (b"ACGTAA".to_vec(), b"g2".to_vec()),
+            (b"ACGTTT".to_vec(), b"g3".to_vec()),
+            (b"GGGGCC".to_vec(), b"g5".to_vec()),
+        ]);
+        let design = LibraryDesign::new(&Library::from_hashmap(table).unwrap());
+        write_library_design(Some(path.to_str().unwrap().to_string()), &design).unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        let rows = contents
+            .lines()
+            .map(|l| l.split('\t').collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        let column = |name: &str| rows[0].iter().position(|c| *c == name).unwrap();
+        let (ambiguous, fraction) = (column("AmbiguousOneOffs"), column("AmbiguousFraction"));
+        assert_eq!(rows[1][0], "g1");
+        assert_eq!(rows[1][ambiguous], "5");
+        assert_eq!(rows[1][fraction], "0.208333");
+        assert_eq!(rows[4][0], "g5");
+        assert_eq!(rows[4][ambiguous], "0");
+        assert_eq!(rows[4][fraction], "0.000000");
+    }
+
+    #[test]
+    fn test_suffixed() {
+        assert_eq!(
+            suffixed(Path::new("out/counts.tsv"), ".summary.json"),
+            PathBuf::from("out/counts.tsv.summary.json")
+        );
+        assert_eq!(
+            suffixed(Path::new("run.v2"), ".summary.tsv"),
+            PathBuf::from("run.v2.summary.tsv")
+        );
+    }
+
     #[test]
     fn test_write_results() {
-        let path = Some("test.txt".to_string());
+        let path = std::env::temp_dir().join("sgcount_test_write_results.tsv");
         let results = vec![build_counter(), build_counter()];
         let library = build_library();
         let genemap = build_gene_map();
         let names = ["sample1".to_string(), "sample2".to_string()];
 
-        write_results(path, &results, &library, &names, &Some(genemap), true).unwrap();
+        write_results(
+            Some(path.to_str().unwrap().to_string()),
+            &results,
+            &library,
+            &names,
+            &Some(genemap),
+            true,
+            false,
+        )
+        .unwrap();
+        std::fs::remove_file(path).unwrap();
     }
 
     #[test]
     fn test_write_results_no_zeros() {
-        let path = Some("test.txt".to_string());
+        let path = std::env::temp_dir().join("sgcount_test_write_results_no_zeros.tsv");
         let results = vec![build_counter(), build_counter()];
         let library = build_library();
         let genemap = build_gene_map();
         let names = ["sample1".to_string(), "sample2".to_string()];
 
-        write_results(path, &results, &library, &names, &Some(genemap), false).unwrap();
+        write_results(
+            Some(path.to_str().unwrap().to_string()),
+            &results,
+            &library,
+            &names,
+            &Some(genemap),
+            false,
+            false,
+        )
+        .unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_build_recombined_row() {
+        use crate::Offset;
+        use fxread::FastaReader;
+
+        let first: &'static [u8] = b">c.0\nAC\n>c.1\nGG\n";
+        let second: &'static [u8] = b">c.0\nGT\n>c.1\nCC\n";
+        let library = DualLibrary::from_readers(
+            Box::new(FastaReader::new(first)),
+            Box::new(FastaReader::new(second)),
+        )
+        .unwrap();
+        let reads: &'static [u8] = b">r.0\nACCC\n>r.1\nACCC\n>r.2\nACCC\n";
+        let results = vec![Counter::new_dual(
+            Box::new(FastaReader::new(reads)),
+            None,
+            &library,
+            Offset::Forward(0),
+            Offset::Forward(2),
+            &AssignmentOptions::new(false),
+            &None,
+        )
+        .unwrap()];
+        let row = build_recombined_row(b"AC_CC", &results, &Some(build_gene_map()), false);
+        assert_eq!(row, "AC_CC\tNA\t3");
+
+        // recombined pairs are only found through their own accessor
+        let results = vec![Counter::from_hashmap(
+            vec![(b"AC_CC".to_vec(), 3)].into_iter().collect(),
+        )];
+        let row = build_recombined_row(b"AC_CC", &results, &None, true);
+        assert_eq!(row, "AC_CC\t0\t0");
+    }
+
+    #[test]
+    fn test_write_cell_results() {
+        use crate::{CellBarcode, Offset};
+        use fxread::FastaReader;
+
+        let barcodes: &'static [u8] = b">r.0\nCCCC\n>r.1\nAAAA\n>r.2\nAAAA\n";
+        let guides: &'static [u8] = b">r.0\nACTG\n>r.1\nGTCA\n>r.2\nGTCA\n";
+        let library = build_library();
+        let counter = CellCounter::new(
+            Box::new(FastaReader::new(barcodes)),
+            Box::new(FastaReader::new(guides)),
+            &library,
+            Offset::Forward(0),
+            &CellBarcode::new(0, 4, None),
+            &AssignmentOptions::new(false),
+        )
+        .unwrap();
+        let directory = "test_cells";
+        write_cell_results(directory, &counter, &library, &Some(build_gene_map())).unwrap();
+        let matrix = std::fs::read_to_string(format!("{}/matrix.mtx", directory)).unwrap();
+        let features = std::fs::read_to_string(format!("{}/features.tsv", directory)).unwrap();
+        std::fs::remove_dir_all(directory).unwrap();
+        assert_eq!(
+            matrix,
+            "%%MatrixMarket matrix coordinate integer general\n2 2 2\n1 2 2\n2 1 1\n"
+        );
+        assert_eq!(
+            features,
+            "sgrna1\tGENE1\tCRISPR Guide Capture\nsgrna2\tGENE2\tCRISPR Guide Capture\n"
+        );
     }
 
     #[test]
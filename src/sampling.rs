@@ -0,0 +1,135 @@
+use anyhow::Result;
+use fxread::{initialize_reader, Record};
+
+/// Describes how reads are subsampled from a file for offset detection.
+///
+/// By default the first reads of the file are taken. Reservoir sampling instead
+/// draws a uniform random sample across the whole file, or across a bounded window
+/// of its first reads, which avoids the biased reads at the start of a run.
+#[derive(Debug, Clone, Copy)]
+pub struct Subsample {
+    size: usize,
+    reservoir: bool,
+    window: Option<usize>,
+    seed: u64,
+}
+impl Subsample {
+    /// Creates a subsample of the first `size` reads
+    #[must_use]
+    pub fn head(size: usize) -> Self {
+        Self {
+            size,
+            reservoir: false,
+            window: None,
+            seed: 0,
+        }
+    }
+
+    /// Creates a uniform random subsample of `size` reads from the whole file, or only
+    /// from its first `window` reads if provided. The same `seed` gives the same reads.
+    #[must_use]
+    pub fn reservoir(size: usize, window: Option<usize>, seed: u64) -> Self {
+        Self {
+            size,
+            reservoir: true,
+            window,
+            seed,
+        }
+    }
+
+    /// Returns the number of subsampled reads
+    #[must_use]
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Subsamples the items of an iterator
+    pub fn sample<T>(&self, iter: impl Iterator<Item = T>) -> Vec<T> {
+        if !self.reservoir {
+            return iter.take(self.size).collect();
+        }
+        let mut rng = SplitMix64::new(self.seed);
+        let mut reservoir = Vec::with_capacity(self.size.min(1 << 16));
+        for (idx, item) in iter.take(self.window.unwrap_or(usize::MAX)).enumerate() {
+            if idx < self.size {
+                reservoir.push(item);
+            } else {
+                let jdx = rng.below(idx as u64 + 1) as usize;
+                if jdx < self.size {
+                    reservoir[jdx] = item;
+                }
+            }
+        }
+        reservoir
+    }
+
+    /// Opens a reader over the subsampled records of a file
+    pub fn reader(&self, path: &str) -> Result<Box<dyn Iterator<Item = Record>>> {
+        let reader = initialize_reader(path)?;
+        if self.reservoir {
+            Ok(Box::new(self.sample(reader).into_iter()))
+        } else {
+            Ok(Box::new(reader.take(self.size)))
+        }
+    }
+}
+
+/// A small seedable pseudo-random number generator (`SplitMix64`)
+struct SplitMix64(u64);
+impl SplitMix64 {
+    fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Draws a number uniformly from `0..n`
+    fn below(&mut self, n: u64) -> u64 {
+        // rejection sampling removes the modulo bias
+        let zone = u64::MAX - u64::MAX % n;
+        loop {
+            let x = self.next();
+            if x < zone {
+                return x % n;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod testing {
+    use super::Subsample;
+
+    #[test]
+    fn test_head() {
+        let sample = Subsample::head(3).sample(0..100);
+        assert_eq!(sample, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_reservoir() {
+        let subsample = Subsample::reservoir(10, None, 42);
+        let sample = subsample.sample(0..1000);
+        assert_eq!(sample.len(), 10);
+        assert!(sample.iter().any(|x| *x >= 10));
+        assert_eq!(sample, subsample.sample(0..1000));
+        assert_ne!(sample, Subsample::reservoir(10, None, 7).sample(0..1000));
+    }
+
+    #[test]
+    fn test_reservoir_window() {
+        let sample = Subsample::reservoir(10, Some(50), 42).sample(0..1000);
+        assert_eq!(sample.len(), 10);
+        assert!(sample.iter().all(|x| *x < 50));
+
+        // fewer items than the sample size are all kept
+        let sample = Subsample::reservoir(10, None, 42).sample(0..5);
+        assert_eq!(sample, vec![0, 1, 2, 3, 4]);
+    }
+}
@@ -0,0 +1,303 @@
+use crate::Offset;
+use anyhow::{bail, Context, Result};
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+};
+
+/// Describes the samples of a run from a tab or comma delimited sample sheet.
+///
+/// The sheet has a header with a `name` (or `sample`) column and an `R1` column,
+/// and optionally an `R2` column of mates, an `offset` column with its `direction`
+/// (forward or reverse), a `mate_offset` column with its `mate_direction` for the
+/// mates, and any number of metadata columns which are carried into the per-sample
+/// output tables. If the `R2` column is present it must be filled for every sample.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SampleSheet {
+    names: Vec<String>,
+    r1: Vec<String>,
+    r2: Option<Vec<String>>,
+    offsets: Vec<Option<Offset>>,
+    mate_offsets: Vec<Option<Offset>>,
+    metadata: Metadata,
+}
+impl SampleSheet {
+    /// Reads a sample sheet, which is comma delimited if the path ends in `.csv`
+    /// and tab delimited otherwise
+    pub fn from_path(path: &str) -> Result<Self> {
+        let delimiter = if path.ends_with(".csv") { ',' } else { '\t' };
+        let file = File::open(path).with_context(|| format!("Unable to open: {}", path))?;
+        Self::from_reader(BufReader::new(file), delimiter)
+            .with_context(|| format!("Invalid sample sheet: {}", path))
+    }
+
+    /// Reads a sample sheet from a reader with the provided delimiter
+    pub fn from_reader<R: BufRead>(reader: R, delimiter: char) -> Result<Self> {
+        let mut lines = reader.lines().enumerate().filter(|(_, line)| {
+            line.as_ref()
+                .map_or(true, |l| !l.trim().is_empty() && !l.starts_with('#'))
+        });
+        let Some((_, header)) = lines.next() else {
+            bail!("Empty sample sheet")
+        };
+        let header = header?
+            .split(delimiter)
+            .map(|c| c.trim().to_string())
+            .collect::<Vec<_>>();
+        let column = |names: &[&str]| {
+            header
+                .iter()
+                .position(|c| names.iter().any(|n| c.eq_ignore_ascii_case(n)))
+        };
+        let Some(name_idx) = column(&["name", "sample"]) else {
+            bail!("Missing sample name column (name)")
+        };
+        let Some(r1_idx) = column(&["r1"]) else {
+            bail!("Missing R1 column")
+        };
+        let r2_idx = column(&["r2"]);
+        let offset_idx = column(&["offset"]);
+        let direction_idx = column(&["direction"]);
+        let mate_offset_idx = column(&["mate_offset"]);
+        let mate_direction_idx = column(&["mate_direction"]);
+        let metadata_idx = (0..header.len())
+            .filter(|idx| {
+                ![
+                    Some(name_idx),
+                    Some(r1_idx),
+                    r2_idx,
+                    offset_idx,
+                    direction_idx,
+                    mate_offset_idx,
+                    mate_direction_idx,
+                ]
+                .contains(&Some(*idx))
+            })
+            .collect::<Vec<_>>();
+
+        let mut sheet = Self {
+            names: Vec::new(),
+            r1: Vec::new(),
+            r2: r2_idx.map(|_| Vec::new()),
+            offsets: Vec::new(),
+            mate_offsets: Vec::new(),
+            metadata: Metadata::new(metadata_idx.iter().map(|i| header[*i].clone()).collect()),
+        };
+        for (idx, line) in lines {
+            let line = line?;
+            let fields = line.split(delimiter).map(str::trim).collect::<Vec<_>>();
+            let field = |i: usize| fields.get(i).copied().unwrap_or_default();
+            let row = idx + 1;
+
+            let name = field(name_idx);
+            if name.is_empty() {
+                bail!("Missing sample name on line {}", row)
+            }
+            if sheet.names.iter().any(|n| n == name) {
+                bail!("Duplicate sample name on line {}: {}", row, name)
+            }
+            if field(r1_idx).is_empty() {
+                bail!("Missing R1 path on line {}", row)
+            }
+            if let (Some(mates), Some(i)) = (&mut sheet.r2, r2_idx) {
+                if field(i).is_empty() {
+                    bail!(
+                        "Missing R2 path on line {} (R2 must be provided for every sample or none)",
+                        row
+                    )
+                }
+                mates.push(field(i).to_string());
+            }
+            let offset = Self::parse_offset(
+                offset_idx.map(field).unwrap_or_default(),
+                direction_idx.map(field).unwrap_or_default(),
+            )
+            .with_context(|| format!("Invalid offset on line {}", row))?;
+            let mate_offset = Self::parse_offset(
+                mate_offset_idx.map(field).unwrap_or_default(),
+                mate_direction_idx.map(field).unwrap_or_default(),
+            )
+            .with_context(|| format!("Invalid mate offset on line {}", row))?;
+            if mate_offset.is_some() && r2_idx.is_none() {
+                bail!(
+                    "A mate offset was provided without an R2 path on line {}",
+                    row
+                )
+            }
+
+            sheet.names.push(name.to_string());
+            sheet.r1.push(field(r1_idx).to_string());
+            sheet.offsets.push(offset);
+            sheet.mate_offsets.push(mate_offset);
+            sheet
+                .metadata
+                .values
+                .push(metadata_idx.iter().map(|i| field(*i).to_string()).collect());
+        }
+        if sheet.names.is_empty() {
+            bail!("No samples in sample sheet")
+        }
+        Ok(sheet)
+    }
+
+    /// Parses an optional fixed offset and its direction (forward by default)
+    fn parse_offset(offset: &str, direction: &str) -> Result<Option<Offset>> {
+        if offset.is_empty() {
+            if !direction.is_empty() {
+                bail!("A direction was provided without an offset")
+            }
+            return Ok(None);
+        }
+        let index = offset.parse::<usize>()?;
+        match direction.to_ascii_lowercase().as_str() {
+            "" | "forward" => Ok(Some(Offset::Forward(index))),
+            "reverse" => Ok(Some(Offset::Reverse(index))),
+            d => bail!("Unknown direction: {} (expected forward or reverse)", d),
+        }
+    }
+
+    /// Returns the sample names
+    #[must_use]
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Returns the paths of the first mates (R1)
+    #[must_use]
+    pub fn r1(&self) -> &[String] {
+        &self.r1
+    }
+
+    /// Returns the paths of the second mates (R2) if provided
+    #[must_use]
+    pub fn r2(&self) -> Option<&[String]> {
+        self.r2.as_deref()
+    }
+
+    /// Returns the fixed offset of each sample if provided
+    #[must_use]
+    pub fn offsets(&self) -> &[Option<Offset>] {
+        &self.offsets
+    }
+
+    /// Returns the fixed offset of each sample's mates if provided
+    #[must_use]
+    pub fn mate_offsets(&self) -> &[Option<Offset>] {
+        &self.mate_offsets
+    }
+
+    /// Returns the metadata of every sample
+    #[must_use]
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+}
+
+/// Metadata columns (i.e. condition, replicate, timepoint) of every sample
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metadata {
+    columns: Vec<String>,
+    values: Vec<Vec<String>>,
+}
+impl Metadata {
+    /// Creates empty metadata with the provided columns
+    #[must_use]
+    pub fn new(columns: Vec<String>) -> Self {
+        Self {
+            columns,
+            values: Vec::new(),
+        }
+    }
+
+    /// Returns the metadata columns
+    #[must_use]
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    /// Returns the metadata values of a sample, which are empty if no metadata was provided
+    #[must_use]
+    pub fn values(&self, sample: usize) -> &[String] {
+        self.values.get(sample).map_or(&[], Vec::as_slice)
+    }
+
+    /// Returns the tab-delimited header of the metadata columns with a leading tab
+    #[must_use]
+    pub fn header(&self) -> String {
+        self.columns.iter().map(|c| format!("\t{}", c)).collect()
+    }
+
+    /// Returns the tab-delimited metadata values of a sample with a leading tab
+    #[must_use]
+    pub fn row(&self, sample: usize) -> String {
+        self.values(sample)
+            .iter()
+            .map(|v| format!("\t{}", v))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod testing {
+    use super::SampleSheet;
+    use crate::Offset;
+
+    fn sheet(contents: &'static str, delimiter: char) -> anyhow::Result<SampleSheet> {
+        SampleSheet::from_reader(contents.as_bytes(), delimiter)
+    }
+
+    #[test]
+    fn test_sample_sheet() {
+        let s = sheet(
+            "name\tR1\tR2\toffset\tdirection\tcondition\n\
+             s1\ta_1.fq\ta_2.fq\t5\treverse\tctrl\n\
+             # a comment\n\
+             s2\tb_1.fq\tb_2.fq\t\t\ttreat\n",
+            '\t',
+        )
+        .unwrap();
+        assert_eq!(s.names(), ["s1", "s2"]);
+        assert_eq!(s.r1(), ["a_1.fq", "b_1.fq"]);
+        assert_eq!(s.r2().unwrap(), ["a_2.fq", "b_2.fq"]);
+        assert_eq!(s.offsets(), [Some(Offset::Reverse(5)), None]);
+        assert_eq!(s.mate_offsets(), [None, None]);
+        assert_eq!(s.metadata().columns(), ["condition"]);
+        assert_eq!(s.metadata().row(1), "\ttreat");
+        assert_eq!(s.metadata().header(), "\tcondition");
+    }
+
+    #[test]
+    fn test_sample_sheet_mate_offsets() {
+        let s = sheet(
+            "name\tR1\tR2\tmate_offset\tmate_direction\n\
+             s1\ta_1.fq\ta_2.fq\t3\treverse\n\
+             s2\tb_1.fq\tb_2.fq\t\t\n",
+            '\t',
+        )
+        .unwrap();
+        assert_eq!(s.offsets(), [None, None]);
+        assert_eq!(s.mate_offsets(), [Some(Offset::Reverse(3)), None]);
+        assert!(s.metadata().columns().is_empty());
+        assert!(sheet("name\tR1\tmate_offset\ns1\ta.fq\t3\n", '\t').is_err());
+    }
+
+    #[test]
+    fn test_sample_sheet_csv() {
+        let s = sheet("sample,r1,replicate\ns1,a.fq,1\n", ',').unwrap();
+        assert_eq!(s.names(), ["s1"]);
+        assert!(s.r2().is_none());
+        assert_eq!(s.offsets(), [None]);
+        assert_eq!(s.metadata().values(0), ["1"]);
+    }
+
+    #[test]
+    fn test_invalid_sample_sheet() {
+        assert!(sheet("R1\na.fq\n", '\t').is_err());
+        assert!(sheet("name\nx\n", '\t').is_err());
+        assert!(sheet("name\tR1\n", '\t').is_err());
+        assert!(sheet("name\tR1\ns1\ta.fq\ns1\tb.fq\n", '\t').is_err());
+        assert!(sheet("name\tR1\tR2\ns1\ta.fq\t\n", '\t').is_err());
+        assert!(sheet("name\tR1\toffset\ns1\ta.fq\tx\n", '\t').is_err());
+        assert!(sheet("name\tR1\toffset\tdirection\ns1\ta.fq\t1\tup\n", '\t').is_err());
+    }
+}
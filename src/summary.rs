@@ -0,0 +1,200 @@
+use crate::{Counter, Offset};
+
+/// Describes the mapping and count distribution statistics of a single sample
+#[derive(Debug, Clone)]
+pub struct SampleSummary {
+    name: String,
+    total_reads: usize,
+    matched_reads: usize,
+    invalid_umi_reads: usize,
+    offset: Option<Offset>,
+    guides: usize,
+    zero_count_guides: usize,
+    gini_index: f64,
+    skew_ratio: Option<f64>,
+}
+impl SampleSummary {
+    /// Summarizes the counts of a sample over the provided library aliases
+    #[must_use]
+    pub fn new<'a>(
+        name: &str,
+        counter: &Counter,
+        aliases: impl Iterator<Item = &'a Vec<u8>>,
+    ) -> Self {
+        let counts = aliases
+            .map(|alias| *counter.get_value(alias))
+            .collect::<Vec<usize>>();
+        Self {
+            total_reads: counter.total_reads(),
+            matched_reads: counter.matched_reads(),
+            invalid_umi_reads: counter.invalid_umi_reads(),
+            offset: counter.offset_counts().first().map(|(offset, _)| *offset),
+            ..Self::from_counts(name, counts)
+        }
+    }
+
+    /// Summarizes the guide counts of a sample without any mapping statistics
+    /// (i.e. from a count table). Every counted read is considered matched.
+    #[must_use]
+    pub fn from_counts(name: &str, mut counts: Vec<usize>) -> Self {
+        counts.sort_unstable();
+        let matched_reads = counts.iter().sum();
+        Self {
+            name: name.to_string(),
+            total_reads: matched_reads,
+            matched_reads,
+            invalid_umi_reads: 0,
+            offset: None,
+            guides: counts.len(),
+            zero_count_guides: counts.iter().filter(|c| **c == 0).count(),
+            gini_index: gini_index(&counts),
+            skew_ratio: skew_ratio(&counts),
+        }
+    }
+
+    /// Returns the name of the sample
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the total number of reads in the sample
+    #[must_use]
+    pub fn total_reads(&self) -> usize {
+        self.total_reads
+    }
+
+    /// Returns the number of reads assigned to a library sequence
+    #[must_use]
+    pub fn matched_reads(&self) -> usize {
+        self.matched_reads
+    }
+
+    /// Returns the number of assigned reads excluded from the UMI counts
+    #[must_use]
+    pub fn invalid_umi_reads(&self) -> usize {
+        self.invalid_umi_reads
+    }
+
+    /// Returns the fraction of reads assigned to a library sequence
+    #[must_use]
+    pub fn mapped_fraction(&self) -> f64 {
+        self.matched_reads as f64 / self.total_reads.max(1) as f64
+    }
+
+    /// Returns the offset with the most assigned reads (if any reads were assigned)
+    #[must_use]
+    pub fn offset(&self) -> Option<Offset> {
+        self.offset
+    }
+
+    /// Returns the number of library guides
+    #[must_use]
+    pub fn guides(&self) -> usize {
+        self.guides
+    }
+
+    /// Returns the number of library guides without any assigned reads
+    #[must_use]
+    pub fn zero_count_guides(&self) -> usize {
+        self.zero_count_guides
+    }
+
+    /// Returns the Gini index of the guide counts
+    #[must_use]
+    pub fn gini_index(&self) -> f64 {
+        self.gini_index
+    }
+
+    /// Returns the ratio of the 90th to the 10th percentile of the guide counts.
+    /// This is undefined if the 10th percentile is zero.
+    #[must_use]
+    pub fn skew_ratio(&self) -> Option<f64> {
+        self.skew_ratio
+    }
+
+    /// Returns the mean number of assigned reads per library guide
+    #[must_use]
+    pub fn reads_per_guide(&self) -> f64 {
+        self.matched_reads as f64 / self.guides.max(1) as f64
+    }
+}
+
+/// Calculates the Gini index of a set of counts sorted in ascending order.
+/// A uniform distribution has an index of zero.
+fn gini_index(sorted: &[usize]) -> f64 {
+    let n = sorted.len() as f64;
+    let total = sorted.iter().sum::<usize>() as f64;
+    if total == 0.0 {
+        return 0.0;
+    }
+    let weighted = sorted
+        .iter()
+        .enumerate()
+        .map(|(idx, c)| (idx + 1) as f64 * *c as f64)
+        .sum::<f64>();
+    (2.0 * weighted) / (n * total) - (n + 1.0) / n
+}
+
+/// Calculates a percentile of a set of counts sorted in ascending order
+/// by linear interpolation between the nearest ranks
+fn percentile(sorted: &[usize], p: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = p * (sorted.len() - 1) as f64;
+    let (lower, upper) = (rank.floor() as usize, rank.ceil() as usize);
+    let weight = rank - lower as f64;
+    sorted[lower] as f64 * (1.0 - weight) + sorted[upper] as f64 * weight
+}
+
+/// Calculates the ratio of the 90th to the 10th percentile of a set of counts
+/// sorted in ascending order
+fn skew_ratio(sorted: &[usize]) -> Option<f64> {
+    let bottom = percentile(sorted, 0.1);
+    if bottom > 0.0 {
+        Some(percentile(sorted, 0.9) / bottom)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod testing {
+    use super::{gini_index, percentile, skew_ratio, SampleSummary};
+    use crate::Counter;
+    use hashbrown::HashMap;
+
+    #[test]
+    fn test_gini_index() {
+        assert_eq!(gini_index(&[5, 5, 5, 5]), 0.0);
+        assert_eq!(gini_index(&[0, 0, 0, 0]), 0.0);
+        assert!((gini_index(&[0, 0, 0, 12]) - 0.75).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_skew_ratio() {
+        let counts = (1..=11).collect::<Vec<usize>>();
+        assert_eq!(percentile(&counts, 0.1), 2.0);
+        assert_eq!(percentile(&counts, 0.9), 10.0);
+        assert_eq!(skew_ratio(&counts), Some(5.0));
+        assert_eq!(skew_ratio(&[0, 0, 4, 8]), None);
+    }
+
+    #[test]
+    fn test_sample_summary() {
+        let map = HashMap::from([(b"g1".to_vec(), 6), (b"g2".to_vec(), 2)]);
+        let counter = Counter::from_hashmap(map);
+        let aliases = [
+            b"g1".to_vec(),
+            b"g2".to_vec(),
+            b"g3".to_vec(),
+            b"g4".to_vec(),
+        ];
+        let summary = SampleSummary::new("s", &counter, aliases.iter());
+        assert_eq!(summary.guides(), 4);
+        assert_eq!(summary.zero_count_guides(), 2);
+        assert_eq!(summary.skew_ratio(), None);
+        assert!(summary.gini_index() > 0.0);
+    }
+}
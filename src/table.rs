@@ -0,0 +1,229 @@
+use crate::SampleSummary;
+use anyhow::{bail, Context, Result};
+use hashbrown::HashMap;
+use std::{
+    fs::File,
+    io::{stdout, BufRead, BufReader, BufWriter, Write},
+};
+
+/// A table of guide counts across samples as written by a count.
+///
+/// The first column holds the guide alias, optionally followed by a `Gene` column,
+/// and every further column holds the counts of a single sample.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CountTable {
+    guides: Vec<String>,
+    genes: Option<Vec<String>>,
+    samples: Vec<String>,
+    counts: Vec<Vec<usize>>,
+}
+impl CountTable {
+    /// Reads a count table from a tab-delimited file
+    pub fn from_path(path: &str) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("Unable to open: {}", path))?;
+        Self::from_reader(BufReader::new(file))
+            .with_context(|| format!("Unable to read count table: {}", path))
+    }
+
+    /// Reads a count table from a tab-delimited reader
+    pub fn from_reader<R: BufRead>(reader: R) -> Result<Self> {
+        let mut lines = reader.lines();
+        let Some(header) = lines.next() else {
+            bail!("Empty count table")
+        };
+        let header = header?;
+        let mut columns = header.split('\t');
+        if columns.next() != Some("Guide") {
+            bail!("Count table must begin with a Guide column")
+        }
+        let columns = columns.map(String::from).collect::<Vec<_>>();
+        let has_genes = columns.first().is_some_and(|c| c == "Gene");
+        let samples = columns[usize::from(has_genes)..].to_vec();
+
+        let mut guides = Vec::new();
+        let mut genes = Vec::new();
+        let mut counts = Vec::new();
+        for (idx, line) in lines.enumerate() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            let mut fields = line.split('\t');
+            guides.push(fields.next().unwrap_or_default().to_string());
+            if has_genes {
+                genes.push(fields.next().unwrap_or_default().to_string());
+            }
+            let row = fields
+                .map(str::parse::<usize>)
+                .collect::<Result<Vec<_>, _>>()
+                .with_context(|| format!("Invalid count on line {}", idx + 2))?;
+            if row.len() != samples.len() {
+                bail!(
+                    "Expected {} counts on line {} but found {}",
+                    samples.len(),
+                    idx + 2,
+                    row.len()
+                )
+            }
+            counts.push(row);
+        }
+        Ok(Self {
+            guides,
+            genes: has_genes.then_some(genes),
+            samples,
+            counts,
+        })
+    }
+
+    /// Combines count tables into a single table with the samples of every table.
+    /// Guides missing from a table are given a zero count in its samples.
+    pub fn merge(tables: &[Self]) -> Result<Self> {
+        let with_genes = tables.iter().filter(|t| t.genes.is_some()).count();
+        if with_genes != 0 && with_genes != tables.len() {
+            bail!("Cannot merge count tables with and without a Gene column")
+        }
+
+        let mut samples = Vec::new();
+        for sample in tables.iter().flat_map(|t| &t.samples) {
+            if samples.contains(sample) {
+                bail!("Duplicate sample name across count tables: {}", sample)
+            }
+            samples.push(sample.clone());
+        }
+
+        let mut index = HashMap::new();
+        let mut guides = Vec::new();
+        let mut genes = Vec::new();
+        let mut counts: Vec<Vec<usize>> = Vec::new();
+        let mut column = 0;
+        for table in tables {
+            for (row, guide) in table.guides.iter().enumerate() {
+                let gene = table.genes.as_ref().map(|g| &g[row]);
+                let idx = *index.entry(guide.clone()).or_insert_with(|| {
+                    guides.push(guide.clone());
+                    genes.push(gene.cloned().unwrap_or_default());
+                    counts.push(vec![0; samples.len()]);
+                    guides.len() - 1
+                });
+                if let Some(gene) = gene {
+                    if genes[idx] != *gene {
+                        bail!(
+                            "Conflicting genes for guide {}: {} and {}",
+                            guide,
+                            genes[idx],
+                            gene
+                        )
+                    }
+                }
+                counts[idx][column..column + table.samples.len()]
+                    .copy_from_slice(&table.counts[row]);
+            }
+            column += table.samples.len();
+        }
+
+        Ok(Self {
+            guides,
+            genes: (with_genes > 0).then_some(genes),
+            samples,
+            counts,
+        })
+    }
+
+    /// Returns the sample names of the table
+    #[must_use]
+    pub fn samples(&self) -> &[String] {
+        &self.samples
+    }
+
+    /// Summarizes the count distribution of every sample. Only the guides present
+    /// in the table are considered, so zero count guides are only reported if the
+    /// table includes them.
+    #[must_use]
+    pub fn summaries(&self) -> Vec<SampleSummary> {
+        self.samples
+            .iter()
+            .enumerate()
+            .map(|(idx, name)| {
+                SampleSummary::from_counts(name, self.counts.iter().map(|row| row[idx]).collect())
+            })
+            .collect()
+    }
+
+    /// Writes the table to the provided path or to stdout
+    pub fn write(&self, path: Option<String>) -> Result<()> {
+        let mut writer: Box<dyn Write> = match path {
+            Some(p) => Box::new(BufWriter::new(File::create(p)?)),
+            None => Box::new(BufWriter::new(stdout())),
+        };
+        write!(writer, "Guide")?;
+        if self.genes.is_some() {
+            write!(writer, "\tGene")?;
+        }
+        for sample in &self.samples {
+            write!(writer, "\t{}", sample)?;
+        }
+        writeln!(writer)?;
+        for (idx, guide) in self.guides.iter().enumerate() {
+            write!(writer, "{}", guide)?;
+            if let Some(genes) = &self.genes {
+                write!(writer, "\t{}", genes[idx])?;
+            }
+            for count in &self.counts[idx] {
+                write!(writer, "\t{}", count)?;
+            }
+            writeln!(writer)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod testing {
+    use super::CountTable;
+
+    fn table(contents: &'static str) -> CountTable {
+        CountTable::from_reader(contents.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_read_table() {
+        let t = table("Guide\tGene\ts1\ts2\ng1\tA\t1\t2\ng2\tB\t3\t4\n");
+        assert_eq!(t.samples(), ["s1", "s2"]);
+        assert_eq!(t.genes, Some(vec!["A".to_string(), "B".to_string()]));
+        assert_eq!(t.counts, vec![vec![1, 2], vec![3, 4]]);
+    }
+
+    #[test]
+    fn test_read_invalid_table() {
+        assert!(CountTable::from_reader("Sample\ts1\n".as_bytes()).is_err());
+        assert!(CountTable::from_reader("Guide\ts1\ng1\tx\n".as_bytes()).is_err());
+        assert!(CountTable::from_reader("Guide\ts1\ng1\t1\t2\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_merge() {
+        let a = table("Guide\ts1\ng1\t1\ng2\t2\n");
+        let b = table("Guide\ts2\ts3\ng2\t3\t4\ng3\t5\t6\n");
+        let merged = CountTable::merge(&[a, b]).unwrap();
+        assert_eq!(merged.samples(), ["s1", "s2", "s3"]);
+        assert_eq!(merged.guides, ["g1", "g2", "g3"]);
+        assert_eq!(
+            merged.counts,
+            vec![vec![1, 0, 0], vec![2, 3, 4], vec![0, 5, 6]]
+        );
+    }
+
+    #[test]
+    fn test_merge_invalid() {
+        let a = table("Guide\ts1\ng1\t1\n");
+        let b = table("Guide\ts1\ng1\t1\n");
+        assert!(CountTable::merge(&[a.clone(), b]).is_err());
+
+        let c = table("Guide\tGene\ts2\ng1\tA\t1\n");
+        assert!(CountTable::merge(&[a, c.clone()]).is_err());
+
+        let d = table("Guide\tGene\ts3\ng1\tB\t1\n");
+        assert!(CountTable::merge(&[c, d]).is_err());
+    }
+}
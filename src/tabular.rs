@@ -0,0 +1,456 @@
+use crate::{ConstantRegions, DuplicatePolicy, GeneMap, Library};
+use anyhow::{bail, Context, Result};
+use fxread::{initialize_reader, FastxRead, Record};
+use hashbrown::HashMap;
+use std::{
+    io::{BufRead, BufReader},
+    str::FromStr,
+};
+
+type FxReader = Box<dyn FastxRead<Item = Record>>;
+
+/// Column aliases used to find the columns of a library with a header
+const ID_COLUMNS: [&str; 4] = ["id", "sgrna", "guide", "name"];
+const SEQUENCE_COLUMNS: [&str; 2] = ["sequence", "seq"];
+const GENE_COLUMNS: [&str; 1] = ["gene"];
+
+/// Extensions of the compression formats decompressed when reading a library
+const COMPRESSION_EXTENSIONS: [&str; 6] = [".gz", ".bgz", ".bz2", ".xz", ".lzma", ".zst"];
+
+/// A column of a delimited library, either by its header name or its 1-based position
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Column {
+    /// 1-based position of the column
+    Index(usize),
+    /// Header name of the column (case-insensitive)
+    Name(String),
+}
+impl FromStr for Column {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s.parse::<usize>() {
+            Ok(0) => bail!("Column positions are 1-based"),
+            Ok(idx) => Ok(Self::Index(idx)),
+            Err(_) => Ok(Self::Name(s.to_string())),
+        }
+    }
+}
+
+/// Describes how the columns of a delimited (`*.csv` or `*.tsv`, optionally
+/// compressed) library are read. Any other library is read as fastx.
+///
+/// The first line is taken as a header unless its sequence column holds a nucleotide
+/// sequence. With a header the columns are found by name (`id`, `sequence`, and
+/// `gene` by default), and without one they are the first, second, and third columns.
+/// Duplicate sequences and ids of any library are handled with a [`DuplicatePolicy`],
+/// and if `trim` is set the [`ConstantRegions`] shared by all of its sequences are
+/// stripped to leave their variable regions.
+#[derive(Debug, Clone, Default)]
+pub struct LibraryFormat {
+    id: Option<Column>,
+    sequence: Option<Column>,
+    gene: Option<Column>,
+    duplicates: DuplicatePolicy,
+    trim: bool,
+}
+impl LibraryFormat {
+    /// Creates a library format with the provided columns, or the defaults if none
+    #[must_use]
+    pub fn new(
+        id: Option<Column>,
+        sequence: Option<Column>,
+        gene: Option<Column>,
+        duplicates: DuplicatePolicy,
+        trim: bool,
+    ) -> Self {
+        Self {
+            id,
+            sequence,
+            gene,
+            duplicates,
+            trim,
+        }
+    }
+
+    /// Returns the delimiter of a library path if it is a delimited table
+    fn delimiter(path: &str) -> Option<char> {
+        let path = COMPRESSION_EXTENSIONS
+            .iter()
+            .find_map(|ext| path.strip_suffix(ext))
+            .unwrap_or(path);
+        if path.ends_with(".csv") {
+            Some(',')
+        } else if path.ends_with(".tsv") {
+            Some('\t')
+        } else {
+            None
+        }
+    }
+
+    /// Returns whether a library path is read as a delimited table
+    #[must_use]
+    pub fn is_tabular(path: &str) -> bool {
+        Self::delimiter(path).is_some()
+    }
+
+    /// Parses a library once into a [`LibraryTable`], stripping the constant regions
+    /// of its sequences if the format trims them
+    pub fn load(&self, path: &str) -> Result<LibraryTable> {
+        let entries = match Self::delimiter(path) {
+            Some(delimiter) => self.read(path, delimiter)?,
+            None => initialize_reader(path)?
+                .map(|record| Entry {
+                    id: record.id().to_vec(),
+                    sequence: record.seq().to_vec(),
+                    gene: None,
+                })
+                .collect(),
+        };
+        Ok(self.table(entries))
+    }
+
+    /// Builds a [`LibraryTable`] from the parsed entries of a library
+    fn table(&self, entries: Vec<Entry>) -> LibraryTable {
+        let regions = self
+            .trim
+            .then(|| ConstantRegions::detect(entries.iter().map(|e| e.sequence.as_slice())));
+        let genes = entries.iter().any(|e| e.gene.is_some()).then(|| {
+            entries
+                .iter()
+                .filter_map(|e| e.gene.clone().map(|gene| (e.id.clone(), gene)))
+                .collect()
+        });
+        let records = entries
+            .into_iter()
+            .map(|e| match &regions {
+                Some(r) => (e.id, r.strip(&e.sequence).to_vec()),
+                None => (e.id, e.sequence),
+            })
+            .collect();
+        LibraryTable {
+            records,
+            genes,
+            regions,
+            duplicates: self.duplicates,
+        }
+    }
+
+    /// Reads the entries of a delimited library
+    fn read(&self, path: &str, delimiter: char) -> Result<Vec<Entry>> {
+        let (reader, _format) =
+            niffler::from_path(path).with_context(|| format!("Unable to open: {}", path))?;
+        self.read_buffer(BufReader::new(reader), delimiter)
+            .with_context(|| format!("Invalid library table: {}", path))
+    }
+
+    /// Reads the entries of a delimited library from a buffer
+    fn read_buffer<R: BufRead>(&self, reader: R, delimiter: char) -> Result<Vec<Entry>> {
+        let mut rows = Vec::new();
+        for (idx, line) in reader.lines().enumerate() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                rows.push((idx + 1, split(&line, delimiter)));
+            }
+        }
+        let Some((_, first)) = rows.first() else {
+            bail!("Empty library")
+        };
+
+        let sequence_idx = match &self.sequence {
+            Some(Column::Index(idx)) => idx - 1,
+            _ => 1,
+        };
+        let has_header = !first.get(sequence_idx).is_some_and(|s| is_nucleotide(s));
+        let header = has_header.then_some(first.as_slice());
+
+        let id = resolve(header, &self.id, &ID_COLUMNS, Some(0))?.context("Missing id column")?;
+        let sequence = resolve(header, &self.sequence, &SEQUENCE_COLUMNS, Some(1))?
+            .context("Missing sequence column")?;
+        let gene = resolve(
+            header,
+            &self.gene,
+            &GENE_COLUMNS,
+            (!has_header && first.len() > 2).then_some(2),
+        )?;
+
+        let mut entries = Vec::new();
+        for (row, fields) in rows.iter().skip(usize::from(has_header)) {
+            let field = |idx: usize| -> Result<&str> {
+                fields
+                    .get(idx)
+                    .map(String::as_str)
+                    .with_context(|| format!("Missing column {} on line {}", idx + 1, row))
+            };
+            let sequence = field(sequence)?;
+            if !is_nucleotide(sequence) {
+                bail!("Invalid sequence on line {}: {}", row, sequence)
+            }
+            entries.push(Entry {
+                id: field(id)?.as_bytes().to_vec(),
+                sequence: sequence.as_bytes().to_vec(),
+                gene: match gene {
+                    Some(idx) => Some(field(idx)?)
+                        .filter(|g| !g.is_empty())
+                        .map(|g| g.as_bytes().to_vec()),
+                    None => None,
+                },
+            });
+        }
+        Ok(entries)
+    }
+}
+
+/// The records of a library parsed once with a [`LibraryFormat`], from which its
+/// [`Library`], [`ConstantRegions`], and [`GeneMap`] are derived
+#[derive(Debug, Clone)]
+pub struct LibraryTable {
+    records: Vec<(Vec<u8>, Vec<u8>)>,
+    genes: Option<Vec<(Vec<u8>, Vec<u8>)>>,
+    regions: Option<ConstantRegions>,
+    duplicates: DuplicatePolicy,
+}
+impl LibraryTable {
+    /// Opens a reader over the (trimmed) records of the library
+    pub fn reader(&self) -> Result<FxReader> {
+        let records = self
+            .records
+            .iter()
+            .map(|(id, sequence)| Record::new_fasta_from_parts(id, sequence))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Box::new(TableReader(records.into_iter())))
+    }
+
+    /// Builds the library, handling its duplicates with the format's [`DuplicatePolicy`]
+    pub fn library(&self) -> Result<Library> {
+        Library::from_reader_with(self.reader()?, self.duplicates)
+    }
+
+    /// Returns the constant regions stripped from the library sequences if trimmed
+    #[must_use]
+    pub fn constant_regions(&self) -> Option<&ConstantRegions> {
+        self.regions.as_ref()
+    }
+
+    /// Builds the gene map of a delimited library with a gene column
+    pub fn genemap(&self) -> Result<Option<GeneMap>> {
+        let Some(genes) = &self.genes else {
+            return Ok(None);
+        };
+        let mut map: HashMap<Vec<u8>, Vec<u8>> = HashMap::new();
+        for (id, gene) in genes {
+            match map.get(id) {
+                // repeated ids keep their first gene unless duplicates are an error
+                Some(existing) if existing != gene && self.duplicates == DuplicatePolicy::Error => {
+                    bail!(
+                        "Conflicting genes for sgRNA in library: {}",
+                        String::from_utf8_lossy(id)
+                    )
+                }
+                Some(_) => {}
+                None => {
+                    map.insert(id.clone(), gene.clone());
+                }
+            }
+        }
+        Ok(Some(GeneMap::from_hashmap(map)))
+    }
+}
+
+/// A single row of a delimited library
+struct Entry {
+    id: Vec<u8>,
+    sequence: Vec<u8>,
+    gene: Option<Vec<u8>>,
+}
+
+/// Finds the 0-based position of a column from a user provided column, or from
+/// the default names if there is a header, falling back to the default position
+fn resolve(
+    header: Option<&[String]>,
+    column: &Option<Column>,
+    defaults: &[&str],
+    default_idx: Option<usize>,
+) -> Result<Option<usize>> {
+    let find = |header: &[String], names: &[&str]| {
+        header
+            .iter()
+            .position(|c| names.iter().any(|n| c.eq_ignore_ascii_case(n)))
+    };
+    match (column, header) {
+        (Some(Column::Index(idx)), _) => Ok(Some(idx - 1)),
+        (Some(Column::Name(name)), Some(h)) => match find(h, &[name]) {
+            Some(idx) => Ok(Some(idx)),
+            None => bail!("Column not found in library header: {}", name),
+        },
+        (Some(Column::Name(name)), None) => {
+            bail!("Library has no header to find the column: {}", name)
+        }
+        (None, Some(h)) => Ok(find(h, defaults).or(default_idx)),
+        (None, None) => Ok(default_idx),
+    }
+}
+
+/// Splits a delimited line into its fields, removing surrounding whitespace and quotes
+fn split(line: &str, delimiter: char) -> Vec<String> {
+    line.split(delimiter)
+        .map(|f| f.trim().trim_matches('"').to_string())
+        .collect()
+}
+
+/// Returns whether a field is a nucleotide sequence
+fn is_nucleotide(s: &str) -> bool {
+    !s.is_empty()
+        && s.bytes()
+            .all(|b| matches!(b.to_ascii_uppercase(), b'A' | b'C' | b'G' | b'T' | b'N'))
+}
+
+/// Iterates over the records of a delimited library as fastx records
+struct TableReader(std::vec::IntoIter<Record>);
+impl Iterator for TableReader {
+    type Item = Record;
+    fn next(&mut self) -> Option<Record> {
+        self.0.next()
+    }
+}
+impl FastxRead for TableReader {
+    fn next_record(&mut self) -> Result<Option<Record>> {
+        Ok(self.0.next())
+    }
+}
+
+#[cfg(test)]
+mod testing {
+    use super::{Column, LibraryFormat};
+    use crate::DuplicatePolicy;
+
+    fn read(format: &LibraryFormat, contents: &'static str, delimiter: char) -> Vec<[String; 3]> {
+        format
+            .read_buffer(contents.as_bytes(), delimiter)
+            .unwrap()
+            .into_iter()
+            .map(|e| {
+                [
+                    String::from_utf8(e.id).unwrap(),
+                    String::from_utf8(e.sequence).unwrap(),
+                    e.gene
+                        .map(|g| String::from_utf8(g).unwrap())
+                        .unwrap_or_default(),
+                ]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_column() {
+        assert_eq!("2".parse::<Column>().unwrap(), Column::Index(2));
+        assert_eq!(
+            "Gene".parse::<Column>().unwrap(),
+            Column::Name("Gene".to_string())
+        );
+        assert!("0".parse::<Column>().is_err());
+    }
+
+    #[test]
+    fn test_tabular_path() {
+        assert!(LibraryFormat::is_tabular("lib.csv"));
+        assert!(LibraryFormat::is_tabular("lib.tsv.gz"));
+        assert!(LibraryFormat::is_tabular("lib.csv.bz2"));
+        assert!(LibraryFormat::is_tabular("lib.csv.zst"));
+        assert!(!LibraryFormat::is_tabular("lib.fa.gz"));
+        assert!(!LibraryFormat::is_tabular("lib.txt"));
+    }
+
+    #[test]
+    fn test_read_with_header() {
+        let entries = read(
+            &LibraryFormat::default(),
+            "Gene,Sequence,ID,Control\n\"g1\",ACTG,s1,0\ng2,TTTT,s2,1\n",
+            ',',
+        );
+        assert_eq!(entries[0], ["s1", "ACTG", "g1"]);
+        assert_eq!(entries[1], ["s2", "TTTT", "g2"]);
+    }
+
+    #[test]
+    fn test_read_without_header() {
+        let entries = read(
+            &LibraryFormat::default(),
+            "s1\tACTG\tg1\ns2\tTTTT\tg2\n",
+            '\t',
+        );
+        assert_eq!(entries[1], ["s2", "TTTT", "g2"]);
+
+        let entries = read(&LibraryFormat::default(), "s1\tACTG\n", '\t');
+        assert_eq!(entries[0], ["s1", "ACTG", ""]);
+    }
+
+    #[test]
+    fn test_read_columns() {
+        let format = LibraryFormat::new(
+            Some(Column::Name("guide_name".to_string())),
+            Some(Column::Index(3)),
+            Some(Column::Name("target".to_string())),
+            DuplicatePolicy::Error,
+            false,
+        );
+        let entries = read(&format, "target\tguide_name\tspacer\nA\ts1\tACTG\n", '\t');
+        assert_eq!(entries[0], ["s1", "ACTG", "A"]);
+
+        let format = LibraryFormat::new(
+            Some(Column::Index(2)),
+            Some(Column::Index(1)),
+            None,
+            DuplicatePolicy::Error,
+            false,
+        );
+        let entries = read(&format, "ACTG\ts1\n", '\t');
+        assert_eq!(entries[0], ["s1", "ACTG", ""]);
+    }
+
+    #[test]
+    fn test_read_invalid() {
+        let format = LibraryFormat::default();
+        assert!(format.read_buffer("".as_bytes(), ',').is_err());
+        assert!(format
+            .read_buffer("id,sequence\ns1,NOPE\n".as_bytes(), ',')
+            .is_err());
+        assert!(format
+            .read_buffer("id,sequence\ns1\n".as_bytes(), ',')
+            .is_err());
+        let format = LibraryFormat::new(
+            None,
+            None,
+            Some(Column::Name("gene".to_string())),
+            DuplicatePolicy::Error,
+            false,
+        );
+        assert!(format.read_buffer("s1,ACTG,A\n".as_bytes(), ',').is_err());
+    }
+
+    #[test]
+    fn test_table() {
+        let contents = "id,sequence,gene\ns1,AAACTGTT,g1\ns2,AAAGCATT,g2\ns3,AAATTTTT,\n";
+        let format = LibraryFormat::new(None, None, None, DuplicatePolicy::Error, true);
+        let table = format.table(format.read_buffer(contents.as_bytes(), ',').unwrap());
+        let regions = table.constant_regions().unwrap();
+        assert_eq!(regions.prefix(), b"AAA");
+        assert_eq!(regions.suffix(), b"TT");
+        let library = table.library().unwrap();
+        assert_eq!(library.contains(b"CTG"), Some(&b"s1".to_vec()));
+        let genemap = table.genemap().unwrap().unwrap();
+        assert_eq!(genemap.get(b"s2"), Some(&b"g2".to_vec()));
+        assert_eq!(genemap.get(b"s3"), None);
+    }
+
+    #[test]
+    fn test_table_conflicting_genes() {
+        let contents = "id,sequence,gene\ns1,ACTG,g1\ns1,ACTG,g2\n";
+        let format = LibraryFormat::default();
+        let table = format.table(format.read_buffer(contents.as_bytes(), ',').unwrap());
+        assert!(table.genemap().is_err());
+        let format = LibraryFormat::new(None, None, None, DuplicatePolicy::First, false);
+        let table = format.table(format.read_buffer(contents.as_bytes(), ',').unwrap());
+        assert!(table.genemap().unwrap().is_some());
+        assert!(table.constant_regions().is_none());
+    }
+}
@@ -0,0 +1,127 @@
+use crate::Anchor;
+
+/// Maximum length of a constant region kept as an anchor, taken from the bases
+/// nearest the variable region
+pub const ANCHOR_SIZE: usize = 10;
+
+/// The constant prefix and suffix shared by every sequence of a library (i.e. the
+/// scaffold of full oligo sequences) which surround the variable region of the guides.
+///
+/// At least a single basepair of variable region is always kept for every sequence,
+/// and a library of a single sequence has no constant regions.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConstantRegions {
+    prefix: Vec<u8>,
+    suffix: Vec<u8>,
+}
+impl ConstantRegions {
+    /// Detects the longest constant prefix and suffix shared by all sequences
+    pub fn detect<'a>(sequences: impl Iterator<Item = &'a [u8]>) -> Self {
+        let sequences = sequences.collect::<Vec<_>>();
+        if sequences.len() < 2 {
+            return Self::default();
+        }
+        let min_size = sequences.iter().map(|s| s.len()).min().unwrap_or(0);
+        let prefix = Self::shared(&sequences, |s, idx| s[idx]).min(min_size.saturating_sub(1));
+        let suffix = Self::shared(&sequences, |s, idx| s[s.len() - 1 - idx])
+            .min(min_size.saturating_sub(prefix + 1));
+        let first = sequences[0];
+        Self {
+            prefix: first[..prefix].to_vec(),
+            suffix: first[first.len() - suffix..].to_vec(),
+        }
+    }
+
+    /// Counts the positions (as indexed by `base`) at which every sequence shares a base
+    fn shared(sequences: &[&[u8]], base: impl Fn(&[u8], usize) -> u8) -> usize {
+        let min_size = sequences.iter().map(|s| s.len()).min().unwrap_or(0);
+        (0..min_size)
+            .take_while(|idx| {
+                let reference = base(sequences[0], *idx);
+                sequences.iter().all(|s| base(s, *idx) == reference)
+            })
+            .count()
+    }
+
+    /// Returns the constant prefix
+    #[must_use]
+    pub fn prefix(&self) -> &[u8] {
+        &self.prefix
+    }
+
+    /// Returns the constant suffix
+    #[must_use]
+    pub fn suffix(&self) -> &[u8] {
+        &self.suffix
+    }
+
+    /// Returns `true` if no constant regions were found
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.prefix.is_empty() && self.suffix.is_empty()
+    }
+
+    /// Strips the constant regions from a library sequence, leaving its variable region
+    #[must_use]
+    pub fn strip<'a>(&self, sequence: &'a [u8]) -> &'a [u8] {
+        &sequence[self.prefix.len()..sequence.len() - self.suffix.len()]
+    }
+
+    /// Builds an [`Anchor`] from the bases of the constant regions nearest the variable
+    /// region (up to [`ANCHOR_SIZE`]), or [`None`] if no constant regions were found
+    #[must_use]
+    pub fn anchor(&self, max_mismatches: usize) -> Option<Anchor> {
+        if self.is_empty() {
+            return None;
+        }
+        let upstream = &self.prefix[self.prefix.len().saturating_sub(ANCHOR_SIZE)..];
+        let downstream = &self.suffix[..self.suffix.len().min(ANCHOR_SIZE)];
+        Some(Anchor::new(
+            upstream,
+            (!downstream.is_empty()).then_some(downstream),
+            max_mismatches,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod testing {
+    use super::ConstantRegions;
+
+    fn detect(sequences: &[&'static [u8]]) -> ConstantRegions {
+        ConstantRegions::detect(sequences.iter().copied())
+    }
+
+    #[test]
+    fn test_detect_constant_regions() {
+        let regions = detect(&[b"GGACTGACTT", b"GGATTGCCTT", b"GGAGGGTTTT"]);
+        assert_eq!(regions.prefix(), b"GGA");
+        assert_eq!(regions.suffix(), b"TT");
+        assert_eq!(regions.strip(b"GGACTGACTT"), b"CTGAC");
+
+        // variable lengths share regions at both ends
+        let regions = detect(&[b"AACGTTT", b"AAGTT", b"AATCTT"]);
+        assert_eq!(regions.prefix(), b"AA");
+        assert_eq!(regions.suffix(), b"TT");
+        assert_eq!(regions.strip(b"AAGTT"), b"G");
+    }
+
+    #[test]
+    fn test_detect_keeps_variable_region() {
+        // a sequence entirely within the shared regions keeps a single base
+        let regions = detect(&[b"ACGT", b"ACGTACGT"]);
+        assert_eq!(regions.prefix(), b"ACG");
+        assert!(regions.suffix().is_empty());
+        assert_eq!(regions.strip(b"ACGT"), b"T");
+
+        assert!(detect(&[b"ACGT"]).is_empty());
+        assert!(detect(&[b"ACGT", b"TGCA"]).is_empty());
+    }
+
+    #[test]
+    fn test_anchor() {
+        let regions = detect(&[b"CCCCCCCCCCCCACTGGG", b"CCCCCCCCCCCCTTTGGG"]);
+        assert!(regions.anchor(1).is_some());
+        assert!(detect(&[b"ACGT", b"TGCA"]).anchor(1).is_none());
+    }
+}
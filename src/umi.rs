@@ -0,0 +1,116 @@
+use crate::Permuter;
+use fxread::Record;
+use hashbrown::{HashMap, HashSet};
+
+/// Describes the location of a unique molecular identifier (UMI) within each read
+/// and how observed UMIs are deduplicated.
+///
+/// The UMI position is always given in the coordinates of the read as sequenced
+/// (i.e. before any reverse complementing applied to extract the guide).
+#[derive(Debug, Clone, Copy)]
+pub struct Umi {
+    offset: usize,
+    size: usize,
+    collapse: bool,
+}
+impl Umi {
+    /// Creates a new UMI description at a positional offset of a given size.
+    /// If `collapse` is set then UMIs within a single mismatch of a more abundant
+    /// UMI of the same guide are merged into it.
+    #[must_use]
+    pub fn new(offset: usize, size: usize, collapse: bool) -> Self {
+        Self {
+            offset,
+            size,
+            collapse,
+        }
+    }
+
+    /// Extracts the UMI from a record. Returns [`None`] if the UMI is out of
+    /// bounds of the sequence or contains an ambiguous nucleotide.
+    #[must_use]
+    pub fn extract(&self, record: &Record) -> Option<Vec<u8>> {
+        let umi = record.seq().get(self.offset..self.offset + self.size)?;
+        if umi.contains(&b'N') {
+            None
+        } else {
+            Some(umi.to_vec())
+        }
+    }
+
+    /// Calculates the number of distinct molecules from the observed UMIs
+    /// and their read counts.
+    #[must_use]
+    pub fn deduplicate(&self, umis: &HashMap<Vec<u8>, usize>) -> usize {
+        if self.collapse {
+            Self::collapse(umis)
+        } else {
+            umis.len()
+        }
+    }
+
+    /// Collapses UMIs which are a single mismatch away from a more abundant UMI
+    /// and returns the number of remaining UMIs.
+    ///
+    /// UMIs are visited in order of decreasing abundance and are only kept if
+    /// none of their one-off neighbours have already been kept.
+    fn collapse(umis: &HashMap<Vec<u8>, usize>) -> usize {
+        let mut ordered = umis.iter().collect::<Vec<_>>();
+        ordered.sort_unstable_by(|(x, a), (y, b)| b.cmp(a).then_with(|| x.cmp(y)));
+        ordered
+            .into_iter()
+            .fold(HashSet::new(), |mut kept, (umi, _)| {
+                if !Permuter::one_off(umi)
+                    .iter()
+                    .any(|x| kept.contains(x.as_slice()))
+                {
+                    kept.insert(umi.as_slice());
+                }
+                kept
+            })
+            .len()
+    }
+}
+
+#[cfg(test)]
+mod testing {
+    use super::Umi;
+    use fxread::{FastaReader, FastxRead, Record};
+    use hashbrown::HashMap;
+
+    fn record(sequence: &'static [u8]) -> Record {
+        let mut reader: Box<dyn FastxRead<Item = Record>> = Box::new(FastaReader::new(sequence));
+        reader.next().unwrap()
+    }
+
+    fn umis() -> HashMap<Vec<u8>, usize> {
+        vec![
+            (b"AAAA".to_vec(), 10),
+            (b"AAAT".to_vec(), 1),
+            (b"CCCC".to_vec(), 5),
+            (b"GGGG".to_vec(), 1),
+        ]
+        .into_iter()
+        .collect()
+    }
+
+    #[test]
+    fn test_extract() {
+        let umi = Umi::new(2, 3, false);
+        assert_eq!(umi.extract(&record(b">r.0\nACTGAC\n")).unwrap(), b"TGA");
+        assert!(umi.extract(&record(b">r.0\nACTG\n")).is_none());
+        assert!(umi.extract(&record(b">r.0\nACTNAC\n")).is_none());
+    }
+
+    #[test]
+    fn test_deduplicate() {
+        let umi = Umi::new(0, 4, false);
+        assert_eq!(umi.deduplicate(&umis()), 4);
+    }
+
+    #[test]
+    fn test_deduplicate_collapse() {
+        let umi = Umi::new(0, 4, true);
+        assert_eq!(umi.deduplicate(&umis()), 3);
+    }
+}
@@ -0,0 +1,182 @@
+use anyhow::Result;
+use fxread::Record;
+use hashbrown::HashMap;
+use std::{
+    fs::{create_dir_all, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+/// Describes where the unmapped reads of each sample are written and how
+/// many of their most frequent sequences are reported.
+#[derive(Debug, Clone)]
+pub struct UnmappedOutput {
+    directory: String,
+    windows: bool,
+    top: usize,
+}
+impl UnmappedOutput {
+    /// Creates a new description writing into `directory`. If `windows` is set
+    /// only the extracted sgRNA windows are written rather than the full reads.
+    #[must_use]
+    pub fn new(directory: &str, windows: bool, top: usize) -> Self {
+        Self {
+            directory: directory.to_string(),
+            windows,
+            top,
+        }
+    }
+
+    /// Opens the writer of the unmapped reads for a sample. Reads are written as
+    /// fastq if `fastq` is set and only full reads are written, otherwise as fasta.
+    pub fn sample(&self, name: &str, fastq: bool) -> Result<Unmapped> {
+        create_dir_all(&self.directory)?;
+        let fastq = fastq && !self.windows;
+        let extension = if fastq { "fq" } else { "fa" };
+        let path = Path::new(&self.directory).join(format!("{}.unmapped.{}", name, extension));
+        Ok(Unmapped::new(
+            Box::new(BufWriter::new(File::create(path)?)),
+            self.windows,
+            fastq,
+        ))
+    }
+
+    /// Returns the number of most frequent unmapped sequences reported per sample
+    #[must_use]
+    pub fn top(&self) -> usize {
+        self.top
+    }
+
+    /// Returns the path of the ranked table of unmapped sequences
+    #[must_use]
+    pub fn summary_path(&self) -> PathBuf {
+        Path::new(&self.directory).join("unmapped_summary.tsv")
+    }
+}
+
+/// Writes the reads which could not be assigned and tallies the sequence of
+/// their extracted sgRNA windows.
+///
+/// Reads too short to extract a window are tallied under an empty sequence.
+pub struct Unmapped {
+    writer: Box<dyn Write + Send>,
+    windows: bool,
+    fastq: bool,
+    sequences: HashMap<Vec<u8>, usize>,
+}
+impl Unmapped {
+    /// Creates a new writer of unmapped reads (or only their windows if `windows` is set)
+    #[must_use]
+    pub fn new(writer: Box<dyn Write + Send>, windows: bool, fastq: bool) -> Self {
+        Self {
+            writer,
+            windows,
+            fastq,
+            sequences: HashMap::new(),
+        }
+    }
+
+    /// Writes an unmapped record and tallies its extracted window
+    pub fn insert(&mut self, record: &Record, window: Option<Vec<u8>>) -> Result<()> {
+        let id = record.id();
+        match (&window, self.windows, self.fastq) {
+            (Some(w), true, _) => {
+                self.writer.write_all(b">")?;
+                self.writer.write_all(id)?;
+                self.writer.write_all(b"\n")?;
+                self.writer.write_all(w)?;
+                self.writer.write_all(b"\n")?;
+            }
+            (None, true, _) => {}
+            (_, false, true) => {
+                self.writer.write_all(b"@")?;
+                self.writer.write_all(id)?;
+                self.writer.write_all(b"\n")?;
+                self.writer.write_all(record.seq())?;
+                self.writer.write_all(b"\n+\n")?;
+                self.writer.write_all(record.qual().unwrap_or_default())?;
+                self.writer.write_all(b"\n")?;
+            }
+            (_, false, false) => {
+                self.writer.write_all(b">")?;
+                self.writer.write_all(id)?;
+                self.writer.write_all(b"\n")?;
+                self.writer.write_all(record.seq())?;
+                self.writer.write_all(b"\n")?;
+            }
+        }
+        *self
+            .sequences
+            .entry(window.unwrap_or_default())
+            .or_insert(0) += 1;
+        Ok(())
+    }
+
+    /// Flushes the writer and returns the tally of unmapped window sequences
+    pub fn finish(mut self) -> Result<HashMap<Vec<u8>, usize>> {
+        self.writer.flush()?;
+        Ok(self.sequences)
+    }
+}
+
+#[cfg(test)]
+mod testing {
+    use super::Unmapped;
+    use fxread::{FastaReader, FastqReader, FastxRead, Record};
+    use std::sync::{Arc, Mutex};
+
+    /// A writer sharing its buffer so the written output can be inspected
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+    impl std::io::Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn records(sequence: &'static [u8]) -> Vec<Record> {
+        let reader: Box<dyn FastxRead<Item = Record>> = if sequence.starts_with(b"@") {
+            Box::new(FastqReader::new(sequence))
+        } else {
+            Box::new(FastaReader::new(sequence))
+        };
+        reader.collect()
+    }
+
+    #[test]
+    fn test_unmapped_reads() {
+        let buffer = Shared::default();
+        let mut unmapped = Unmapped::new(Box::new(buffer.clone()), false, true);
+        for record in records(b"@r.0\nACTGAA\n+\nIIIIII\n@r.1\nAC\n+\nII\n") {
+            let window = record.seq().get(0..4).map(<[u8]>::to_vec);
+            unmapped.insert(&record, window).unwrap();
+        }
+        let sequences = unmapped.finish().unwrap();
+        assert_eq!(sequences[b"ACTG".as_slice()], 1);
+        assert_eq!(sequences[b"".as_slice()], 1);
+        assert_eq!(
+            buffer.0.lock().unwrap().as_slice(),
+            b"@r.0\nACTGAA\n+\nIIIIII\n@r.1\nAC\n+\nII\n"
+        );
+    }
+
+    #[test]
+    fn test_unmapped_windows() {
+        let buffer = Shared::default();
+        let mut unmapped = Unmapped::new(Box::new(buffer.clone()), true, false);
+        for record in records(b">r.0\nACTGAA\n>r.1\nACTGTT\n>r.2\nAC\n") {
+            let window = record.seq().get(0..4).map(<[u8]>::to_vec);
+            unmapped.insert(&record, window).unwrap();
+        }
+        let sequences = unmapped.finish().unwrap();
+        assert_eq!(sequences[b"ACTG".as_slice()], 2);
+        assert_eq!(sequences[b"".as_slice()], 1);
+        assert_eq!(
+            buffer.0.lock().unwrap().as_slice(),
+            b">r.0\nACTG\n>r.1\nACTG\n"
+        );
+    }
+}
@@ -19,7 +19,7 @@ pub fn generate_sample_names(input_paths: &[String]) -> Vec<String> {
     // calculate basenames of input files
     let base_names = input_paths
         .iter()
-        .map(|x| x.split('/').last().unwrap())
+        .map(|x| x.split('/').next_back().unwrap())
         .map(|x| x.trim_end_matches(".gz"))
         .map(|x| x.trim_end_matches(".fasta"))
         .map(|x| x.trim_end_matches(".fastq"))